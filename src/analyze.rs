@@ -0,0 +1,549 @@
+//! Steganalysis of PNG files: looks at the usual hiding places (extra chunks, data after
+//! IEND, slack in the zlib stream, pixel LSBs) and combines what it finds into a score.
+
+use std::fmt;
+
+use crate::ihdr::ColorType;
+use crate::image::{filtered_len, inflate_prefix, RawImage, INFLATE_SLACK};
+use crate::png::Png;
+use crate::Result;
+
+/// Ancillary chunks at least this long are checked for random-looking contents
+const ENTROPY_MIN_LENGTH: usize = 64;
+/// Bits per byte above which an uncompressed chunk looks encrypted or compressed
+const ENTROPY_THRESHOLD: f64 = 7.2;
+/// Chunk types whose contents are compressed and therefore expected to look random
+const COMPRESSED_CHUNK_TYPES: [&str; 3] = ["zTXt", "iCCP", "iTXt"];
+/// Chi-square p-values above this suggest the LSB pairs were equalized by embedding
+const CHI_SQUARE_THRESHOLD: f64 = 0.9;
+/// RS embedding rate estimates above this are reported
+const RS_THRESHOLD: f64 = 0.05;
+
+/// Something that suggests hidden data, with the points it adds to the suspicion score
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub points: u32,
+    pub description: String,
+}
+
+/// Results of the LSB statistics of one color channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelStatistics {
+    pub channel: &'static str,
+    /// Probability that the LSB histogram pairs are equal, as LSB embedding makes them
+    pub chi_square: Option<f64>,
+    /// Fraction of pixels whose LSB was replaced, estimated with RS analysis
+    pub rs_rate: Option<f64>,
+}
+
+/// The outcome of analyzing one file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub findings: Vec<Finding>,
+    pub statistics: Vec<ChannelStatistics>,
+}
+
+impl Analysis {
+    /// Suspicion score from 0 (nothing found) to 100
+    pub fn score(&self) -> u32 {
+        self.findings.iter().map(|f| f.points).sum::<u32>().min(100)
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Suspicion score: {}/100", self.score())?;
+        for finding in self.findings.iter() {
+            writeln!(f, "  [+{}] {}", finding.points, finding.description)?;
+        }
+        if self.statistics.is_empty() {
+            writeln!(f, "  LSB statistics: not applicable to this image format")?;
+        }
+        for stats in self.statistics.iter() {
+            writeln!(
+                f,
+                "  {} channel: chi-square p = {}, RS rate = {}",
+                stats.channel,
+                format_statistic(stats.chi_square),
+                format_statistic(stats.rs_rate)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn format_statistic(value: Option<f64>) -> String {
+    value.map_or_else(|| String::from("n/a"), |v| format!("{:.3}", v))
+}
+
+/// Analyzes a PNG for signs of hidden data.
+pub fn analyze(png: &Png) -> Result<Analysis> {
+    let mut findings = Vec::new();
+
+    if !png.trailer().is_empty() {
+        findings.push(Finding {
+            points: 40,
+            description: format!("{} bytes of data after IEND", png.trailer().len()),
+        });
+    }
+
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type();
+        if !chunk_type.is_standard() {
+            let kind = if chunk_type.is_public() {
+                "Unregistered"
+            } else {
+                "Private"
+            };
+            let reserved = if chunk_type.is_reserved_bit_valid() {
+                ""
+            } else {
+                ", reserved bit set"
+            };
+            findings.push(Finding {
+                points: 15,
+                description: format!(
+                    "{} chunk {} ({} bytes{})",
+                    kind,
+                    chunk_type,
+                    chunk.length(),
+                    reserved
+                ),
+            });
+        }
+        let compressed = COMPRESSED_CHUNK_TYPES.contains(&chunk_type.to_string().as_str());
+        if !chunk_type.is_critical() && !compressed && chunk.data().len() >= ENTROPY_MIN_LENGTH {
+            let entropy = entropy(chunk.data());
+            if entropy > ENTROPY_THRESHOLD {
+                findings.push(Finding {
+                    points: 20,
+                    description: format!(
+                        "Ancillary chunk {} looks random ({:.2} bits per byte)",
+                        chunk_type, entropy
+                    ),
+                });
+            }
+        }
+    }
+
+    let ihdr = png.ihdr()?;
+    let compressed = png.image_data();
+    let expected = filtered_len(&ihdr)?;
+    let (filtered, used) = inflate_prefix(&compressed, expected.saturating_add(INFLATE_SLACK))?;
+    match used {
+        Some(used) if used < compressed.len() => findings.push(Finding {
+            points: 30,
+            description: format!(
+                "{} bytes after the end of the zlib stream",
+                compressed.len() - used
+            ),
+        }),
+        Some(_) => {}
+        None => findings.push(Finding {
+            points: 30,
+            description: format!(
+                "Image data decompresses to more than {} bytes beyond the last scanline",
+                INFLATE_SLACK
+            ),
+        }),
+    }
+    if used.is_some() && filtered.len() > expected {
+        findings.push(Finding {
+            points: 30,
+            description: format!(
+                "{} decompressed bytes beyond the last scanline",
+                filtered.len() - expected
+            ),
+        });
+    }
+
+    let statistics = if ihdr.bit_depth == 8 && ihdr.color_type != ColorType::Indexed {
+        let image = RawImage::from_filtered(ihdr, &filtered[..expected.min(filtered.len())])?;
+        lsb_statistics(&image)
+    } else {
+        Vec::new()
+    };
+    if let Some(p) = statistics
+        .iter()
+        .filter_map(|s| s.chi_square)
+        .reduce(f64::max)
+    {
+        if p > CHI_SQUARE_THRESHOLD {
+            findings.push(Finding {
+                points: 25,
+                description: format!("LSB histogram pairs are suspiciously even (p = {:.3})", p),
+            });
+        }
+    }
+    if let Some(rate) = statistics.iter().filter_map(|s| s.rs_rate).reduce(f64::max) {
+        if rate > RS_THRESHOLD {
+            findings.push(Finding {
+                points: ((rate * 60.0).round() as u32).min(40),
+                description: format!(
+                    "RS analysis estimates {:.0}% of LSBs were replaced",
+                    rate * 100.0
+                ),
+            });
+        }
+    }
+
+    Ok(Analysis {
+        findings,
+        statistics,
+    })
+}
+
+/// Shannon entropy of `data` in bits per byte
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / data.len() as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn lsb_statistics(image: &RawImage) -> Vec<ChannelStatistics> {
+    let names: &[&'static str] = match image.ihdr().color_type {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => &["Gray"],
+        _ => &["Red", "Green", "Blue"],
+    };
+    names
+        .iter()
+        .enumerate()
+        .map(|(channel, &name)| {
+            let plane: Vec<Vec<i32>> = (0..image.height())
+                .map(|y| {
+                    (0..image.width())
+                        .map(|x| image.sample(x, y, channel) as i32)
+                        .collect()
+                })
+                .collect();
+            ChannelStatistics {
+                channel: name,
+                chi_square: chi_square(&plane),
+                rs_rate: rs_rate(&plane),
+            }
+        })
+        .collect()
+}
+
+/// Westfeld and Pfitzmann's chi-square attack: replacing LSBs with message bits evens
+/// out the counts of each pair of values 2k and 2k+1. Returns the p-value of the pair
+/// counts being equal.
+fn chi_square(plane: &[Vec<i32>]) -> Option<f64> {
+    let mut histogram = [0u64; 256];
+    for &value in plane.iter().flatten() {
+        histogram[value as usize] += 1;
+    }
+    let mut statistic = 0.0;
+    let mut categories = 0;
+    for pair in histogram.chunks(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        if expected < 5.0 {
+            continue;
+        }
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        categories += 1;
+    }
+    if categories < 2 {
+        return None;
+    }
+    Some(1.0 - regularized_gamma((categories - 1) as f64 / 2.0, statistic / 2.0))
+}
+
+/// Fridrich's RS analysis: estimates the fraction of pixels with replaced LSBs from how
+/// flipping LSBs changes the smoothness of small pixel groups.
+fn rs_rate(plane: &[Vec<i32>]) -> Option<f64> {
+    let flipped: Vec<Vec<i32>> = plane
+        .iter()
+        .map(|row| row.iter().map(|v| v ^ 1).collect())
+        .collect();
+    let (r, s, r_neg, s_neg) = rs_counts(plane)?;
+    let (r_f, s_f, r_neg_f, s_neg_f) = rs_counts(&flipped)?;
+
+    let d0 = r - s;
+    let d1 = r_f - s_f;
+    let d0_neg = r_neg - s_neg;
+    let d1_neg = r_neg_f - s_neg_f;
+    let a = 2.0 * (d1 + d0);
+    let b = d0_neg - d1_neg - d1 - 3.0 * d0;
+    let c = d0 - d0_neg;
+
+    let z = if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return None;
+        }
+        -c / b
+    } else {
+        // Near full embedding the roots turn complex; their real part is the best estimate
+        let root = (b * b - 4.0 * a * c).max(0.0).sqrt();
+        let (z1, z2) = ((-b + root) / (2.0 * a), (-b - root) / (2.0 * a));
+        if z1.abs() <= z2.abs() {
+            z1
+        } else {
+            z2
+        }
+    };
+    let rate = z / (z - 0.5);
+    rate.is_finite().then(|| rate.clamp(0.0, 1.0).abs())
+}
+
+/// Fractions of regular and singular groups of four horizontal pixels under the
+/// mask [0, 1, 1, 0] and its negation.
+fn rs_counts(plane: &[Vec<i32>]) -> Option<(f64, f64, f64, f64)> {
+    const MASK: [i32; 4] = [0, 1, 1, 0];
+    let smoothness = |group: &[i32]| -> i32 { group.windows(2).map(|w| (w[1] - w[0]).abs()).sum() };
+    let flip = |value: i32, direction: i32| -> i32 {
+        match direction {
+            1 => value ^ 1,
+            -1 => ((value + 1) ^ 1) - 1,
+            _ => value,
+        }
+    };
+
+    let mut counts = [0usize; 4];
+    let mut groups = 0;
+    for group in plane.iter().flat_map(|row| row.chunks_exact(4)) {
+        groups += 1;
+        let original = smoothness(group);
+        for (sign, offset) in [(1, 0), (-1, 2)] {
+            let flipped: Vec<i32> = group
+                .iter()
+                .zip(MASK.iter())
+                .map(|(&value, &m)| flip(value, m * sign))
+                .collect();
+            let changed = smoothness(&flipped);
+            if changed > original {
+                counts[offset] += 1;
+            } else if changed < original {
+                counts[offset + 1] += 1;
+            }
+        }
+    }
+    if groups == 0 {
+        return None;
+    }
+    let fraction = |count: usize| count as f64 / groups as f64;
+    Some((
+        fraction(counts[0]),
+        fraction(counts[1]),
+        fraction(counts[2]),
+        fraction(counts[3]),
+    ))
+}
+
+/// Natural log of the gamma function (Lanczos approximation)
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let series: f64 = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .map(|(i, c)| c / (x + 1.0 + i as f64))
+        .sum::<f64>()
+        + 1.000000000190015;
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+/// The regularized lower incomplete gamma function P(a, x), which gives the chi-square
+/// CDF as P(k / 2, x / 2).
+fn regularized_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let prefix = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..500 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * 1e-14 {
+                break;
+            }
+        }
+        (sum * prefix).min(1.0)
+    } else {
+        // Lentz's continued fraction for Q(a, x)
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..500 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-14 {
+                break;
+            }
+        }
+        (1.0 - prefix * h).max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::ihdr::Ihdr;
+    use crate::image::deflate;
+    use std::str::FromStr;
+
+    /// Small xorshift generator so the tests are deterministic
+    fn random_bits(seed: &mut u32) -> i32 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        (*seed & 1) as i32
+    }
+
+    fn smooth_plane() -> Vec<Vec<i32>> {
+        (0..128)
+            .map(|y| {
+                (0..128)
+                    .map(|x| {
+                        let v =
+                            128.0 + 60.0 * (x as f64 / 9.0).sin() + 50.0 * (y as f64 / 13.0).cos();
+                        v as i32 & !1
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn with_random_lsbs(plane: &[Vec<i32>]) -> Vec<Vec<i32>> {
+        let mut seed = 2463534242;
+        plane
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|v| (v & !1) | random_bits(&mut seed))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn rgb_png(extra: Vec<Chunk>) -> Png {
+        let ihdr = Ihdr {
+            width: 16,
+            height: 16,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+        let image = RawImage::blank(ihdr.clone());
+        let mut chunks = vec![ihdr.to_chunk()];
+        chunks.extend(extra);
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        let mut png = Png::from_chunks(chunks);
+        png.set_image_data(image.encode(6));
+        png
+    }
+
+    #[test]
+    fn test_chi_square_detects_full_embedding() {
+        let clean = smooth_plane();
+        assert!(chi_square(&clean).unwrap() < 0.01);
+        assert!(chi_square(&with_random_lsbs(&clean)).unwrap() > CHI_SQUARE_THRESHOLD);
+    }
+
+    #[test]
+    fn test_rs_rate_detects_embedding() {
+        let clean: Vec<Vec<i32>> = (0..128)
+            .map(|y| {
+                (0..128)
+                    .map(|x| {
+                        let noise = 1.5 * ((x * 31 + y * 17) % 7) as f64 / 7.0;
+                        let v =
+                            128.0 + 60.0 * (x as f64 / 9.0).sin() + 50.0 * (y as f64 / 13.0).cos();
+                        (v + noise).round() as i32
+                    })
+                    .collect()
+            })
+            .collect();
+        assert!(rs_rate(&clean).unwrap() < RS_THRESHOLD);
+        assert!(rs_rate(&with_random_lsbs(&clean)).unwrap() > 0.5);
+    }
+
+    #[test]
+    fn test_regularized_gamma() {
+        // chi-square CDF with 2 degrees of freedom is 1 - exp(-x / 2)
+        let p = regularized_gamma(1.0, 1.5);
+        assert!((p - (1.0 - (-1.5f64).exp())).abs() < 1e-9);
+        let p = regularized_gamma(1.0, 10.0);
+        assert!((p - (1.0 - (-10.0f64).exp())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let analysis = analyze(&rgb_png(Vec::new())).unwrap();
+        assert!(analysis.findings.is_empty());
+        assert_eq!(analysis.score(), 0);
+    }
+
+    #[test]
+    fn test_trailing_data_and_private_chunk() {
+        let secret = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hi".to_vec());
+        let mut png = rgb_png(vec![secret]);
+        png.set_trailer(b"appended payload".to_vec());
+        let analysis = analyze(&png).unwrap();
+        assert_eq!(analysis.findings.len(), 2);
+        assert_eq!(analysis.score(), 55);
+    }
+
+    #[test]
+    fn test_zlib_slack_and_random_chunk() {
+        let mut seed = 88172645;
+        let noise: Vec<u8> = (0..1024)
+            .map(|_| (0..8).fold(0, |byte, _| byte << 1 | random_bits(&mut seed) as u8))
+            .collect();
+        let mut png = rgb_png(vec![Chunk::new(
+            ChunkType::from_str("tEXt").unwrap(),
+            noise,
+        )]);
+        let mut data = png.image_data();
+        data.extend_from_slice(b"slack");
+        png.set_image_data(data);
+        let analysis = analyze(&png).unwrap();
+        assert_eq!(analysis.findings.len(), 2);
+        assert!(analysis.findings[0].description.contains("tEXt"));
+        assert!(analysis.findings[1].description.contains("zlib"));
+    }
+
+    #[test]
+    fn test_deflate_bomb_is_not_fully_decompressed() {
+        let mut png = rgb_png(Vec::new());
+        let expected = filtered_len(&png.ihdr().unwrap()).unwrap();
+        png.set_image_data(deflate(&vec![0; expected + INFLATE_SLACK + 4096], 9));
+        let analysis = analyze(&png).unwrap();
+        assert_eq!(analysis.findings.len(), 1);
+        assert!(analysis.findings[0].description.contains("more than"));
+    }
+}
@@ -0,0 +1,1069 @@
+//! Typed versions of the smaller ancillary chunks: pHYs, sBIT, bKGD, tRNS, hIST, sPLT
+//! and the extension chunks oFFs, pCAL and sCAL.
+//! https://www.w3.org/TR/png-3/#11addnlcolinfo
+//! https://ftp-osl.osuosl.org/pub/libpng/documents/pngext-1.5.0.html
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+use crate::text::is_valid_keyword;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum AncillaryError {
+    #[error("Expected a {expected} chunk, found {found}")]
+    WrongChunkType {
+        expected: &'static str,
+        found: String,
+    },
+    #[error("{chunk_type} chunk has an invalid length of {found} bytes")]
+    InvalidLength {
+        chunk_type: &'static str,
+        found: usize,
+    },
+    #[error("{chunk_type} is not allowed in {color_type} images")]
+    NotAllowed {
+        chunk_type: &'static str,
+        color_type: ColorType,
+    },
+    #[error("Invalid {0}")]
+    InvalidValue(String),
+}
+
+/// Returns the data of `chunk` after checking its type
+fn check_type<'a>(chunk: &'a Chunk, expected: &'static str) -> Result<&'a [u8], AncillaryError> {
+    if chunk.chunk_type().to_string() != expected {
+        return Err(AncillaryError::WrongChunkType {
+            expected,
+            found: chunk.chunk_type().to_string(),
+        });
+    }
+    Ok(chunk.data())
+}
+
+fn invalid_length(chunk_type: &'static str, data: &[u8]) -> AncillaryError {
+    AncillaryError::InvalidLength {
+        chunk_type,
+        found: data.len(),
+    }
+}
+
+fn u16_at(data: &[u8], index: usize) -> u16 {
+    u16::from_be_bytes([data[index], data[index + 1]])
+}
+
+fn u32_at(data: &[u8], index: usize) -> u32 {
+    u32::from_be_bytes([
+        data[index],
+        data[index + 1],
+        data[index + 2],
+        data[index + 3],
+    ])
+}
+
+/// The largest sample value at the image's bit depth
+fn sample_max(ihdr: &Ihdr) -> u16 {
+    match ihdr.bit_depth {
+        16 => u16::MAX,
+        depth => (1 << depth) - 1,
+    }
+}
+
+/// Decodes the Latin-1 keywords and strings used by sPLT, pCAL and sCAL
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// A gray or RGB sample value, as stored by bKGD and tRNS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sample {
+    Gray(u16),
+    Rgb(u16, u16, u16),
+}
+
+impl Sample {
+    fn parse(data: &[u8], chunk_type: &'static str, ihdr: &Ihdr) -> Result<Self, AncillaryError> {
+        let sample = match (ihdr.color_type, data.len()) {
+            (ColorType::Grayscale | ColorType::GrayscaleAlpha, 2) => Sample::Gray(u16_at(data, 0)),
+            (ColorType::Rgb | ColorType::Rgba, 6) => {
+                Sample::Rgb(u16_at(data, 0), u16_at(data, 2), u16_at(data, 4))
+            }
+            _ => return Err(invalid_length(chunk_type, data)),
+        };
+        sample.check(chunk_type, ihdr)?;
+        Ok(sample)
+    }
+
+    /// Checks the sample fits the image's bit depth and color type
+    fn check(self, chunk_type: &str, ihdr: &Ihdr) -> Result<(), AncillaryError> {
+        let (values, gray) = match self {
+            Sample::Gray(v) => (vec![v], true),
+            Sample::Rgb(r, g, b) => (vec![r, g, b], false),
+        };
+        let gray_image = matches!(
+            ihdr.color_type,
+            ColorType::Grayscale | ColorType::GrayscaleAlpha
+        );
+        if gray != gray_image || ihdr.color_type == ColorType::Indexed {
+            return Err(AncillaryError::InvalidValue(format!(
+                "{} {} for a {} image",
+                chunk_type, self, ihdr.color_type
+            )));
+        }
+        if values.iter().any(|&v| v > sample_max(ihdr)) {
+            return Err(AncillaryError::InvalidValue(format!(
+                "{} {} for a {}-bit image",
+                chunk_type, self, ihdr.bit_depth
+            )));
+        }
+        Ok(())
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        match self {
+            Sample::Gray(v) => v.to_be_bytes().to_vec(),
+            Sample::Rgb(r, g, b) => [r, g, b].iter().flat_map(|v| v.to_be_bytes()).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sample::Gray(v) => write!(f, "gray {}", v),
+            Sample::Rgb(r, g, b) => write!(f, "RGB ({}, {}, {})", r, g, b),
+        }
+    }
+}
+
+/// The unit of pHYs measurements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhysicalUnit {
+    /// Only the pixel aspect ratio is known
+    Unknown,
+    Meter,
+}
+
+/// The pHYs chunk: pixels per unit along each axis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysicalDimensions {
+    pub x: u32,
+    pub y: u32,
+    pub unit: PhysicalUnit,
+}
+
+impl PhysicalDimensions {
+    pub const CHUNK_TYPE: &'static str = "pHYs";
+    const INCHES_PER_METER: f64 = 39.3701;
+
+    /// Square pixels at `dpi` dots per inch
+    pub fn from_dpi(dpi: f64) -> Result<Self, AncillaryError> {
+        let per_meter = (dpi * Self::INCHES_PER_METER).round();
+        if !(1.0..=u32::MAX as f64).contains(&per_meter) {
+            return Err(AncillaryError::InvalidValue(format!(
+                "resolution {} dpi",
+                dpi
+            )));
+        }
+        Ok(PhysicalDimensions {
+            x: per_meter as u32,
+            y: per_meter as u32,
+            unit: PhysicalUnit::Meter,
+        })
+    }
+
+    /// The horizontal and vertical resolution in dots per inch, if the unit is known
+    pub fn dpi(self) -> Option<(f64, f64)> {
+        match self.unit {
+            PhysicalUnit::Meter => Some((
+                self.x as f64 / Self::INCHES_PER_METER,
+                self.y as f64 / Self::INCHES_PER_METER,
+            )),
+            PhysicalUnit::Unknown => None,
+        }
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.x.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.y.to_be_bytes());
+        data.push(match self.unit {
+            PhysicalUnit::Unknown => 0,
+            PhysicalUnit::Meter => 1,
+        });
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for PhysicalDimensions {
+    type Error = AncillaryError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, AncillaryError> {
+        let data = check_type(chunk, Self::CHUNK_TYPE)?;
+        if data.len() != 9 {
+            return Err(invalid_length(Self::CHUNK_TYPE, data));
+        }
+        let unit = match data[8] {
+            0 => PhysicalUnit::Unknown,
+            1 => PhysicalUnit::Meter,
+            unit => return Err(AncillaryError::InvalidValue(format!("pHYs unit {}", unit))),
+        };
+        let (x, y) = (u32_at(data, 0), u32_at(data, 4));
+        if x == 0 || y == 0 {
+            return Err(AncillaryError::InvalidValue(format!(
+                "pHYs resolution {}x{}",
+                x, y
+            )));
+        }
+        Ok(PhysicalDimensions { x, y, unit })
+    }
+}
+
+impl fmt::Display for PhysicalDimensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.dpi() {
+            Some((x, _)) if self.x == self.y => {
+                write!(f, "{} pixels per meter ({:.0} dpi)", self.x, x)
+            }
+            Some((x, y)) => write!(
+                f,
+                "{}x{} pixels per meter ({:.0}x{:.0} dpi)",
+                self.x, self.y, x, y
+            ),
+            None => write!(f, "pixel aspect ratio {}:{}", self.x, self.y),
+        }
+    }
+}
+
+/// The sBIT chunk: how many bits of each sample were significant in the original image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignificantBits {
+    /// One entry per channel, with palette images counting as RGB
+    pub bits: Vec<u8>,
+}
+
+impl SignificantBits {
+    pub const CHUNK_TYPE: &'static str = "sBIT";
+
+    /// Checks there is one value per channel, each no larger than the sample depth
+    pub fn new(bits: Vec<u8>, ihdr: &Ihdr) -> Result<Self, AncillaryError> {
+        let (channels, depth) = match ihdr.color_type {
+            ColorType::Indexed => (3, 8),
+            color_type => (color_type.channels(), ihdr.bit_depth),
+        };
+        if bits.len() != channels {
+            return Err(invalid_length(Self::CHUNK_TYPE, &bits));
+        }
+        if bits.iter().any(|&b| b == 0 || b > depth) {
+            return Err(AncillaryError::InvalidValue(format!(
+                "sBIT {:?} for {}-bit samples",
+                bits, depth
+            )));
+        }
+        Ok(SignificantBits { bits })
+    }
+
+    pub fn parse(chunk: &Chunk, ihdr: &Ihdr) -> Result<Self, AncillaryError> {
+        let data = check_type(chunk, Self::CHUNK_TYPE)?;
+        SignificantBits::new(data.to_vec(), ihdr)
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(Self::CHUNK_TYPE).unwrap(),
+            self.bits.clone(),
+        )
+    }
+}
+
+impl fmt::Display for SignificantBits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bits: Vec<String> = self.bits.iter().map(|b| b.to_string()).collect();
+        write!(f, "{} significant bits", bits.join(","))
+    }
+}
+
+/// The bKGD chunk: the color to show behind the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Sample(Sample),
+    PaletteIndex(u8),
+}
+
+impl Background {
+    pub const CHUNK_TYPE: &'static str = "bKGD";
+
+    pub fn parse(chunk: &Chunk, ihdr: &Ihdr) -> Result<Self, AncillaryError> {
+        let data = check_type(chunk, Self::CHUNK_TYPE)?;
+        match (ihdr.color_type, data) {
+            (ColorType::Indexed, &[index]) => Ok(Background::PaletteIndex(index)),
+            (ColorType::Indexed, _) => Err(invalid_length(Self::CHUNK_TYPE, data)),
+            _ => Sample::parse(data, Self::CHUNK_TYPE, ihdr).map(Background::Sample),
+        }
+    }
+
+    /// Checks the background suits the image, including the palette size for indexed images
+    pub fn check(self, ihdr: &Ihdr, palette_entries: usize) -> Result<(), AncillaryError> {
+        match self {
+            Background::Sample(sample) => sample.check(Self::CHUNK_TYPE, ihdr),
+            Background::PaletteIndex(_) if ihdr.color_type != ColorType::Indexed => {
+                Err(AncillaryError::InvalidValue(format!(
+                    "bKGD {} for a {} image",
+                    self, ihdr.color_type
+                )))
+            }
+            Background::PaletteIndex(index) if index as usize >= palette_entries => {
+                Err(AncillaryError::InvalidValue(format!(
+                    "bKGD palette index {} with {} palette entries",
+                    index, palette_entries
+                )))
+            }
+            Background::PaletteIndex(_) => Ok(()),
+        }
+    }
+
+    /// The background color as 16-bit RGB, looking palette indices up in `palette`
+    pub fn to_rgb16(self, ihdr: &Ihdr, palette: &[[u8; 4]]) -> Option<[u16; 3]> {
+        let scale = |v: u16| (v as u32 * 65535 / sample_max(ihdr) as u32) as u16;
+        match self {
+            Background::Sample(Sample::Gray(v)) => Some([scale(v); 3]),
+            Background::Sample(Sample::Rgb(r, g, b)) => Some([scale(r), scale(g), scale(b)]),
+            Background::PaletteIndex(index) => palette
+                .get(index as usize)
+                .map(|entry| [0, 1, 2].map(|c| entry[c] as u16 * 257)),
+        }
+    }
+
+    /// The background for a 16-bit RGB `color` in an image described by `ihdr`, if it can
+    /// be stored exactly. Indexed images need the color to be in `palette`.
+    pub fn from_rgb16(color: [u16; 3], ihdr: &Ihdr, palette: &[[u8; 4]]) -> Option<Self> {
+        if ihdr.color_type == ColorType::Indexed {
+            let index = palette
+                .iter()
+                .position(|entry| [0, 1, 2].map(|c| entry[c] as u16 * 257) == color)?;
+            return u8::try_from(index).ok().map(Background::PaletteIndex);
+        }
+        let step = 65535 / sample_max(ihdr);
+        if color.iter().any(|v| v % step != 0) {
+            return None;
+        }
+        let [r, g, b] = color.map(|v| v / step);
+        match ihdr.color_type {
+            ColorType::Grayscale | ColorType::GrayscaleAlpha if r == g && g == b => {
+                Some(Background::Sample(Sample::Gray(r)))
+            }
+            ColorType::Rgb | ColorType::Rgba => Some(Background::Sample(Sample::Rgb(r, g, b))),
+            _ => None,
+        }
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let data = match self {
+            Background::Sample(sample) => sample.to_bytes(),
+            Background::PaletteIndex(index) => vec![index],
+        };
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl fmt::Display for Background {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Background::Sample(sample) => write!(f, "{}", sample),
+            Background::PaletteIndex(index) => write!(f, "palette index {}", index),
+        }
+    }
+}
+
+/// The tRNS chunk: a transparent color key, or alpha values for palette entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transparency {
+    Key(Sample),
+    /// Alpha for the first palette entries; the rest are opaque
+    PaletteAlpha(Vec<u8>),
+}
+
+impl Transparency {
+    pub const CHUNK_TYPE: &'static str = "tRNS";
+
+    pub fn parse(chunk: &Chunk, ihdr: &Ihdr) -> Result<Self, AncillaryError> {
+        let data = check_type(chunk, Self::CHUNK_TYPE)?;
+        match ihdr.color_type {
+            color_type if color_type.has_alpha() => Err(AncillaryError::NotAllowed {
+                chunk_type: Self::CHUNK_TYPE,
+                color_type,
+            }),
+            ColorType::Indexed if data.is_empty() || data.len() > 256 => {
+                Err(invalid_length(Self::CHUNK_TYPE, data))
+            }
+            ColorType::Indexed => Ok(Transparency::PaletteAlpha(data.to_vec())),
+            _ => Sample::parse(data, Self::CHUNK_TYPE, ihdr).map(Transparency::Key),
+        }
+    }
+
+    /// Checks the transparency suits the image, including the palette size for indexed images
+    pub fn check(&self, ihdr: &Ihdr, palette_entries: usize) -> Result<(), AncillaryError> {
+        if ihdr.color_type.has_alpha() {
+            return Err(AncillaryError::NotAllowed {
+                chunk_type: Self::CHUNK_TYPE,
+                color_type: ihdr.color_type,
+            });
+        }
+        match self {
+            Transparency::Key(sample) => sample.check(Self::CHUNK_TYPE, ihdr),
+            Transparency::PaletteAlpha(_) if ihdr.color_type != ColorType::Indexed => {
+                Err(AncillaryError::InvalidValue(format!(
+                    "tRNS palette alpha for a {} image",
+                    ihdr.color_type
+                )))
+            }
+            Transparency::PaletteAlpha(alpha) if alpha.len() > palette_entries => {
+                Err(AncillaryError::InvalidValue(format!(
+                    "tRNS with {} alpha values for {} palette entries",
+                    alpha.len(),
+                    palette_entries
+                )))
+            }
+            Transparency::PaletteAlpha(_) => Ok(()),
+        }
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let data = match self {
+            Transparency::Key(sample) => sample.to_bytes(),
+            Transparency::PaletteAlpha(alpha) => alpha.clone(),
+        };
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl fmt::Display for Transparency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transparency::Key(sample) => write!(f, "transparent {}", sample),
+            Transparency::PaletteAlpha(alpha) => {
+                let translucent = alpha.iter().filter(|&&a| a != 255).count();
+                write!(
+                    f,
+                    "alpha for {} palette entries, {} not opaque",
+                    alpha.len(),
+                    translucent
+                )
+            }
+        }
+    }
+}
+
+/// The hIST chunk: how often each palette entry is used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    pub frequencies: Vec<u16>,
+}
+
+impl Histogram {
+    pub const CHUNK_TYPE: &'static str = "hIST";
+
+    /// hIST needs a palette, and must have exactly one entry per palette color
+    pub fn check(&self, ihdr: &Ihdr, palette_entries: usize) -> Result<(), AncillaryError> {
+        if matches!(
+            ihdr.color_type,
+            ColorType::Grayscale | ColorType::GrayscaleAlpha
+        ) {
+            return Err(AncillaryError::NotAllowed {
+                chunk_type: Self::CHUNK_TYPE,
+                color_type: ihdr.color_type,
+            });
+        }
+        if self.frequencies.len() != palette_entries {
+            return Err(AncillaryError::InvalidValue(format!(
+                "hIST with {} entries for {} palette entries",
+                self.frequencies.len(),
+                palette_entries
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let data = self
+            .frequencies
+            .iter()
+            .flat_map(|f| f.to_be_bytes())
+            .collect();
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for Histogram {
+    type Error = AncillaryError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, AncillaryError> {
+        let data = check_type(chunk, Self::CHUNK_TYPE)?;
+        if data.is_empty() || data.len() % 2 != 0 || data.len() > 512 {
+            return Err(invalid_length(Self::CHUNK_TYPE, data));
+        }
+        Ok(Histogram {
+            frequencies: data.chunks(2).map(|f| u16_at(f, 0)).collect(),
+        })
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} entries", self.frequencies.len())?;
+        let most_used = (0..self.frequencies.len()).max_by_key(|&i| self.frequencies[i]);
+        if let Some(index) = most_used {
+            write!(f, ", most used index {}", index)?;
+        }
+        Ok(())
+    }
+}
+
+/// One color of a suggested palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuggestedEntry {
+    pub rgba: [u16; 4],
+    pub frequency: u16,
+}
+
+/// The sPLT chunk: a named palette suggested for displays with few colors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    pub name: String,
+    /// 8 or 16
+    pub sample_depth: u8,
+    pub entries: Vec<SuggestedEntry>,
+}
+
+impl SuggestedPalette {
+    pub const CHUNK_TYPE: &'static str = "sPLT";
+
+    // No command writes sPLT yet; the tests round-trip through it
+    #[allow(dead_code)]
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data: Vec<u8> = self.name.chars().map(|c| c as u8).collect();
+        data.push(0);
+        data.push(self.sample_depth);
+        for entry in self.entries.iter() {
+            for sample in entry.rgba {
+                match self.sample_depth {
+                    8 => data.push(sample as u8),
+                    _ => data.extend_from_slice(&sample.to_be_bytes()),
+                }
+            }
+            data.extend_from_slice(&entry.frequency.to_be_bytes());
+        }
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for SuggestedPalette {
+    type Error = AncillaryError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, AncillaryError> {
+        let data = check_type(chunk, Self::CHUNK_TYPE)?;
+        let separator = data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid_length(Self::CHUNK_TYPE, data))?;
+        let name = latin1(&data[..separator]);
+        if !is_valid_keyword(&name) {
+            return Err(AncillaryError::InvalidValue(format!(
+                "sPLT name {:?}",
+                name
+            )));
+        }
+        let sample_depth = *data
+            .get(separator + 1)
+            .ok_or_else(|| invalid_length(Self::CHUNK_TYPE, data))?;
+        let entry_size = match sample_depth {
+            8 => 6,
+            16 => 10,
+            depth => {
+                return Err(AncillaryError::InvalidValue(format!(
+                    "sPLT sample depth {}",
+                    depth
+                )))
+            }
+        };
+        let entries = &data[separator + 2..];
+        if entries.len() % entry_size != 0 {
+            return Err(invalid_length(Self::CHUNK_TYPE, data));
+        }
+        let entries = entries
+            .chunks(entry_size)
+            .map(|e| {
+                let sample = |i: usize| match sample_depth {
+                    8 => e[i] as u16,
+                    _ => u16_at(e, i * 2),
+                };
+                SuggestedEntry {
+                    rgba: [sample(0), sample(1), sample(2), sample(3)],
+                    frequency: u16_at(e, entry_size - 2),
+                }
+            })
+            .collect();
+        Ok(SuggestedPalette {
+            name,
+            sample_depth,
+            entries,
+        })
+    }
+}
+
+impl fmt::Display for SuggestedPalette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, {} {}-bit entries",
+            self.name,
+            self.entries.len(),
+            self.sample_depth
+        )
+    }
+}
+
+/// The unit of oFFs positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetUnit {
+    Pixel,
+    Micrometer,
+}
+
+/// The oFFs chunk: where the image sits on a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Offset {
+    pub x: i32,
+    pub y: i32,
+    pub unit: OffsetUnit,
+}
+
+impl Offset {
+    pub const CHUNK_TYPE: &'static str = "oFFs";
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.x.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.y.to_be_bytes());
+        data.push(match self.unit {
+            OffsetUnit::Pixel => 0,
+            OffsetUnit::Micrometer => 1,
+        });
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for Offset {
+    type Error = AncillaryError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, AncillaryError> {
+        let data = check_type(chunk, Self::CHUNK_TYPE)?;
+        if data.len() != 9 {
+            return Err(invalid_length(Self::CHUNK_TYPE, data));
+        }
+        let unit = match data[8] {
+            0 => OffsetUnit::Pixel,
+            1 => OffsetUnit::Micrometer,
+            unit => return Err(AncillaryError::InvalidValue(format!("oFFs unit {}", unit))),
+        };
+        Ok(Offset {
+            x: u32_at(data, 0) as i32,
+            y: u32_at(data, 4) as i32,
+            unit,
+        })
+    }
+}
+
+impl fmt::Display for Offset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.unit {
+            OffsetUnit::Pixel => "pixels",
+            OffsetUnit::Micrometer => "micrometers",
+        };
+        write!(f, "offset ({}, {}) {}", self.x, self.y, unit)
+    }
+}
+
+/// The pCAL chunk: maps sample values to physical values through an equation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PixelCalibration {
+    pub name: String,
+    /// Sample values map from the range `x0..=x1` into the equation
+    pub x0: i32,
+    pub x1: i32,
+    /// 0 linear, 1 base-e exponential, 2 arbitrary-base exponential, 3 hyperbolic sine
+    pub equation: u8,
+    pub unit: String,
+    /// Floating-point parameters, kept as the text stored in the chunk
+    pub parameters: Vec<String>,
+}
+
+impl PixelCalibration {
+    pub const CHUNK_TYPE: &'static str = "pCAL";
+    const EQUATIONS: [(&'static str, usize); 4] = [
+        ("linear", 2),
+        ("exponential", 3),
+        ("arbitrary-base exponential", 3),
+        ("hyperbolic", 4),
+    ];
+
+    // The inverse of try_from, kept for callers that edit pCAL
+    #[allow(dead_code)]
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data: Vec<u8> = self.name.chars().map(|c| c as u8).collect();
+        data.push(0);
+        data.extend_from_slice(&self.x0.to_be_bytes());
+        data.extend_from_slice(&self.x1.to_be_bytes());
+        data.push(self.equation);
+        data.push(self.parameters.len() as u8);
+        data.extend(self.unit.chars().map(|c| c as u8));
+        for parameter in self.parameters.iter() {
+            data.push(0);
+            data.extend_from_slice(parameter.as_bytes());
+        }
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for PixelCalibration {
+    type Error = AncillaryError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, AncillaryError> {
+        let data = check_type(chunk, Self::CHUNK_TYPE)?;
+        let separator = data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid_length(Self::CHUNK_TYPE, data))?;
+        let name = latin1(&data[..separator]);
+        if !is_valid_keyword(&name) {
+            return Err(AncillaryError::InvalidValue(format!(
+                "pCAL name {:?}",
+                name
+            )));
+        }
+        let rest = &data[separator + 1..];
+        if rest.len() < 10 {
+            return Err(invalid_length(Self::CHUNK_TYPE, data));
+        }
+        let (x0, x1) = (u32_at(rest, 0) as i32, u32_at(rest, 4) as i32);
+        let (equation, count) = (rest[8], rest[9] as usize);
+        let expected = Self::EQUATIONS.get(equation as usize).map(|e| e.1);
+        if expected != Some(count) {
+            return Err(AncillaryError::InvalidValue(format!(
+                "pCAL equation {} with {} parameters",
+                equation, count
+            )));
+        }
+        if x0 == x1 {
+            return Err(AncillaryError::InvalidValue(format!(
+                "pCAL range {}..{}",
+                x0, x1
+            )));
+        }
+        let mut fields = rest[10..].split(|&b| b == 0);
+        let unit = latin1(fields.next().unwrap_or_default());
+        let parameters: Vec<String> = fields.map(latin1).collect();
+        if parameters.len() != count || parameters.iter().any(|p| p.parse::<f64>().is_err()) {
+            return Err(AncillaryError::InvalidValue(format!(
+                "pCAL parameters {:?}",
+                parameters
+            )));
+        }
+        Ok(PixelCalibration {
+            name,
+            x0,
+            x1,
+            equation,
+            unit,
+            parameters,
+        })
+    }
+}
+
+impl fmt::Display for PixelCalibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let equation = Self::EQUATIONS
+            .get(self.equation as usize)
+            .map_or("unknown", |e| e.0);
+        write!(
+            f,
+            "{}, samples {}..{}, {} ({})",
+            self.name,
+            self.x0,
+            self.x1,
+            equation,
+            self.parameters.join(", ")
+        )?;
+        if !self.unit.is_empty() {
+            write!(f, " in {}", self.unit)?;
+        }
+        Ok(())
+    }
+}
+
+/// The unit of sCAL sizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleUnit {
+    Meter,
+    Radian,
+}
+
+/// The sCAL chunk: the physical size each pixel covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scale {
+    pub unit: ScaleUnit,
+    /// Positive floating-point values, kept as the text stored in the chunk
+    pub width: String,
+    pub height: String,
+}
+
+impl Scale {
+    pub const CHUNK_TYPE: &'static str = "sCAL";
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = vec![match self.unit {
+            ScaleUnit::Meter => 1,
+            ScaleUnit::Radian => 2,
+        }];
+        data.extend_from_slice(self.width.as_bytes());
+        data.push(0);
+        data.extend_from_slice(self.height.as_bytes());
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for Scale {
+    type Error = AncillaryError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, AncillaryError> {
+        let data = check_type(chunk, Self::CHUNK_TYPE)?;
+        let (unit, rest) = match data {
+            [1, rest @ ..] => (ScaleUnit::Meter, rest),
+            [2, rest @ ..] => (ScaleUnit::Radian, rest),
+            [unit, ..] => return Err(AncillaryError::InvalidValue(format!("sCAL unit {}", unit))),
+            [] => return Err(invalid_length(Self::CHUNK_TYPE, data)),
+        };
+        let mut fields = rest.split(|&b| b == 0).map(latin1);
+        let (width, height) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(width), Some(height), None) => (width, height),
+            _ => return Err(invalid_length(Self::CHUNK_TYPE, data)),
+        };
+        let positive = |v: &str| v.parse::<f64>().is_ok_and(|v| v > 0.0 && v.is_finite());
+        if !positive(&width) || !positive(&height) {
+            return Err(AncillaryError::InvalidValue(format!(
+                "sCAL size {} x {}",
+                width, height
+            )));
+        }
+        Ok(Scale {
+            unit,
+            width,
+            height,
+        })
+    }
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.unit {
+            ScaleUnit::Meter => "meters",
+            ScaleUnit::Radian => "radians",
+        };
+        write!(f, "pixel size {} x {} {}", self.width, self.height, unit)
+    }
+}
+
+/// Describes the chunks in this module, parsing the ones that depend on the color type
+/// against `ihdr`. Returns `None` for other chunk types.
+pub fn describe(chunk: &Chunk, ihdr: Option<&Ihdr>) -> Option<String> {
+    fn show<T: fmt::Display>(result: Result<T, AncillaryError>) -> String {
+        match result {
+            Ok(value) => value.to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+    let description = match (chunk.chunk_type().to_string().as_str(), ihdr) {
+        (PhysicalDimensions::CHUNK_TYPE, _) => show(PhysicalDimensions::try_from(chunk)),
+        (Histogram::CHUNK_TYPE, _) => show(Histogram::try_from(chunk)),
+        (SuggestedPalette::CHUNK_TYPE, _) => show(SuggestedPalette::try_from(chunk)),
+        (Offset::CHUNK_TYPE, _) => show(Offset::try_from(chunk)),
+        (PixelCalibration::CHUNK_TYPE, _) => show(PixelCalibration::try_from(chunk)),
+        (Scale::CHUNK_TYPE, _) => show(Scale::try_from(chunk)),
+        (SignificantBits::CHUNK_TYPE, Some(ihdr)) => show(SignificantBits::parse(chunk, ihdr)),
+        (Background::CHUNK_TYPE, Some(ihdr)) => show(Background::parse(chunk, ihdr)),
+        (Transparency::CHUNK_TYPE, Some(ihdr)) => show(Transparency::parse(chunk, ihdr)),
+        _ => return None,
+    };
+    Some(description)
+}
+
+/// Problems with the chunks in this module that only show up against the rest of the file,
+/// such as palette indices out of range or chunks the color type doesn't allow.
+pub fn warnings(png: &Png) -> Vec<String> {
+    let ihdr = match png.ihdr() {
+        Ok(ihdr) => ihdr,
+        Err(_) => return Vec::new(),
+    };
+    let palette_entries = png.chunk_by_type("PLTE").map_or(0, |c| c.data().len() / 3);
+    let mut warnings = Vec::new();
+    for chunk in png.chunks() {
+        let error = match chunk.chunk_type().to_string().as_str() {
+            SignificantBits::CHUNK_TYPE => SignificantBits::parse(chunk, &ihdr).err(),
+            Background::CHUNK_TYPE => Background::parse(chunk, &ihdr)
+                .and_then(|b| b.check(&ihdr, palette_entries))
+                .err(),
+            Transparency::CHUNK_TYPE => Transparency::parse(chunk, &ihdr)
+                .and_then(|t| t.check(&ihdr, palette_entries))
+                .err(),
+            Histogram::CHUNK_TYPE => Histogram::try_from(chunk)
+                .and_then(|h| h.check(&ihdr, palette_entries))
+                .err(),
+            PhysicalDimensions::CHUNK_TYPE => PhysicalDimensions::try_from(chunk).err(),
+            SuggestedPalette::CHUNK_TYPE => SuggestedPalette::try_from(chunk).err(),
+            Offset::CHUNK_TYPE => Offset::try_from(chunk).err(),
+            PixelCalibration::CHUNK_TYPE => PixelCalibration::try_from(chunk).err(),
+            Scale::CHUNK_TYPE => Scale::try_from(chunk).err(),
+            _ => None,
+        };
+        if let Some(error) = error {
+            warnings.push(error.to_string());
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr(color_type: ColorType, bit_depth: u8) -> Ihdr {
+        Ihdr {
+            width: 4,
+            height: 4,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        }
+    }
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_physical_dimensions() {
+        let phys = PhysicalDimensions::from_dpi(300.0).unwrap();
+        assert_eq!(phys.x, 11811);
+        assert_eq!(
+            PhysicalDimensions::try_from(&phys.to_chunk()).unwrap(),
+            phys
+        );
+        assert_eq!(phys.to_string(), "11811 pixels per meter (300 dpi)");
+        let aspect = chunk("pHYs", &[0, 0, 0, 2, 0, 0, 0, 1, 0]);
+        assert_eq!(
+            PhysicalDimensions::try_from(&aspect).unwrap().to_string(),
+            "pixel aspect ratio 2:1"
+        );
+        assert!(PhysicalDimensions::try_from(&chunk("pHYs", &[0; 9])).is_err());
+    }
+
+    #[test]
+    fn test_color_type_dependent_chunks() {
+        let rgb = ihdr(ColorType::Rgb, 8);
+        let gray = ihdr(ColorType::Grayscale, 4);
+        let rgba = ihdr(ColorType::Rgba, 8);
+
+        assert_eq!(
+            SignificantBits::parse(&chunk("sBIT", &[5, 6, 5]), &rgb)
+                .unwrap()
+                .to_string(),
+            "5,6,5 significant bits"
+        );
+        assert!(SignificantBits::parse(&chunk("sBIT", &[5]), &rgb).is_err());
+        assert!(SignificantBits::parse(&chunk("sBIT", &[5]), &gray).is_err());
+
+        let background = Background::Sample(Sample::Rgb(255, 0, 128));
+        assert_eq!(
+            Background::parse(&background.to_chunk(), &rgb).unwrap(),
+            background
+        );
+        assert!(Background::parse(&chunk("bKGD", &[0, 16]), &gray).is_err());
+        assert!(Background::parse(&chunk("bKGD", &[0, 15]), &gray).is_ok());
+
+        assert_eq!(
+            Transparency::parse(&chunk("tRNS", &[0, 0]), &rgba),
+            Err(AncillaryError::NotAllowed {
+                chunk_type: "tRNS",
+                color_type: ColorType::Rgba
+            })
+        );
+        assert!(Transparency::parse(&chunk("tRNS", &[0, 1, 0, 2, 0, 3]), &rgb).is_ok());
+    }
+
+    #[test]
+    fn test_palette_checks() {
+        let indexed = ihdr(ColorType::Indexed, 8);
+        let background = Background::parse(&chunk("bKGD", &[4]), &indexed).unwrap();
+        assert!(background.check(&indexed, 5).is_ok());
+        assert!(background.check(&indexed, 4).is_err());
+
+        let alpha = Transparency::parse(&chunk("tRNS", &[0, 128, 255]), &indexed).unwrap();
+        assert!(alpha.check(&indexed, 2).is_err());
+
+        let histogram = Histogram::try_from(&chunk("hIST", &[0, 1, 0, 9])).unwrap();
+        assert_eq!(histogram.to_string(), "2 entries, most used index 1");
+        assert!(histogram.check(&indexed, 2).is_ok());
+        assert!(histogram.check(&ihdr(ColorType::Grayscale, 8), 2).is_err());
+    }
+
+    #[test]
+    fn test_extension_chunks() {
+        let palette = SuggestedPalette {
+            name: "web safe".to_string(),
+            sample_depth: 16,
+            entries: vec![SuggestedEntry {
+                rgba: [0xffff, 0, 0x3333, 0xffff],
+                frequency: 7,
+            }],
+        };
+        assert_eq!(
+            SuggestedPalette::try_from(&palette.to_chunk()).unwrap(),
+            palette
+        );
+
+        let offset = Offset {
+            x: -10,
+            y: 20,
+            unit: OffsetUnit::Micrometer,
+        };
+        assert_eq!(Offset::try_from(&offset.to_chunk()).unwrap(), offset);
+        assert_eq!(offset.to_string(), "offset (-10, 20) micrometers");
+
+        let calibration = PixelCalibration {
+            name: "Elevation".to_string(),
+            x0: 0,
+            x1: 255,
+            equation: 0,
+            unit: "m".to_string(),
+            parameters: vec!["-100".to_string(), "2.5".to_string()],
+        };
+        assert_eq!(
+            PixelCalibration::try_from(&calibration.to_chunk()).unwrap(),
+            calibration
+        );
+        let mut wrong = calibration.clone();
+        wrong.equation = 3;
+        assert!(PixelCalibration::try_from(&wrong.to_chunk()).is_err());
+
+        let scale = Scale {
+            unit: ScaleUnit::Meter,
+            width: "0.001".to_string(),
+            height: "0.001".to_string(),
+        };
+        assert_eq!(Scale::try_from(&scale.to_chunk()).unwrap(), scale);
+        assert!(Scale::try_from(&chunk("sCAL", b"\x01-1\x001")).is_err());
+    }
+}
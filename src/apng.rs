@@ -0,0 +1,987 @@
+//! Animated PNG: the acTL, fcTL and fdAT chunks, and compositing the frames they describe.
+//! https://www.w3.org/TR/png-3/#apng-frame-based-animation
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::Ihdr;
+use crate::image::{inflate_image_data, RawImage, Rgba16Image};
+use crate::png::Png;
+use crate::Result;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ApngError {
+    #[error("Expected a {expected} chunk, found {found}")]
+    WrongChunkType {
+        expected: &'static str,
+        found: String,
+    },
+    #[error("{chunk_type} chunk has an invalid length of {found} bytes")]
+    InvalidLength {
+        chunk_type: &'static str,
+        found: usize,
+    },
+    #[error("Invalid {0}")]
+    InvalidValue(String),
+    #[error("Not an animated PNG: there is no acTL chunk")]
+    NotAnimated,
+    #[error("Expected sequence number {expected}, found {found}")]
+    Sequence { expected: u32, found: u32 },
+    #[error("acTL declares {declared} frames but {found} fcTL chunks were found")]
+    FrameCount { declared: u32, found: usize },
+    #[error("Frame {0} has no image data")]
+    MissingFrameData(usize),
+    #[error("Frame {index} does not match the first frame: {reason}")]
+    MismatchedFrame { index: usize, reason: String },
+}
+
+/// Returns the data of `chunk` after checking its type and length
+fn check_chunk<'a>(
+    chunk: &'a Chunk,
+    expected: &'static str,
+    length: Option<usize>,
+) -> std::result::Result<&'a [u8], ApngError> {
+    if chunk.chunk_type().to_string() != expected {
+        return Err(ApngError::WrongChunkType {
+            expected,
+            found: chunk.chunk_type().to_string(),
+        });
+    }
+    let data = chunk.data();
+    if length.is_some_and(|length| data.len() != length) || data.len() < 4 {
+        return Err(ApngError::InvalidLength {
+            chunk_type: expected,
+            found: data.len(),
+        });
+    }
+    Ok(data)
+}
+
+fn u16_at(data: &[u8], index: usize) -> u16 {
+    u16::from_be_bytes([data[index], data[index + 1]])
+}
+
+fn u32_at(data: &[u8], index: usize) -> u32 {
+    u32::from_be_bytes([
+        data[index],
+        data[index + 1],
+        data[index + 2],
+        data[index + 3],
+    ])
+}
+
+/// The acTL chunk: how many frames there are and how often to play them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    /// 0 means loop forever
+    pub num_plays: u32,
+}
+
+impl AnimationControl {
+    pub const CHUNK_TYPE: &'static str = "acTL";
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.num_frames.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.num_plays.to_be_bytes());
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for AnimationControl {
+    type Error = ApngError;
+
+    fn try_from(chunk: &Chunk) -> std::result::Result<Self, ApngError> {
+        let data = check_chunk(chunk, Self::CHUNK_TYPE, Some(8))?;
+        let control = AnimationControl {
+            num_frames: u32_at(data, 0),
+            num_plays: u32_at(data, 4),
+        };
+        if control.num_frames == 0 {
+            return Err(ApngError::InvalidValue("acTL frame count 0".to_string()));
+        }
+        Ok(control)
+    }
+}
+
+impl fmt::Display for AnimationControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} frames, ", self.num_frames)?;
+        match self.num_plays {
+            0 => write!(f, "loops forever"),
+            1 => write!(f, "plays once"),
+            plays => write!(f, "plays {} times", plays),
+        }
+    }
+}
+
+/// A frame delay such as `100ms`, `0.5s` or `1/30`; plain numbers are milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delay {
+    pub num: u16,
+    pub den: u16,
+}
+
+impl FromStr for Delay {
+    type Err = ApngError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, ApngError> {
+        let invalid = || ApngError::InvalidValue(format!("delay {}", s));
+        if let Some((num, den)) = s.split_once('/') {
+            return Ok(Delay {
+                num: num.trim().parse().map_err(|_| invalid())?,
+                den: den.trim().parse().map_err(|_| invalid())?,
+            });
+        }
+        let milliseconds = match (s.strip_suffix("ms"), s.strip_suffix('s')) {
+            (Some(ms), _) => ms.trim().parse::<f64>(),
+            (None, Some(seconds)) => seconds.trim().parse::<f64>().map(|s| s * 1000.0),
+            (None, None) => s.trim().parse::<f64>(),
+        }
+        .map_err(|_| invalid())?;
+        if !(0.0..=6_553_500.0).contains(&milliseconds) {
+            return Err(invalid());
+        }
+        // Keep millisecond precision when it fits, otherwise fall back to centiseconds
+        let milliseconds = milliseconds.round() as u32;
+        match u16::try_from(milliseconds) {
+            Ok(num) => Ok(Delay { num, den: 1000 }),
+            Err(_) => Ok(Delay {
+                num: (milliseconds / 10) as u16,
+                den: 100,
+            }),
+        }
+    }
+}
+
+/// What happens to a frame's region once the frame has been shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    /// Leave the canvas as it is
+    None,
+    /// Clear the region to transparent black
+    Background,
+    /// Restore the region to what it was before the frame
+    Previous,
+}
+
+/// How a frame is drawn onto the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    /// Replace the region, alpha included
+    Source,
+    /// Alpha-composite the frame over the region
+    Over,
+}
+
+/// The fcTL chunk: the size, position, timing and compositing of one frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    /// The frame is shown for `delay_num / delay_den` seconds, where a zero
+    /// denominator means 100
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl FrameControl {
+    pub const CHUNK_TYPE: &'static str = "fcTL";
+
+    /// How long the frame is shown, in seconds
+    pub fn delay(&self) -> f64 {
+        let den = match self.delay_den {
+            0 => 100,
+            den => den,
+        };
+        self.delay_num as f64 / den as f64
+    }
+
+    /// Checks the frame lies within the canvas described by `ihdr`
+    pub fn check(&self, ihdr: &Ihdr) -> std::result::Result<(), ApngError> {
+        let right = self.x_offset as u64 + self.width as u64;
+        let bottom = self.y_offset as u64 + self.height as u64;
+        if right > ihdr.width as u64 || bottom > ihdr.height as u64 {
+            return Err(ApngError::InvalidValue(format!(
+                "frame {}x{} at ({}, {}) outside the {}x{} canvas",
+                self.width, self.height, self.x_offset, self.y_offset, ihdr.width, ihdr.height
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = Vec::with_capacity(26);
+        for value in [
+            self.sequence_number,
+            self.width,
+            self.height,
+            self.x_offset,
+            self.y_offset,
+        ] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&self.delay_num.to_be_bytes());
+        data.extend_from_slice(&self.delay_den.to_be_bytes());
+        data.push(match self.dispose_op {
+            DisposeOp::None => 0,
+            DisposeOp::Background => 1,
+            DisposeOp::Previous => 2,
+        });
+        data.push(match self.blend_op {
+            BlendOp::Source => 0,
+            BlendOp::Over => 1,
+        });
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for FrameControl {
+    type Error = ApngError;
+
+    fn try_from(chunk: &Chunk) -> std::result::Result<Self, ApngError> {
+        let data = check_chunk(chunk, Self::CHUNK_TYPE, Some(26))?;
+        let dispose_op = match data[24] {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            op => return Err(ApngError::InvalidValue(format!("dispose op {}", op))),
+        };
+        let blend_op = match data[25] {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            op => return Err(ApngError::InvalidValue(format!("blend op {}", op))),
+        };
+        let control = FrameControl {
+            sequence_number: u32_at(data, 0),
+            width: u32_at(data, 4),
+            height: u32_at(data, 8),
+            x_offset: u32_at(data, 12),
+            y_offset: u32_at(data, 16),
+            delay_num: u16_at(data, 20),
+            delay_den: u16_at(data, 22),
+            dispose_op,
+            blend_op,
+        };
+        if control.width == 0 || control.height == 0 {
+            return Err(ApngError::InvalidValue(format!(
+                "frame size {}x{}",
+                control.width, control.height
+            )));
+        }
+        Ok(control)
+    }
+}
+
+impl fmt::Display for FrameControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dispose = match self.dispose_op {
+            DisposeOp::None => "none",
+            DisposeOp::Background => "background",
+            DisposeOp::Previous => "previous",
+        };
+        let blend = match self.blend_op {
+            BlendOp::Source => "source",
+            BlendOp::Over => "over",
+        };
+        write!(
+            f,
+            "sequence {}, {}x{} at ({}, {}), {:.3} s, dispose {}, blend {}",
+            self.sequence_number,
+            self.width,
+            self.height,
+            self.x_offset,
+            self.y_offset,
+            self.delay(),
+            dispose,
+            blend
+        )
+    }
+}
+
+/// The fdAT chunk: part of the compressed image data of a frame after the first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameData {
+    pub sequence_number: u32,
+    pub data: Vec<u8>,
+}
+
+impl FrameData {
+    pub const CHUNK_TYPE: &'static str = "fdAT";
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = self.sequence_number.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.data);
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for FrameData {
+    type Error = ApngError;
+
+    fn try_from(chunk: &Chunk) -> std::result::Result<Self, ApngError> {
+        let data = check_chunk(chunk, Self::CHUNK_TYPE, None)?;
+        Ok(FrameData {
+            sequence_number: u32_at(data, 0),
+            data: data[4..].to_vec(),
+        })
+    }
+}
+
+impl fmt::Display for FrameData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sequence {}", self.sequence_number)
+    }
+}
+
+/// One frame of an animation with its complete zlib stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub control: FrameControl,
+    pub data: Vec<u8>,
+}
+
+/// The frames of an APNG, checked for consistent sequence numbers and frame counts
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub control: AnimationControl,
+    pub frames: Vec<Frame>,
+    /// Whether the IDAT image is the first frame, rather than a fallback for decoders
+    /// without APNG support
+    pub default_image_is_frame: bool,
+    ihdr: Ihdr,
+    /// PLTE and tRNS, needed to decode the frames
+    palette_chunks: Vec<Chunk>,
+}
+
+impl Animation {
+    /// Collects the frames of `png`, checking the sequence numbers of fcTL and fdAT run
+    /// from 0 without gaps and that acTL gives the right number of frames.
+    pub fn from_png(png: &Png) -> std::result::Result<Self, ApngError> {
+        let ihdr = png
+            .ihdr()
+            .map_err(|e| ApngError::InvalidValue(e.to_string()))?;
+        let control = png
+            .chunk_by_type(AnimationControl::CHUNK_TYPE)
+            .ok_or(ApngError::NotAnimated)
+            .and_then(AnimationControl::try_from)?;
+
+        let mut expected_sequence = 0;
+        let mut check_sequence = |found: u32| {
+            if found != expected_sequence {
+                return Err(ApngError::Sequence {
+                    expected: expected_sequence,
+                    found,
+                });
+            }
+            expected_sequence += 1;
+            Ok(())
+        };
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut seen_idat = false;
+        let mut default_image_is_frame = false;
+        for chunk in png.chunks() {
+            match chunk.chunk_type().to_string().as_str() {
+                FrameControl::CHUNK_TYPE => {
+                    let frame = FrameControl::try_from(chunk)?;
+                    check_sequence(frame.sequence_number)?;
+                    frame.check(&ihdr)?;
+                    if !seen_idat {
+                        if !frames.is_empty() {
+                            return Err(ApngError::InvalidValue(
+                                "second fcTL before the image data".to_string(),
+                            ));
+                        }
+                        default_image_is_frame = true;
+                    }
+                    frames.push(Frame {
+                        control: frame,
+                        data: Vec::new(),
+                    });
+                }
+                "IDAT" => {
+                    seen_idat = true;
+                    if default_image_is_frame && frames.len() == 1 {
+                        frames[0].data.extend_from_slice(chunk.data());
+                    }
+                }
+                FrameData::CHUNK_TYPE => {
+                    let data = FrameData::try_from(chunk)?;
+                    check_sequence(data.sequence_number)?;
+                    let in_default_image = default_image_is_frame && frames.len() == 1;
+                    match frames.last_mut() {
+                        Some(frame) if !in_default_image => frame.data.extend(data.data),
+                        _ => {
+                            return Err(ApngError::InvalidValue(format!(
+                                "fdAT {} without a frame to belong to",
+                                data.sequence_number
+                            )))
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if frames.len() != control.num_frames as usize {
+            return Err(ApngError::FrameCount {
+                declared: control.num_frames,
+                found: frames.len(),
+            });
+        }
+        // This holds whether or not the default image is part of the animation
+        if let Some(first) = frames.first().map(|frame| &frame.control) {
+            let full = (first.width, first.height, first.x_offset, first.y_offset)
+                == (ihdr.width, ihdr.height, 0, 0);
+            if !full {
+                return Err(ApngError::InvalidValue(
+                    "first frame must cover the whole canvas".to_string(),
+                ));
+            }
+        }
+        if let Some(index) = frames.iter().position(|frame| frame.data.is_empty()) {
+            return Err(ApngError::MissingFrameData(index));
+        }
+        let palette_chunks = ["PLTE", "tRNS"]
+            .iter()
+            .filter_map(|chunk_type| png.chunk_by_type(chunk_type).cloned())
+            .collect();
+        Ok(Animation {
+            control,
+            frames,
+            default_image_is_frame,
+            ihdr,
+            palette_chunks,
+        })
+    }
+
+    /// The size and pixel format shared by every frame
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    /// The IHDR a standalone image of `frame` would have
+    pub fn frame_ihdr(&self, frame: &Frame) -> Ihdr {
+        Ihdr {
+            width: frame.control.width,
+            height: frame.control.height,
+            ..self.ihdr.clone()
+        }
+    }
+
+    /// Decodes the samples of a single frame in the image's own pixel format
+    pub fn decode_raw(&self, frame: &Frame) -> Result<RawImage> {
+        let ihdr = self.frame_ihdr(frame);
+        let (filtered, _) = inflate_image_data(&frame.data, &ihdr)?;
+        RawImage::from_filtered(ihdr, &filtered)
+    }
+
+    /// Decodes a single frame without compositing it
+    pub fn decode(&self, frame: &Frame) -> Result<Rgba16Image> {
+        let mut chunks = vec![self.frame_ihdr(frame).to_chunk()];
+        chunks.extend(self.palette_chunks.iter().cloned());
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        let mut png = Png::from_chunks(chunks);
+        png.set_image_data(frame.data.clone());
+        Rgba16Image::from_png(&png)
+    }
+
+    /// Composites every frame onto the canvas, applying the blend and dispose operations,
+    /// and returns the canvas as it looks while each frame is shown.
+    pub fn render(&self) -> Result<Vec<Rgba16Image>> {
+        let images = self
+            .frames
+            .iter()
+            .map(|frame| self.decode(frame))
+            .collect::<Result<Vec<_>>>()?;
+        // The canvas size comes from the header, so only allocate it once the first frame,
+        // which covers the whole canvas, has been decoded from data that is really there
+        if images.is_empty() {
+            return Ok(Vec::new());
+        }
+        let width = self.ihdr.width as usize;
+        let mut canvas = Rgba16Image::blank(self.ihdr.width, self.ihdr.height);
+        let mut rendered = Vec::with_capacity(self.frames.len());
+        for (frame, image) in self.frames.iter().zip(images) {
+            let control = &frame.control;
+            let before = match control.dispose_op {
+                DisposeOp::Previous => Some(canvas.clone()),
+                _ => None,
+            };
+            let region = (0..control.height as usize)
+                .flat_map(|y| (0..control.width as usize).map(move |x| (x, y)));
+            let canvas_index = |x: usize, y: usize| {
+                (control.y_offset as usize + y) * width + control.x_offset as usize + x
+            };
+
+            for (x, y) in region.clone() {
+                let source = image.pixels[y * control.width as usize + x];
+                let target = &mut canvas.pixels[canvas_index(x, y)];
+                *target = match control.blend_op {
+                    BlendOp::Source => source,
+                    BlendOp::Over => over(source, *target),
+                };
+            }
+            rendered.push(canvas.clone());
+
+            // Disposing "previous" on the first frame restores the blank canvas, which
+            // is what the spec asks for
+            for (x, y) in region {
+                let index = canvas_index(x, y);
+                match (control.dispose_op, &before) {
+                    (DisposeOp::Background, _) => canvas.pixels[index] = [0; 4],
+                    (DisposeOp::Previous, Some(before)) => {
+                        canvas.pixels[index] = before.pixels[index]
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+/// Replaces the compressed image data of every frame of the animation in `png`, one
+/// element of `frames` per frame. Each frame after the default image gets a single fdAT
+/// chunk, and the sequence numbers of all fcTL and fdAT chunks are renumbered to match.
+pub fn set_frame_data(png: &mut Png, frames: Vec<Vec<u8>>) -> std::result::Result<(), ApngError> {
+    let animation = Animation::from_png(png)?;
+    if frames.len() != animation.frames.len() {
+        return Err(ApngError::FrameCount {
+            declared: animation.control.num_frames,
+            found: frames.len(),
+        });
+    }
+    let mut frames = frames.into_iter();
+    let mut default_image = match animation.default_image_is_frame {
+        true => frames.next(),
+        false => None,
+    };
+    let mut sequence_number = 0;
+    let mut frame_index = 0;
+    let mut chunks = Vec::with_capacity(png.chunks().len());
+    for chunk in png.chunks() {
+        match chunk.chunk_type().to_string().as_str() {
+            FrameControl::CHUNK_TYPE => {
+                let mut control = FrameControl::try_from(chunk)?;
+                control.sequence_number = sequence_number;
+                sequence_number += 1;
+                chunks.push(control.to_chunk());
+                if frame_index > 0 || !animation.default_image_is_frame {
+                    let data = FrameData {
+                        sequence_number,
+                        data: frames.next().unwrap_or_default(),
+                    };
+                    sequence_number += 1;
+                    chunks.push(data.to_chunk());
+                }
+                frame_index += 1;
+            }
+            FrameData::CHUNK_TYPE => {}
+            // The default image keeps its IDAT chunks but they hold the new data, all in
+            // the first one
+            "IDAT" if animation.default_image_is_frame => {
+                if let Some(data) = default_image.take() {
+                    chunks.push(Chunk::new(chunk.chunk_type().clone(), data));
+                }
+            }
+            _ => chunks.push(chunk.clone()),
+        }
+    }
+    *png.chunks_mut() = chunks;
+    Ok(())
+}
+
+/// Assembles an animation from `frames`, which must share their IHDR and any PLTE and
+/// tRNS. The first frame also becomes the default image, and the other frames' image data
+/// is moved into fdAT chunks. Ancillary chunks are taken from the first frame only.
+pub fn assemble(
+    frames: &[Png],
+    delay: Delay,
+    num_plays: u32,
+) -> std::result::Result<Png, ApngError> {
+    let first = frames
+        .first()
+        .ok_or_else(|| ApngError::InvalidValue("animation with no frames".to_string()))?;
+    let ihdr = first
+        .ihdr()
+        .map_err(|e| ApngError::InvalidValue(e.to_string()))?;
+    let find =
+        |png: &Png, chunk_type: &str| png.chunk_by_type(chunk_type).map(|c| c.data().to_vec());
+    for (index, frame) in frames.iter().enumerate().skip(1) {
+        let mismatch = |reason: String| ApngError::MismatchedFrame { index, reason };
+        let frame_ihdr = frame.ihdr().map_err(|e| mismatch(e.to_string()))?;
+        if frame_ihdr != ihdr {
+            return Err(mismatch(format!("{} rather than {}", frame_ihdr, ihdr)));
+        }
+        for chunk_type in ["PLTE", "tRNS"] {
+            if find(frame, chunk_type) != find(first, chunk_type) {
+                return Err(mismatch(format!("{} differs", chunk_type)));
+            }
+        }
+        if frame.chunk_by_type("IDAT").is_none() {
+            return Err(ApngError::MissingFrameData(index));
+        }
+    }
+
+    let mut png = Png::from_chunks(
+        first
+            .chunks()
+            .iter()
+            .filter(|c| {
+                let chunk_type = c.chunk_type().to_string();
+                ![
+                    AnimationControl::CHUNK_TYPE,
+                    FrameControl::CHUNK_TYPE,
+                    FrameData::CHUNK_TYPE,
+                ]
+                .contains(&chunk_type.as_str())
+            })
+            .cloned()
+            .collect(),
+    );
+    png.set_chunk(
+        AnimationControl {
+            num_frames: frames.len() as u32,
+            num_plays,
+        }
+        .to_chunk(),
+    );
+
+    let mut sequence_number = 0;
+    let frame_control = |sequence_number: &mut u32| {
+        let control = FrameControl {
+            sequence_number: *sequence_number,
+            width: ihdr.width,
+            height: ihdr.height,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: delay.num,
+            delay_den: delay.den,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+        *sequence_number += 1;
+        control.to_chunk()
+    };
+    let is_idat = |c: &Chunk| c.chunk_type().to_string() == "IDAT";
+    let first_idat = png.chunks().iter().position(is_idat).unwrap_or(0);
+    png.chunks_mut()
+        .insert(first_idat, frame_control(&mut sequence_number));
+
+    let mut animation_chunks = Vec::new();
+    for frame in frames.iter().skip(1) {
+        animation_chunks.push(frame_control(&mut sequence_number));
+        for idat in frame.chunks().iter().filter(|c| is_idat(c)) {
+            let data = FrameData {
+                sequence_number,
+                data: idat.data().to_vec(),
+            };
+            sequence_number += 1;
+            animation_chunks.push(data.to_chunk());
+        }
+    }
+    let after_idat = png.chunks().iter().rposition(is_idat).map_or(0, |i| i + 1);
+    png.chunks_mut()
+        .splice(after_idat..after_idat, animation_chunks);
+    Ok(png)
+}
+
+/// Alpha-composites `source` over `destination`, both non-premultiplied 16-bit RGBA
+fn over(source: [u16; 4], destination: [u16; 4]) -> [u16; 4] {
+    match source[3] {
+        65535 => return source,
+        0 => return destination,
+        _ => {}
+    }
+    let source_alpha = source[3] as f64 / 65535.0;
+    let destination_alpha = destination[3] as f64 / 65535.0 * (1.0 - source_alpha);
+    let alpha = source_alpha + destination_alpha;
+    let mut pixel = [0; 4];
+    for channel in 0..3 {
+        let value =
+            source[channel] as f64 * source_alpha + destination[channel] as f64 * destination_alpha;
+        pixel[channel] = (value / alpha).round() as u16;
+    }
+    pixel[3] = (alpha * 65535.0).round() as u16;
+    pixel
+}
+
+impl fmt::Display for Animation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Animation: {}", self.control)?;
+        if !self.default_image_is_frame {
+            writeln!(f, "The default image is not part of the animation")?;
+        }
+        for (i, frame) in self.frames.iter().enumerate() {
+            writeln!(f, "  Frame {}: {}", i, frame.control)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+    use crate::image::{deflate, filtered_len, INFLATE_SLACK};
+
+    fn ihdr(width: u32, height: u32) -> Ihdr {
+        Ihdr {
+            width,
+            height,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        }
+    }
+
+    /// Compressed image data for a frame filled with one color
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> Vec<u8> {
+        let mut image = RawImage::blank(ihdr(width, height));
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                for (channel, &value) in color.iter().enumerate() {
+                    image.set_sample(x, y, channel, value as u16);
+                }
+            }
+        }
+        image.encode(6)
+    }
+
+    fn frame_control(sequence_number: u32, size: (u32, u32), offset: (u32, u32)) -> FrameControl {
+        FrameControl {
+            sequence_number,
+            width: size.0,
+            height: size.1,
+            x_offset: offset.0,
+            y_offset: offset.1,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+    }
+
+    /// A 4x4 red first frame followed by a 2x2 frame at (1, 1)
+    fn animation(second: FrameControl, color: [u8; 4]) -> Png {
+        let first = frame_control(0, (4, 4), (0, 0));
+        let idat = Chunk::new(
+            ChunkType::from_str("IDAT").unwrap(),
+            solid(4, 4, [255, 0, 0, 255]),
+        );
+        let data = FrameData {
+            sequence_number: 2,
+            data: solid(2, 2, color),
+        };
+        Png::from_chunks(vec![
+            ihdr(4, 4).to_chunk(),
+            AnimationControl {
+                num_frames: 2,
+                num_plays: 0,
+            }
+            .to_chunk(),
+            first.to_chunk(),
+            idat,
+            second.to_chunk(),
+            data.to_chunk(),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_chunk_round_trips() {
+        let control = AnimationControl {
+            num_frames: 3,
+            num_plays: 2,
+        };
+        assert_eq!(
+            AnimationControl::try_from(&control.to_chunk()).unwrap(),
+            control
+        );
+        assert_eq!(control.to_string(), "3 frames, plays 2 times");
+
+        let mut frame = frame_control(5, (2, 3), (1, 0));
+        frame.dispose_op = DisposeOp::Previous;
+        frame.blend_op = BlendOp::Over;
+        assert_eq!(FrameControl::try_from(&frame.to_chunk()).unwrap(), frame);
+        assert_eq!(frame.delay(), 0.1);
+
+        let data = FrameData {
+            sequence_number: 7,
+            data: vec![1, 2, 3],
+        };
+        assert_eq!(FrameData::try_from(&data.to_chunk()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_sequence_validation() {
+        let png = animation(frame_control(1, (2, 2), (1, 1)), [0, 0, 255, 255]);
+        let parsed = Animation::from_png(&png).unwrap();
+        assert!(parsed.default_image_is_frame);
+        assert_eq!(parsed.frames.len(), 2);
+
+        let png = animation(frame_control(3, (2, 2), (1, 1)), [0, 0, 255, 255]);
+        assert_eq!(
+            Animation::from_png(&png).err(),
+            Some(ApngError::Sequence {
+                expected: 1,
+                found: 3
+            })
+        );
+
+        let png = animation(frame_control(1, (2, 2), (3, 3)), [0, 0, 255, 255]);
+        assert!(Animation::from_png(&png).is_err());
+    }
+
+    #[test]
+    fn test_first_frame_covers_canvas_after_default_image() {
+        let png = |size: (u32, u32)| {
+            let data = FrameData {
+                sequence_number: 1,
+                data: solid(size.0, size.1, [0, 0, 255, 255]),
+            };
+            Png::from_chunks(vec![
+                ihdr(4, 4).to_chunk(),
+                AnimationControl {
+                    num_frames: 1,
+                    num_plays: 0,
+                }
+                .to_chunk(),
+                Chunk::new(
+                    ChunkType::from_str("IDAT").unwrap(),
+                    solid(4, 4, [255, 0, 0, 255]),
+                ),
+                frame_control(0, size, (0, 0)).to_chunk(),
+                data.to_chunk(),
+                Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+            ])
+        };
+        let parsed = Animation::from_png(&png((4, 4))).unwrap();
+        assert!(!parsed.default_image_is_frame);
+        assert!(matches!(
+            Animation::from_png(&png((2, 2))),
+            Err(ApngError::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn test_delay() {
+        assert_eq!(
+            Delay::from_str("100ms").unwrap(),
+            Delay {
+                num: 100,
+                den: 1000
+            }
+        );
+        assert_eq!(
+            Delay::from_str("1.5s").unwrap(),
+            Delay {
+                num: 1500,
+                den: 1000
+            }
+        );
+        assert_eq!(Delay::from_str("1/30").unwrap(), Delay { num: 1, den: 30 });
+        assert_eq!(
+            Delay::from_str("90s").unwrap(),
+            Delay {
+                num: 9000,
+                den: 100
+            }
+        );
+        assert!(Delay::from_str("soon").is_err());
+    }
+
+    #[test]
+    fn test_assemble() {
+        let frame = |color: [u8; 4]| {
+            let mut png = Png::from_chunks(vec![
+                ihdr(4, 4).to_chunk(),
+                Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+            ]);
+            png.set_image_chunks(vec![
+                solid(4, 4, color)[..5].to_vec(),
+                solid(4, 4, color)[5..].to_vec(),
+            ]);
+            png
+        };
+        let frames = [
+            frame([255, 0, 0, 255]),
+            frame([0, 255, 0, 255]),
+            frame([0, 0, 255, 255]),
+        ];
+        let png = assemble(&frames, Delay::from_str("50ms").unwrap(), 2).unwrap();
+        let png = Png::try_from(&png.as_bytes()[..]).unwrap();
+        let animation = Animation::from_png(&png).unwrap();
+        assert_eq!(animation.control.num_plays, 2);
+        assert!(animation.default_image_is_frame);
+        assert_eq!(animation.frames[2].control.sequence_number, 4);
+        assert_eq!(animation.frames[1].control.delay(), 0.05);
+        let rendered = animation.render().unwrap();
+        assert_eq!(rendered[2].pixels[0], [0, 0, 65535, 65535]);
+
+        let mut small = Png::from_chunks(vec![ihdr(2, 4).to_chunk()]);
+        small.set_image_data(solid(2, 4, [0; 4]));
+        assert!(matches!(
+            assemble(
+                &[frames[0].clone(), small],
+                Delay::from_str("1s").unwrap(),
+                0
+            ),
+            Err(ApngError::MismatchedFrame { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_render_blend_and_dispose() {
+        let mut second = frame_control(1, (2, 2), (1, 1));
+        second.blend_op = BlendOp::Over;
+        let png = animation(second, [0, 0, 255, 128]);
+        let frames = Animation::from_png(&png).unwrap().render().unwrap();
+        assert_eq!(frames[1].pixels[0], [65535, 0, 0, 65535]);
+        let blended = frames[1].pixels[4 + 1];
+        assert_eq!(blended[3], 65535);
+        assert!(blended[0] > 30000 && blended[2] > 30000);
+
+        // The first frame is cleared before the second is drawn with source blending
+        let mut png = animation(frame_control(1, (2, 2), (1, 1)), [0, 0, 255, 0]);
+        let mut first = frame_control(0, (4, 4), (0, 0));
+        first.dispose_op = DisposeOp::Background;
+        png.chunks_mut()[2] = first.to_chunk();
+        let frames = Animation::from_png(&png).unwrap().render().unwrap();
+        assert_eq!(frames[0].pixels[0], [65535, 0, 0, 65535]);
+        assert!(frames[1].pixels.iter().all(|p| p[3] == 0));
+    }
+
+    #[test]
+    fn test_frame_deflate_bomb() {
+        let mut png = animation(frame_control(1, (2, 2), (1, 1)), [0, 0, 255, 255]);
+        let limit = filtered_len(&ihdr(2, 2)).unwrap() + INFLATE_SLACK;
+        let data = FrameData {
+            sequence_number: 2,
+            data: deflate(&vec![0; limit + 1], 9),
+        };
+        png.chunks_mut()[5] = data.to_chunk();
+        let animation = Animation::from_png(&png).unwrap();
+        assert!(animation.decode_raw(&animation.frames[1]).is_err());
+        assert!(animation.decode_raw(&animation.frames[0]).is_ok());
+    }
+
+    #[test]
+    fn test_render_huge_canvas() {
+        // The header claims a canvas far larger than the frame data can fill
+        let mut png = animation(frame_control(1, (2, 2), (1, 1)), [0, 0, 255, 255]);
+        png.chunks_mut()[0] = ihdr(1 << 20, 1 << 20).to_chunk();
+        png.chunks_mut()[2] = frame_control(0, (1 << 20, 1 << 20), (0, 0)).to_chunk();
+        assert!(Animation::from_png(&png).unwrap().render().is_err());
+    }
+}
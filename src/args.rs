@@ -0,0 +1,492 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use crate::apng::Delay;
+use crate::chunk_type::ChunkType;
+use crate::color::{Chromaticities, Cicp, Gamma, RenderingIntent};
+use crate::convert::Target;
+use crate::hdr::{ContentLightLevel, MasteringDisplay};
+use crate::netpbm::ExportFormat;
+use crate::palette::Replacement;
+use crate::stego::Method;
+use crate::strip::Preset;
+use crate::transform::{Crop, Flip, Rotation};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "pngme", about = "Hide secret messages in PNG files")]
+pub struct Cli {
+    /// Set the tIME chunk to the current time in every file written
+    #[structopt(long, global = true)]
+    pub touch: bool,
+    #[structopt(subcommand)]
+    pub command: PngMeArgs,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum PngMeArgs {
+    /// Store a message in a new chunk of a PNG, MNG, JNG or RIFF (WebP, WAV) file
+    Encode(EncodeArgs),
+    /// Print the message stored in a chunk
+    Decode(DecodeArgs),
+    /// Remove the first chunk of a type
+    Remove(RemoveArgs),
+    /// Print the chunks of a PNG file
+    Print(PrintArgs),
+    /// Hide a message inside the image data
+    Embed(EmbedArgs),
+    /// Recover a message hidden with `embed`
+    Extract(ExtractArgs),
+    /// Show how large a message `embed` can hide
+    Capacity(CapacityArgs),
+    /// Look for signs of hidden data
+    Analyze(AnalyzeArgs),
+    /// Extract or strip data appended after IEND
+    Trailer(TrailerArgs),
+    /// Check whether two files show the same pixels
+    Same(SameArgs),
+    /// Remove metadata before publishing a file
+    Strip(StripArgs),
+    /// Copy ancillary chunks from one file into another
+    Transplant(TransplantArgs),
+    /// Show or edit the EXIF metadata in the eXIf chunk
+    Exif(ExifArgs),
+    /// Show or edit the XMP metadata packet
+    Xmp(XmpArgs),
+    /// Extract or embed the ICC color profile
+    Icc(IccArgs),
+    /// Show or set the color-management chunks
+    Color(ColorArgs),
+    /// Show or set the physical resolution in the pHYs chunk
+    Dpi(DpiArgs),
+    /// Show or edit the palette of an indexed image
+    Palette(PaletteArgs),
+    /// Inspect animated PNGs or extract their frames
+    Frames(FramesArgs),
+    /// Convert an Apple CgBI image from an iOS app bundle into a standard PNG
+    Normalize(NormalizeArgs),
+    /// Make a file smaller without changing its pixels or message chunks
+    Optimize(OptimizeArgs),
+    /// Change the color type and bit depth of an image
+    Convert(ConvertArgs),
+    /// Write the decoded pixels as PPM, PAM or raw samples
+    Export(ExportArgs),
+    /// Create a PNG from a PPM, PGM or PAM image
+    Import(ImportArgs),
+    /// Crop, rotate or flip an image, keeping its other chunks
+    Transform(TransformArgs),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct EncodeArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    pub chunk_type: ChunkType,
+    pub message: String,
+    /// Write the result here instead of overwriting the input
+    #[structopt(parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct DecodeArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    pub chunk_type: ChunkType,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct RemoveArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    pub chunk_type: ChunkType,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct PrintArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Also print the tags of the eXIf chunk
+    #[structopt(long)]
+    pub exif: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct EmbedArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    pub message: String,
+    /// Embedding method: palette, zlib-trailer, stored-block, idat-split, after-iend,
+    /// frame-chunks or frame-lsb
+    #[structopt(long, default_value = "palette")]
+    pub method: Method,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExtractArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Embedding method: palette, zlib-trailer, stored-block, idat-split, after-iend,
+    /// frame-chunks or frame-lsb
+    #[structopt(long, default_value = "palette")]
+    pub method: Method,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CapacityArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Embedding method: palette, zlib-trailer, stored-block, idat-split, after-iend,
+    /// frame-chunks or frame-lsb
+    #[structopt(long, default_value = "palette")]
+    pub method: Method,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AnalyzeArgs {
+    #[structopt(parse(from_os_str), required = true)]
+    pub file_paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum TrailerArgs {
+    /// Write the data after IEND to a file
+    Extract(TrailerExtractArgs),
+    /// Remove the data after IEND
+    Strip(TrailerStripArgs),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct TrailerExtractArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    #[structopt(parse(from_os_str))]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct TrailerStripArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct SameArgs {
+    #[structopt(parse(from_os_str))]
+    pub first: PathBuf,
+    #[structopt(parse(from_os_str))]
+    pub second: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct StripArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Chunks to keep: all (no ancillary chunks), color (color management only) or listed
+    /// (only the --keep types). Transparency and animation chunks are always kept unless
+    /// named with --drop
+    #[structopt(long, default_value = "all")]
+    pub preset: Preset,
+    /// Keep chunks of this type as well
+    #[structopt(long, number_of_values = 1)]
+    pub keep: Vec<ChunkType>,
+    /// Remove chunks of this type even if the preset keeps them
+    #[structopt(long, number_of_values = 1)]
+    pub drop: Vec<ChunkType>,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct TransplantArgs {
+    /// File to copy chunks from
+    #[structopt(parse(from_os_str))]
+    pub source: PathBuf,
+    /// File to copy chunks into
+    #[structopt(parse(from_os_str))]
+    pub destination: PathBuf,
+    /// Copy unsafe-to-copy chunks even if the images differ, and replace the destination's
+    /// sRGB or iCCP chunk with a conflicting one from the source
+    #[structopt(long)]
+    pub force: bool,
+    /// Write the result here instead of overwriting the destination
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExifArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Print only the value of the tag with this name, such as Model or GPSLatitude
+    #[structopt(long)]
+    pub tag: Option<String>,
+    /// Remove the GPS location tags
+    #[structopt(long)]
+    pub remove_gps: bool,
+    /// Remove the orientation tag
+    #[structopt(long)]
+    pub remove_orientation: bool,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum XmpArgs {
+    /// Pretty-print the XMP packet and list its properties
+    Show(XmpShowArgs),
+    /// Set a property such as dc:title, adding a packet if there is none
+    Set(XmpSetArgs),
+    /// Remove a property
+    Remove(XmpRemoveArgs),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct XmpShowArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct XmpSetArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    pub property: String,
+    pub value: String,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct XmpRemoveArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    pub property: String,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum IccArgs {
+    /// Write the embedded ICC profile to a file
+    Extract(IccExtractArgs),
+    /// Embed an ICC profile, replacing any sRGB chunk
+    Embed(IccEmbedArgs),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct IccExtractArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    #[structopt(parse(from_os_str))]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct IccEmbedArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    #[structopt(parse(from_os_str))]
+    pub profile: PathBuf,
+    /// Profile name stored in the chunk; defaults to the profile's description
+    #[structopt(long)]
+    pub name: Option<String>,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ColorArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Set gAMA, such as 0.45455 or 1/2.2
+    #[structopt(long)]
+    pub gamma: Option<Gamma>,
+    /// Set cHRM from eight comma-separated values: white, red, green and blue x,y
+    #[structopt(long)]
+    pub chromaticities: Option<Chromaticities>,
+    /// Set sRGB with a rendering intent: perceptual, relative, saturation or absolute.
+    /// Removes any iCCP chunk
+    #[structopt(long)]
+    pub srgb: Option<RenderingIntent>,
+    /// Set cICP from primaries,transfer,matrix,full-range, such as 9,16,0,1
+    #[structopt(long)]
+    pub cicp: Option<Cicp>,
+    /// Set mDCV from red, green, blue and white x,y then the maximum and minimum
+    /// luminance in nits, such as 0.68,0.32,0.265,0.69,0.15,0.06,0.3127,0.329,1000,0.005
+    #[structopt(long)]
+    pub mdcv: Option<MasteringDisplay>,
+    /// Set cLLI from MaxCLL,MaxFALL in nits
+    #[structopt(long)]
+    pub clli: Option<ContentLightLevel>,
+    /// Remove gAMA, cHRM, sRGB, cICP, mDCV or cLLI
+    #[structopt(long, number_of_values = 1)]
+    pub clear: Vec<ChunkType>,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct DpiArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Set the resolution to this many dots per inch
+    #[structopt(long)]
+    pub set: Option<f64>,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum PaletteArgs {
+    /// Print the palette as a table, or as a GIMP palette file
+    Show(PaletteShowArgs),
+    /// Replace palette colors given as INDEX=#RRGGBB[AA] or #RRGGBB[AA]=#RRGGBB[AA]
+    Replace(PaletteReplaceArgs),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct PaletteShowArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Print a GIMP palette (.gpl) instead of a table
+    #[structopt(long)]
+    pub gpl: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct PaletteReplaceArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    #[structopt(required = true)]
+    pub replacements: Vec<Replacement>,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum FramesArgs {
+    /// List the frames of an animated PNG
+    Info(FramesInfoArgs),
+    /// Write each composited frame to a standalone PNG
+    Extract(FramesExtractArgs),
+    /// Combine PNGs of the same size and format into an animated PNG
+    Build(FramesBuildArgs),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct FramesInfoArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct FramesExtractArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Directory for the frames, which are named after the input file
+    #[structopt(parse(from_os_str))]
+    pub output_dir: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct FramesBuildArgs {
+    /// The frames in order; the first is also shown by viewers without APNG support
+    #[structopt(parse(from_os_str), required = true)]
+    pub frames: Vec<PathBuf>,
+    /// How long each frame is shown, such as 100ms, 0.5s or 1/30
+    #[structopt(long, default_value = "100ms")]
+    pub delay: Delay,
+    /// How many times to play the animation, 0 for forever
+    #[structopt(long, default_value = "0")]
+    pub loops: u32,
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct NormalizeArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct OptimizeArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// zlib compression level, 0 to 9
+    #[structopt(long, default_value = "9")]
+    pub level: u32,
+    /// Keep the color type and bit depth
+    #[structopt(long)]
+    pub no_reduce: bool,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ConvertArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Format to convert to: rgba8, rgb8, gray8, gray16 or indexed
+    #[structopt(long)]
+    pub to: Target,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExportArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    #[structopt(parse(from_os_str))]
+    pub output: PathBuf,
+    /// ppm (RGB), pam (with alpha and grayscale kept) or raw (unfiltered scanlines)
+    #[structopt(long)]
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ImportArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    #[structopt(parse(from_os_str))]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct TransformArgs {
+    #[structopt(parse(from_os_str))]
+    pub file_path: PathBuf,
+    /// Keep only the region x,y,width,height of the original image
+    #[structopt(long)]
+    pub crop: Option<Crop>,
+    /// Rotate clockwise by 90, 180 or 270 degrees, after cropping
+    #[structopt(long)]
+    pub rotate: Option<Rotation>,
+    /// Mirror horizontally (h) or vertically (v), after rotating
+    #[structopt(long)]
+    pub flip: Option<Flip>,
+    /// Write the result here instead of overwriting the input
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
@@ -0,0 +1,166 @@
+//! Apple's CgBI variant of PNG, written by Xcode's `pngcrush -iphone` into iOS app bundles.
+//!
+//! A CgBI chunk comes before IHDR, the IDAT chunks hold a raw deflate stream without the
+//! zlib header and checksum, and the pixels are stored as BGRA with the color channels
+//! premultiplied by alpha. Decoders other than Apple's reject or misread these files.
+
+use std::io::Read;
+
+use flate2::read::DeflateDecoder;
+
+use crate::ihdr::ColorType;
+use crate::image::{filtered_len, RawImage};
+use crate::png::Png;
+use crate::{Error, Result};
+
+pub const CHUNK_TYPE: &str = "CgBI";
+
+/// Apple's index of IDAT offsets for parallel decoding, which re-encoding invalidates
+const IDOT_CHUNK_TYPE: &str = "iDOT";
+
+/// Whether `png` is an Apple CgBI image rather than a standard PNG
+pub fn is_cgbi(png: &Png) -> bool {
+    png.chunk_by_type(CHUNK_TYPE).is_some()
+}
+
+/// Converts a CgBI image into a standard PNG: the image data is given its zlib wrapper
+/// back, BGRA becomes RGBA with straight alpha, and the CgBI and iDOT chunks are removed.
+/// Only 8-bit truecolor images have their channels reordered, which is all Apple's
+/// tools write.
+pub fn normalize(png: &Png) -> Result<Png> {
+    if !is_cgbi(png) {
+        return Err(Error::from("Not a CgBI image"));
+    }
+    let ihdr = png.ihdr()?;
+    let expected = filtered_len(&ihdr)?;
+    let mut filtered = Vec::new();
+    DeflateDecoder::new(&png.image_data()[..])
+        .take(expected as u64 + 1)
+        .read_to_end(&mut filtered)?;
+    if filtered.len() != expected {
+        return Err(Error::from(format!(
+            "Image data does not decompress to the {} bytes the image needs",
+            expected
+        )));
+    }
+    let mut image = RawImage::from_filtered(ihdr.clone(), &filtered)?;
+
+    if ihdr.bit_depth == 8 && matches!(ihdr.color_type, ColorType::Rgb | ColorType::Rgba) {
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let (blue, red) = (image.sample(x, y, 0), image.sample(x, y, 2));
+                image.set_sample(x, y, 0, red);
+                image.set_sample(x, y, 2, blue);
+                if ihdr.color_type == ColorType::Rgba {
+                    let alpha = image.sample(x, y, 3) as u32;
+                    for channel in 0..3 {
+                        let value = image.sample(x, y, channel) as u32;
+                        image.set_sample(x, y, channel, unpremultiply(value, alpha) as u16);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut normalized = Png::from_chunks(
+        png.chunks()
+            .iter()
+            .filter(|c| {
+                let chunk_type = c.chunk_type().to_string();
+                chunk_type != CHUNK_TYPE && chunk_type != IDOT_CHUNK_TYPE
+            })
+            .cloned()
+            .collect(),
+    );
+    normalized.set_image_data(image.encode(9));
+    Ok(normalized)
+}
+
+/// The straight-alpha value of an 8-bit sample premultiplied by `alpha`, rounded
+fn unpremultiply(value: u32, alpha: u32) -> u32 {
+    match alpha {
+        0 => 0,
+        _ => ((value * 255 + alpha / 2) / alpha).min(255),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::ihdr::Ihdr;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::str::FromStr;
+
+    /// Straight RGBA pixels of a 3x1 image
+    const PIXELS: [[u8; 4]; 3] = [[255, 0, 10, 255], [200, 100, 50, 128], [9, 9, 9, 0]];
+
+    fn cgbi_png() -> Png {
+        let ihdr = Ihdr {
+            width: 3,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+        let mut image = RawImage::blank(ihdr.clone());
+        for (x, [r, g, b, a]) in PIXELS.iter().enumerate() {
+            let premultiply = |v: &u8| ((*v as u32 * *a as u32 + 127) / 255) as u16;
+            for (channel, value) in [premultiply(b), premultiply(g), premultiply(r)]
+                .into_iter()
+                .enumerate()
+            {
+                image.set_sample(x, 0, channel, value);
+            }
+            image.set_sample(x, 0, 3, *a as u16);
+        }
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&image.filtered()).unwrap();
+
+        let chunk = |chunk_type: &str, data: Vec<u8>| {
+            Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+        };
+        Png::from_chunks(vec![
+            chunk("CgBI", vec![0x50, 0, 0x20, 0x06]),
+            ihdr.to_chunk(),
+            chunk("IDAT", encoder.finish().unwrap()),
+            chunk("IEND", Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_normalize() {
+        let png = cgbi_png();
+        assert!(is_cgbi(&png));
+        assert_eq!(png.ihdr().unwrap().width, 3);
+        assert!(RawImage::from_png(&png).is_err());
+
+        let normalized = normalize(&png).unwrap();
+        assert!(!is_cgbi(&normalized));
+        assert_eq!(normalized.chunks()[0].chunk_type().to_string(), "IHDR");
+        let image = RawImage::from_png(&normalized).unwrap();
+        let pixel = |x: usize| [0, 1, 2, 3].map(|c| image.sample(x, 0, c) as u8);
+        assert_eq!(pixel(0), PIXELS[0]);
+        // Premultiplying by half an alpha loses the lowest bit
+        assert_eq!(pixel(1), [199, 100, 50, 128]);
+        assert_eq!(pixel(2), [0, 0, 0, 0]);
+
+        assert!(normalize(&normalized).is_err());
+    }
+
+    #[test]
+    fn test_wrong_data_length() {
+        let ihdr = cgbi_png().ihdr().unwrap();
+        let expected = filtered_len(&ihdr).unwrap();
+        for length in [expected - 1, expected + 1, 1 << 24] {
+            let mut png = cgbi_png();
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&vec![0; length]).unwrap();
+            png.set_image_data(encoder.finish().unwrap());
+            assert!(normalize(&png).is_err());
+        }
+    }
+}
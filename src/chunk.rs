@@ -1,40 +1,47 @@
-#![allow(unused_variables)]
-
-use crc::crc32;
-
 use std::convert::TryFrom;
 use std::fmt;
-use std::str::FromStr;
-use std::error::Error;
 use thiserror::Error;
 
-use crate::chunk_type;
+use crate::chunk_type::{ChunkType, ChunkTypeError};
 
-use super::chunk_type::ChunkType;
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chunk{
     data: Vec<u8>,
     chunk_type: ChunkType,
     crc: u32,
     length: u32,
 }
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum ChunkError{
+    #[error("Invalid chunk type")]
     InvalidChunkType,
+    #[error("Chunk data is truncated")]
     InvalidData,
+    #[error("Chunk CRC does not match its contents")]
     InvalidCRC,
 }
 
+impl From<ChunkTypeError> for ChunkError {
+    fn from(_: ChunkTypeError) -> Self {
+        ChunkError::InvalidChunkType
+    }
+}
+
 impl TryFrom<&[u8]> for Chunk{
     type Error = ChunkError;
     
         fn try_from(bytes: &[u8]) -> Result<Self, ChunkError>{
-            let data_length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            if bytes.len() < Chunk::METADATA_LENGTH {
+                return Err(ChunkError::InvalidData);
+            }
+            let data_length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+            if bytes.len() < Chunk::METADATA_LENGTH + data_length {
+                return Err(ChunkError::InvalidData);
+            }
             let chunktbytes= [bytes[4], bytes[5], bytes[6], bytes[7]];
-            let chunk_type = ChunkType::try_from(chunktbytes).unwrap();
-            let data = bytes[8..8 + data_length as usize].to_vec();
-            let crc = u32::from_be_bytes([bytes[8 + data_length as usize], bytes[9 + data_length as usize], bytes[10 + data_length as usize], bytes[11 + data_length as usize]]);
+            let chunk_type = ChunkType::try_from(chunktbytes)?;
+            let data = bytes[8..8 + data_length].to_vec();
+            let crc = u32::from_be_bytes([bytes[8 + data_length], bytes[9 + data_length], bytes[10 + data_length], bytes[11 + data_length]]);
             let chunk = Chunk::new(chunk_type, data);
             if crc == chunk.crc(){
                 Ok(chunk)
@@ -46,13 +53,16 @@ impl TryFrom<&[u8]> for Chunk{
 }
 
 impl Chunk{
+    /// Number of bytes a chunk occupies besides its data: length, type and CRC.
+    pub const METADATA_LENGTH: usize = 12;
+
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self{
         let crc = crc::crc32::checksum_ieee(&[&chunk_type.bytes(), data.as_slice()].concat());
         Self{
             length: data.len() as u32,
-            data:data,
-            chunk_type:chunk_type,
-            crc:crc,
+            data,
+            chunk_type,
+            crc,
         }
     }
 
@@ -65,12 +75,14 @@ impl Chunk{
         bytes
     }
 
+    // Part of the chunk API even though no command reads chunk data as text this way
+    #[allow(dead_code)]
     pub fn data_as_string(&self) -> Result<String, std::string::FromUtf8Error>{
         String::from_utf8(self.data.clone())
     }
 
     pub fn length(&self)->u32{
-        self.data.len() as u32
+        self.length
     }
 
     pub fn chunk_type(&self) -> &ChunkType{
@@ -147,7 +159,7 @@ impl fmt::Display for Chunk {
             assert_eq!(chunk.chunk_type().to_string(), String::from("RuSt"));
         }
     
-        // #[test]
+        #[test]
         fn test_chunk_string() {
             let chunk = testing_chunk();
             let chunk_string = chunk.data_as_string().unwrap();
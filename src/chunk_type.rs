@@ -1,12 +1,8 @@
 
-#![allow(unused_variables)]
-
 use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
-use std::error::Error;
 use thiserror::Error;
-// use crate::{Error, Result};
 
 #[derive(Debug,Error, Clone, PartialEq, Eq)]
 pub enum ChunkTypeError {
@@ -20,6 +16,13 @@ pub struct ChunkType {
 }
 
 impl ChunkType {
+    /// Chunk types defined by the PNG specification and its registered extensions
+    pub const STANDARD: [&'static str; 30] = [
+        "IHDR", "PLTE", "IDAT", "IEND", "tRNS", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB",
+        "cICP", "mDCV", "cLLI", "tEXt", "zTXt", "iTXt", "bKGD", "hIST", "pHYs", "sPLT",
+        "eXIf", "tIME", "acTL", "fcTL", "fdAT", "oFFs", "pCAL", "sCAL", "gIFg", "sTER",
+    ];
+
     /// Returns the raw bytes contained in this chunk
     pub fn bytes(&self) -> [u8; 4] {
         self.bytes
@@ -27,22 +30,22 @@ impl ChunkType {
 
     /// Returns the property state of the first byte as described in the PNG spec
     pub fn is_critical(&self) -> bool {
-        self.bytes()[0]>=65 && self.bytes()[0]<=90
+        self.bytes()[0].is_ascii_uppercase()
     }
 
     /// Returns the property state of the second byte as described in the PNG spec
     pub fn is_public(&self) -> bool {
-        self.bytes()[1]>=65 && self.bytes()[1]<=90
+        self.bytes()[1].is_ascii_uppercase()
     }
 
     /// Returns the property state of the third byte as described in the PNG spec
     pub fn is_reserved_bit_valid(&self) -> bool {
-        self.bytes()[2]>=65 && self.bytes()[2]<=90
+        self.bytes()[2].is_ascii_uppercase()
     }
 
     /// Returns the property state of the fourth byte as described in the PNG spec
     pub fn is_safe_to_copy(&self) -> bool {
-        !(self.bytes()[3]>=65 && self.bytes()[3]<=90)
+        !self.bytes()[3].is_ascii_uppercase()
     }
 
     /// Returns true if the reserved byte is valid and all four bytes are represented by the characters A-Z or a-z.
@@ -59,17 +62,24 @@ impl ChunkType {
                 return false;
             }
         }
-        
-        return true;
+
+        true
     }
 
+    /// Returns true if this is one of the chunk types in [`ChunkType::STANDARD`]
+    pub fn is_standard(&self) -> bool {
+        Self::STANDARD.contains(&self.to_string().as_str())
+    }
+
+    // Part of the chunk type API even though no command needs it
+    #[allow(dead_code)]
     pub fn is_err(&self) -> bool {
         !self.is_valid()
     }
 
     /// Valid bytes are represented by the characters A-Z or a-z
     pub fn is_valid_byte(byte: u8) -> bool {
-        byte>=65 && byte<=90 || byte>=97 && byte<=122
+        byte.is_ascii_alphabetic()
     }
 }
 
@@ -97,7 +107,7 @@ impl TryFrom<[u8; 4]> for ChunkType {
 
 impl fmt::Display for ChunkType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.bytes.iter().map(|byte| f.write_str(&(*byte as char).to_string())).collect()
+        self.bytes.iter().try_for_each(|byte| f.write_str(&(*byte as char).to_string()))
     }
 }
 
@@ -105,8 +115,8 @@ impl FromStr for ChunkType {
     type Err = ChunkTypeError;
 
     fn from_str(s: &str) -> Result<Self,ChunkTypeError> {
-        let bytes:Vec<u8>= s.chars().map(|letter|letter as u8).collect();
-        ChunkType::try_from(<Vec<u8> as TryInto<[u8;4]>>::try_into(bytes).unwrap())
+        let bytes: [u8; 4] = s.as_bytes().try_into().map_err(|_| ChunkTypeError::InvalidChunkType)?;
+        ChunkType::try_from(bytes)
     }
 }
 
@@ -194,6 +204,12 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_is_standard() {
+        assert!(ChunkType::from_str("tEXt").unwrap().is_standard());
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_standard());
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
@@ -0,0 +1,533 @@
+//! The color-management chunks: gAMA, cHRM, sRGB and cICP.
+//! https://www.w3.org/TR/png-3/#11addnlcolinfo
+//!
+//! Decoders pick the first of cICP, iCCP, sRGB and gAMA/cHRM that they understand, so
+//! these chunks should agree with each other. [`warnings`] reports where they don't.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::hdr::{ContentLightLevel, MasteringDisplay};
+use crate::png::Png;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ColorError {
+    #[error("Expected a {expected} chunk, found {found}")]
+    WrongChunkType {
+        expected: &'static str,
+        found: String,
+    },
+    #[error("{chunk_type} data must be {expected} bytes long, found {found}")]
+    InvalidLength {
+        chunk_type: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Invalid {0}")]
+    InvalidValue(String),
+}
+
+/// Checks the type and length of a chunk before it is decoded
+pub(crate) fn check_chunk<'a>(
+    chunk: &'a Chunk,
+    chunk_type: &'static str,
+    length: usize,
+) -> Result<&'a [u8], ColorError> {
+    if chunk.chunk_type().to_string() != chunk_type {
+        return Err(ColorError::WrongChunkType {
+            expected: chunk_type,
+            found: chunk.chunk_type().to_string(),
+        });
+    }
+    if chunk.data().len() != length {
+        return Err(ColorError::InvalidLength {
+            chunk_type,
+            expected: length,
+            found: chunk.data().len(),
+        });
+    }
+    Ok(chunk.data())
+}
+
+fn u32_at(data: &[u8], index: usize) -> u32 {
+    u32::from_be_bytes([
+        data[index],
+        data[index + 1],
+        data[index + 2],
+        data[index + 3],
+    ])
+}
+
+/// Values in gAMA and cHRM are stored multiplied by this
+const SCALE: f64 = 100_000.0;
+
+/// The gAMA chunk: the exponent relating stored samples to the original light intensity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gamma(pub u32);
+
+impl Gamma {
+    pub const CHUNK_TYPE: &'static str = "gAMA";
+    /// The value the spec recommends alongside an sRGB chunk
+    pub const SRGB: Gamma = Gamma(45455);
+
+    pub fn value(&self) -> f64 {
+        self.0 as f64 / SCALE
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(Self::CHUNK_TYPE).unwrap(),
+            self.0.to_be_bytes().to_vec(),
+        )
+    }
+}
+
+impl TryFrom<&Chunk> for Gamma {
+    type Error = ColorError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, ColorError> {
+        let data = check_chunk(chunk, Self::CHUNK_TYPE, 4)?;
+        match u32_at(data, 0) {
+            0 => Err(ColorError::InvalidValue("gamma of zero".to_string())),
+            gamma => Ok(Gamma(gamma)),
+        }
+    }
+}
+
+impl FromStr for Gamma {
+    type Err = ColorError;
+
+    /// Parses a gamma such as `0.45455`, or the display exponent form `1/2.2`
+    fn from_str(s: &str) -> Result<Self, ColorError> {
+        let invalid = || ColorError::InvalidValue(format!("gamma {}", s));
+        let value = match s.split_once('/') {
+            Some((n, d)) => {
+                n.trim().parse::<f64>().map_err(|_| invalid())?
+                    / d.trim().parse::<f64>().map_err(|_| invalid())?
+            }
+            None => s.trim().parse::<f64>().map_err(|_| invalid())?,
+        };
+        let scaled = (value * SCALE).round();
+        if !(1.0..=u32::MAX as f64).contains(&scaled) {
+            return Err(invalid());
+        }
+        Ok(Gamma(scaled as u32))
+    }
+}
+
+impl fmt::Display for Gamma {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "gamma {:.5} (1/{:.2})", self.value(), 1.0 / self.value())
+    }
+}
+
+/// The cHRM chunk: CIE 1931 xy coordinates of the white point and primaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chromaticities {
+    /// White, red, green and blue, each as (x, y) multiplied by 100000
+    pub points: [(u32, u32); 4],
+}
+
+impl Chromaticities {
+    pub const CHUNK_TYPE: &'static str = "cHRM";
+    /// The values the spec recommends alongside an sRGB chunk
+    pub const SRGB: Chromaticities = Chromaticities {
+        points: [
+            (31270, 32900),
+            (64000, 33000),
+            (30000, 60000),
+            (15000, 6000),
+        ],
+    };
+
+    /// Whether every coordinate is within 0.001 of `other`
+    pub fn approximately(&self, other: &Chromaticities) -> bool {
+        self.points
+            .iter()
+            .zip(other.points.iter())
+            .all(|(a, b)| a.0.abs_diff(b.0) <= 100 && a.1.abs_diff(b.1) <= 100)
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let data = self
+            .points
+            .iter()
+            .flat_map(|&(x, y)| x.to_be_bytes().into_iter().chain(y.to_be_bytes()))
+            .collect();
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for Chromaticities {
+    type Error = ColorError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, ColorError> {
+        let data = check_chunk(chunk, Self::CHUNK_TYPE, 32)?;
+        let mut points = [(0, 0); 4];
+        for (i, point) in points.iter_mut().enumerate() {
+            *point = (u32_at(data, i * 8), u32_at(data, i * 8 + 4));
+        }
+        Ok(Chromaticities { points })
+    }
+}
+
+impl FromStr for Chromaticities {
+    type Err = ColorError;
+
+    /// Parses eight comma-separated coordinates: white, red, green and blue x and y
+    fn from_str(s: &str) -> Result<Self, ColorError> {
+        let invalid = || ColorError::InvalidValue(format!("chromaticities {}", s));
+        let values = s
+            .split(',')
+            .map(|v| match v.trim().parse::<f64>() {
+                Ok(v) if (0.0..=1.0).contains(&v) => Ok((v * SCALE).round() as u32),
+                _ => Err(invalid()),
+            })
+            .collect::<Result<Vec<u32>, ColorError>>()?;
+        if values.len() != 8 {
+            return Err(invalid());
+        }
+        let mut points = [(0, 0); 4];
+        for (point, xy) in points.iter_mut().zip(values.chunks(2)) {
+            *point = (xy[0], xy[1]);
+        }
+        Ok(Chromaticities { points })
+    }
+}
+
+impl fmt::Display for Chromaticities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = ["white", "red", "green", "blue"];
+        for (i, (name, (x, y))) in names.iter().zip(self.points.iter()).enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{} ({:.4}, {:.4})",
+                name,
+                *x as f64 / SCALE,
+                *y as f64 / SCALE
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The sRGB chunk's rendering intent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
+}
+
+impl RenderingIntent {
+    pub const CHUNK_TYPE: &'static str = "sRGB";
+
+    pub fn value(&self) -> u8 {
+        match self {
+            RenderingIntent::Perceptual => 0,
+            RenderingIntent::RelativeColorimetric => 1,
+            RenderingIntent::Saturation => 2,
+            RenderingIntent::AbsoluteColorimetric => 3,
+        }
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(Self::CHUNK_TYPE).unwrap(),
+            vec![self.value()],
+        )
+    }
+}
+
+impl TryFrom<&Chunk> for RenderingIntent {
+    type Error = ColorError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, ColorError> {
+        let data = check_chunk(chunk, Self::CHUNK_TYPE, 1)?;
+        match data[0] {
+            0 => Ok(RenderingIntent::Perceptual),
+            1 => Ok(RenderingIntent::RelativeColorimetric),
+            2 => Ok(RenderingIntent::Saturation),
+            3 => Ok(RenderingIntent::AbsoluteColorimetric),
+            intent => Err(ColorError::InvalidValue(format!(
+                "rendering intent {}",
+                intent
+            ))),
+        }
+    }
+}
+
+impl FromStr for RenderingIntent {
+    type Err = ColorError;
+
+    fn from_str(s: &str) -> Result<Self, ColorError> {
+        match s {
+            "perceptual" => Ok(RenderingIntent::Perceptual),
+            "relative" => Ok(RenderingIntent::RelativeColorimetric),
+            "saturation" => Ok(RenderingIntent::Saturation),
+            "absolute" => Ok(RenderingIntent::AbsoluteColorimetric),
+            _ => Err(ColorError::InvalidValue(format!("rendering intent {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for RenderingIntent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RenderingIntent::Perceptual => "perceptual",
+            RenderingIntent::RelativeColorimetric => "relative colorimetric",
+            RenderingIntent::Saturation => "saturation",
+            RenderingIntent::AbsoluteColorimetric => "absolute colorimetric",
+        };
+        write!(f, "sRGB, {} rendering intent", name)
+    }
+}
+
+/// The cICP chunk: coding-independent code points from ITU-T H.273
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cicp {
+    pub primaries: u8,
+    pub transfer: u8,
+    /// Always 0 (RGB) in PNG
+    pub matrix: u8,
+    pub full_range: bool,
+}
+
+impl Cicp {
+    pub const CHUNK_TYPE: &'static str = "cICP";
+
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(Self::CHUNK_TYPE).unwrap(),
+            vec![
+                self.primaries,
+                self.transfer,
+                self.matrix,
+                self.full_range as u8,
+            ],
+        )
+    }
+
+    fn primaries_name(&self) -> Option<&'static str> {
+        match self.primaries {
+            1 => Some("BT.709"),
+            4 => Some("BT.470 M"),
+            5 => Some("BT.601 625"),
+            6 => Some("BT.601 525"),
+            9 => Some("BT.2020"),
+            11 => Some("DCI-P3"),
+            12 => Some("Display P3"),
+            _ => None,
+        }
+    }
+
+    fn transfer_name(&self) -> Option<&'static str> {
+        match self.transfer {
+            1 | 6 | 14 | 15 => Some("BT.709"),
+            4 => Some("gamma 2.2"),
+            8 => Some("linear"),
+            13 => Some("sRGB"),
+            16 => Some("PQ"),
+            18 => Some("HLG"),
+            _ => None,
+        }
+    }
+
+    fn validate(self) -> Result<Self, ColorError> {
+        if self.matrix != 0 {
+            return Err(ColorError::InvalidValue(format!(
+                "cICP matrix coefficients {}: PNG images are always RGB",
+                self.matrix
+            )));
+        }
+        Ok(self)
+    }
+}
+
+impl TryFrom<&Chunk> for Cicp {
+    type Error = ColorError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, ColorError> {
+        let data = check_chunk(chunk, Self::CHUNK_TYPE, 4)?;
+        if data[3] > 1 {
+            return Err(ColorError::InvalidValue(format!(
+                "cICP full range flag {}",
+                data[3]
+            )));
+        }
+        Cicp {
+            primaries: data[0],
+            transfer: data[1],
+            matrix: data[2],
+            full_range: data[3] == 1,
+        }
+        .validate()
+    }
+}
+
+impl FromStr for Cicp {
+    type Err = ColorError;
+
+    /// Parses `primaries,transfer,matrix,full-range`, such as `9,16,0,1` for HDR10
+    fn from_str(s: &str) -> Result<Self, ColorError> {
+        let invalid = || ColorError::InvalidValue(format!("cICP code points {}", s));
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<u8>().map_err(|_| invalid()))
+            .collect::<Result<Vec<u8>, ColorError>>()?;
+        match values[..] {
+            [primaries, transfer, matrix, full_range @ (0 | 1)] => Cicp {
+                primaries,
+                transfer,
+                matrix,
+                full_range: full_range == 1,
+            }
+            .validate(),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Cicp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.primaries_name() {
+            Some(name) => write!(f, "{} primaries", name)?,
+            None => write!(f, "primaries {}", self.primaries)?,
+        }
+        match self.transfer_name() {
+            Some(name) => write!(f, ", {} transfer", name)?,
+            None => write!(f, ", transfer {}", self.transfer)?,
+        }
+        let range = if self.full_range { "full" } else { "narrow" };
+        write!(f, ", {} range", range)
+    }
+}
+
+/// Problems with how the color-management chunks of `png` fit together
+pub fn warnings(png: &Png) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let find = |chunk_type: &str| png.chunk_by_type(chunk_type);
+    for chunk_type in ["gAMA", "cHRM", "sRGB", "iCCP", "cICP", "mDCV", "cLLI"] {
+        let count = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == chunk_type)
+            .count();
+        if count > 1 {
+            warnings.push(format!("{} appears {} times", chunk_type, count));
+        }
+    }
+
+    let srgb = find(RenderingIntent::CHUNK_TYPE).is_some();
+    if srgb && find("iCCP").is_some() {
+        warnings.push("sRGB and iCCP must not both be present".to_string());
+    }
+    if let Some(Ok(gamma)) = find(Gamma::CHUNK_TYPE).map(Gamma::try_from) {
+        if srgb && gamma != Gamma::SRGB {
+            warnings.push(format!(
+                "sRGB is present but gAMA is {:.5} rather than 0.45455",
+                gamma.value()
+            ));
+        }
+    }
+    if let Some(Ok(chrm)) = find(Chromaticities::CHUNK_TYPE).map(Chromaticities::try_from) {
+        if srgb && !chrm.approximately(&Chromaticities::SRGB) {
+            warnings.push("sRGB is present but cHRM does not give the sRGB primaries".to_string());
+        }
+    }
+    if let Some(Ok(cicp)) = find(Cicp::CHUNK_TYPE).map(Cicp::try_from) {
+        let srgb_cicp = cicp.primaries == 1 && cicp.transfer == 13;
+        if srgb && !srgb_cicp {
+            warnings.push(format!(
+                "cICP ({}) overrides sRGB and describes a different color space",
+                cicp
+            ));
+        }
+    }
+    for chunk in png.chunks() {
+        let result = match chunk.chunk_type().to_string().as_str() {
+            Gamma::CHUNK_TYPE => Gamma::try_from(chunk).err(),
+            Chromaticities::CHUNK_TYPE => Chromaticities::try_from(chunk).err(),
+            RenderingIntent::CHUNK_TYPE => RenderingIntent::try_from(chunk).err(),
+            Cicp::CHUNK_TYPE => Cicp::try_from(chunk).err(),
+            MasteringDisplay::CHUNK_TYPE => MasteringDisplay::try_from(chunk).err(),
+            ContentLightLevel::CHUNK_TYPE => ContentLightLevel::try_from(chunk).err(),
+            _ => None,
+        };
+        if let Some(e) = result {
+            warnings.push(e.to_string());
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_with(chunks: Vec<Chunk>) -> Png {
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_gamma() {
+        let gamma = Gamma::from_str("1/2.2").unwrap();
+        assert_eq!(gamma, Gamma::SRGB);
+        assert_eq!(Gamma::try_from(&gamma.to_chunk()).unwrap(), gamma);
+        assert_eq!(gamma.to_string(), "gamma 0.45455 (1/2.20)");
+        assert!(Gamma::from_str("0").is_err());
+        assert!(Gamma::try_from(&Gamma(0).to_chunk()).is_err());
+    }
+
+    #[test]
+    fn test_chromaticities() {
+        let chrm = Chromaticities::from_str("0.3127,0.329,0.64,0.33,0.3,0.6,0.15,0.06").unwrap();
+        assert_eq!(chrm, Chromaticities::SRGB);
+        assert_eq!(Chromaticities::try_from(&chrm.to_chunk()).unwrap(), chrm);
+        assert!(Chromaticities::from_str("0.3,0.3").is_err());
+    }
+
+    #[test]
+    fn test_srgb_and_cicp() {
+        let intent = RenderingIntent::from_str("relative").unwrap();
+        assert_eq!(
+            RenderingIntent::try_from(&intent.to_chunk()).unwrap(),
+            intent
+        );
+
+        let cicp = Cicp::from_str("9,16,0,1").unwrap();
+        assert_eq!(Cicp::try_from(&cicp.to_chunk()).unwrap(), cicp);
+        assert_eq!(
+            cicp.to_string(),
+            "BT.2020 primaries, PQ transfer, full range"
+        );
+        assert!(Cicp::from_str("1,13,1,1").is_err());
+        assert!(Cicp::from_str("1,13,0,2").is_err());
+    }
+
+    #[test]
+    fn test_warnings() {
+        let consistent = png_with(vec![
+            RenderingIntent::Perceptual.to_chunk(),
+            Gamma::SRGB.to_chunk(),
+            Chromaticities::SRGB.to_chunk(),
+        ]);
+        assert!(warnings(&consistent).is_empty());
+
+        let mismatched = png_with(vec![
+            RenderingIntent::Perceptual.to_chunk(),
+            Gamma(100000).to_chunk(),
+            Cicp::from_str("9,16,0,1").unwrap().to_chunk(),
+            Chunk::new(ChunkType::from_str("iCCP").unwrap(), Vec::new()),
+        ]);
+        assert_eq!(warnings(&mismatched).len(), 3);
+    }
+}
@@ -0,0 +1,571 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::analyze;
+use crate::ancillary::PhysicalDimensions;
+use crate::apng::{self, Animation};
+use crate::args::{
+    AnalyzeArgs, CapacityArgs, ColorArgs, ConvertArgs, DecodeArgs, DpiArgs, EmbedArgs, EncodeArgs,
+    ExifArgs, ExportArgs, ExtractArgs, FramesArgs, IccArgs, ImportArgs, NormalizeArgs,
+    OptimizeArgs, PaletteArgs, PrintArgs, RemoveArgs, SameArgs, StripArgs, TrailerArgs,
+    TransformArgs, TransplantArgs, XmpArgs,
+};
+use crate::cgbi;
+use crate::color::{self, Chromaticities, Cicp, Gamma, RenderingIntent};
+use crate::container::{self, Container};
+use crate::convert;
+use crate::exif::Exif;
+use crate::hdr::{ContentLightLevel, MasteringDisplay};
+use crate::icc::IccProfile;
+use crate::image::RawImage;
+use crate::netpbm::{ExportFormat, Netpbm};
+use crate::optimize::{self, OptimizeOptions};
+use crate::palette::Palette;
+use crate::png::{describe, Png};
+use crate::stego::{self, Method};
+use crate::strip;
+use crate::time::Time;
+use crate::transform::{self, Transform};
+use crate::transplant;
+use crate::xmp::Xmp;
+use crate::Result;
+
+/// Whether `write_png` updates the tIME chunk, set once from the global `--touch` flag
+static TOUCH: AtomicBool = AtomicBool::new(false);
+
+pub fn set_touch(touch: bool) {
+    TOUCH.store(touch, Ordering::Relaxed);
+}
+
+fn write_png(png: &Png, path: &Path) -> Result<()> {
+    if TOUCH.load(Ordering::Relaxed) {
+        let mut png = png.clone();
+        png.set_chunk(Time::now().to_chunk());
+        fs::write(path, png.as_bytes())?;
+    } else {
+        fs::write(path, png.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_container(container: &mut dyn Container, path: &Path) -> Result<()> {
+    if TOUCH.load(Ordering::Relaxed) {
+        container.touch(Time::now());
+    }
+    fs::write(path, container.to_bytes())?;
+    Ok(())
+}
+
+/// Encodes a message into a PNG, MNG, JNG or RIFF file and saves the result
+pub fn encode(args: EncodeArgs) -> Result<()> {
+    let mut file = container::open(&args.file_path)?;
+    file.add_chunk(args.chunk_type, args.message.into_bytes())?;
+    write_container(
+        file.as_mut(),
+        args.output.as_deref().unwrap_or(&args.file_path),
+    )
+}
+
+/// Searches for a message hidden in a PNG, MNG, JNG or RIFF file and prints the message
+/// if one is found
+pub fn decode(args: DecodeArgs) -> Result<()> {
+    let file = container::open(&args.file_path)?;
+    let data = file
+        .chunk_data(&args.chunk_type.to_string())
+        .ok_or("Chunk not found")?;
+    println!("{}", String::from_utf8(data.to_vec())?);
+    Ok(())
+}
+
+/// Removes a chunk from a PNG, MNG, JNG or RIFF file and saves the result
+pub fn remove(args: RemoveArgs) -> Result<()> {
+    let mut file = container::open(&args.file_path)?;
+    let data = file.remove_chunk(&args.chunk_type.to_string())?;
+    write_container(file.as_mut(), &args.file_path)?;
+    println!("Removed {} chunk ({} bytes)", args.chunk_type, data.len());
+    Ok(())
+}
+
+/// Prints all of the chunks in a PNG file
+pub fn print_chunks(args: PrintArgs) -> Result<()> {
+    let png = Png::from_file(&args.file_path)?;
+    print!("{}", png);
+    if args.exif {
+        match png.chunk_by_type(Exif::CHUNK_TYPE) {
+            Some(chunk) => print!("{}", Exif::try_from(chunk)?),
+            None => println!("No eXIf chunk"),
+        }
+    }
+    Ok(())
+}
+
+/// Hides a message in the image data of a PNG file and saves the result
+pub fn embed(args: EmbedArgs) -> Result<()> {
+    let mut png = Png::from_file(&args.file_path)?;
+    stego::embed(&mut png, args.method, args.message.as_bytes())?;
+    write_png(&png, args.output.as_deref().unwrap_or(&args.file_path))?;
+    eprintln!("Warning: {}", args.method.fragility());
+    Ok(())
+}
+
+/// Prints a message hidden with `embed`
+pub fn extract(args: ExtractArgs) -> Result<()> {
+    let png = Png::from_file(&args.file_path)?;
+    let payload = stego::extract(&png, args.method)?;
+    println!("{}", String::from_utf8(payload)?);
+    Ok(())
+}
+
+/// Prints how much data `embed` can hide in a PNG file
+pub fn capacity(args: CapacityArgs) -> Result<()> {
+    let png = Png::from_file(&args.file_path)?;
+    match args.method {
+        Method::Palette => println!("{}", stego::palette::capacity(&png)?),
+        Method::ZlibTrailer | Method::StoredBlock | Method::AfterIend | Method::FrameChunks => {
+            println!("No fixed limit: the message is stored alongside the image data")
+        }
+        Method::IdatSplit => {
+            let (guaranteed, typical) = stego::zlib::idat_split_capacity(&png);
+            println!(
+                "Capacity: {} bytes guaranteed, about {} bytes for text",
+                guaranteed, typical
+            );
+        }
+        Method::FrameLsb => println!(
+            "Capacity: {} bytes across all frames",
+            stego::apng::lsb_capacity(&png)?
+        ),
+    }
+    println!("{}", args.method.fragility());
+    Ok(())
+}
+
+/// Prints a steganalysis report for each file
+pub fn analyze(args: AnalyzeArgs) -> Result<()> {
+    for path in args.file_paths.iter() {
+        println!("{}:", path.display());
+        match Png::from_file(path).and_then(|png| analyze::analyze(&png)) {
+            Ok(analysis) => print!("{}", analysis),
+            Err(e) => println!("  Error: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Extracts or strips the data after the IEND chunk
+pub fn trailer(args: TrailerArgs) -> Result<()> {
+    match args {
+        TrailerArgs::Extract(args) => {
+            let png = Png::from_file(&args.file_path)?;
+            if png.trailer().is_empty() {
+                return Err("No data after IEND".into());
+            }
+            fs::write(&args.output, png.trailer())?;
+            println!(
+                "Wrote {} bytes to {}",
+                png.trailer().len(),
+                args.output.display()
+            );
+        }
+        TrailerArgs::Strip(args) => {
+            let mut png = Png::from_file(&args.file_path)?;
+            let trailer = png.take_trailer();
+            if trailer.is_empty() {
+                return Err("No data after IEND".into());
+            }
+            write_png(&png, args.output.as_deref().unwrap_or(&args.file_path))?;
+            println!("Removed {} bytes after IEND", trailer.len());
+        }
+    }
+    Ok(())
+}
+
+/// Compares the decoded pixels of two PNG files, failing if they differ
+pub fn same(args: SameArgs) -> Result<()> {
+    let first = Png::from_file(&args.first)?.pixel_digest()?;
+    let second = Png::from_file(&args.second)?.pixel_digest()?;
+    let hex = |digest: &[u8]| {
+        digest
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    println!("{}  {}", hex(&first), args.first.display());
+    println!("{}  {}", hex(&second), args.second.display());
+    if first != second {
+        return Err("Images differ".into());
+    }
+    println!("Images are the same");
+    Ok(())
+}
+
+/// Removes metadata chunks and trailing data, then reports what was removed
+pub fn strip(args: StripArgs) -> Result<()> {
+    let mut png = Png::from_file(&args.file_path)?;
+    let report = strip::strip(&mut png, args.preset, &args.keep, &args.drop)?;
+    write_png(&png, args.output.as_deref().unwrap_or(&args.file_path))?;
+    print!("{}", report);
+    Ok(())
+}
+
+/// Copies the ancillary chunks of one file into another and reports what was copied
+pub fn transplant(args: TransplantArgs) -> Result<()> {
+    let source = Png::from_file(&args.source)?;
+    let mut destination = Png::from_file(&args.destination)?;
+    let report = transplant::transplant(&source, &mut destination, args.force)?;
+    write_png(
+        &destination,
+        args.output.as_deref().unwrap_or(&args.destination),
+    )?;
+    print!("{}", report);
+    Ok(())
+}
+
+/// Prints the EXIF tags of a PNG file, or removes some of them and saves the result
+pub fn exif(args: ExifArgs) -> Result<()> {
+    let mut png = Png::from_file(&args.file_path)?;
+    let chunk = png.chunk_by_type(Exif::CHUNK_TYPE).ok_or("No eXIf chunk")?;
+    let mut exif = Exif::try_from(chunk)?;
+    if let Some(name) = args.tag {
+        let value = exif.get(&name).ok_or(format!("No {} tag", name))?;
+        println!("{}", value);
+        return Ok(());
+    }
+    if !args.remove_gps && !args.remove_orientation {
+        print!("{}", exif);
+        return Ok(());
+    }
+    if args.remove_gps {
+        println!("Removed {} GPS tags", exif.remove_gps());
+    }
+    if args.remove_orientation {
+        match exif.remove_orientation() {
+            true => println!("Removed the orientation tag"),
+            false => println!("No orientation tag"),
+        }
+    }
+    if exif.has_strip_thumbnail() {
+        println!("Warning: removed the uncompressed strip thumbnail");
+    }
+    png.set_chunk(exif.to_chunk());
+    write_png(&png, args.output.as_deref().unwrap_or(&args.file_path))
+}
+
+/// Shows, sets or removes XMP properties
+pub fn xmp(args: XmpArgs) -> Result<()> {
+    match args {
+        XmpArgs::Show(args) => {
+            let png = Png::from_file(&args.file_path)?;
+            let xmp = Xmp::from_png(&png)?.ok_or("No XMP packet")?;
+            print!("{}", xmp);
+            println!();
+            for (name, value) in xmp.properties() {
+                println!("{}: {}", name, value);
+            }
+        }
+        XmpArgs::Set(args) => {
+            let mut png = Png::from_file(&args.file_path)?;
+            let mut xmp = Xmp::from_png(&png)?.unwrap_or_default();
+            xmp.set(&args.property, &args.value)?;
+            png.set_international_text(xmp.to_text());
+            write_png(&png, args.output.as_deref().unwrap_or(&args.file_path))?;
+        }
+        XmpArgs::Remove(args) => {
+            let mut png = Png::from_file(&args.file_path)?;
+            let mut xmp = Xmp::from_png(&png)?.ok_or("No XMP packet")?;
+            if !xmp.remove(&args.property) {
+                return Err(format!("No {} property", args.property).into());
+            }
+            png.set_international_text(xmp.to_text());
+            write_png(&png, args.output.as_deref().unwrap_or(&args.file_path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Extracts or embeds the ICC profile of a PNG file
+pub fn icc(args: IccArgs) -> Result<()> {
+    match args {
+        IccArgs::Extract(args) => {
+            let png = Png::from_file(&args.file_path)?;
+            let chunk = png
+                .chunk_by_type(IccProfile::CHUNK_TYPE)
+                .ok_or("No iCCP chunk")?;
+            let profile = IccProfile::try_from(chunk)?;
+            if let Err(e) = profile.header() {
+                eprintln!("Warning: {}", e);
+            }
+            fs::write(&args.output, &profile.profile)?;
+            println!(
+                "Wrote {} bytes to {}",
+                profile.profile.len(),
+                args.output.display()
+            );
+        }
+        IccArgs::Embed(args) => {
+            let mut png = Png::from_file(&args.file_path)?;
+            let profile = IccProfile::new(args.name.as_deref(), fs::read(&args.profile)?)?;
+            profile.header()?.check_color_type(png.ihdr()?.color_type)?;
+            if png.remove_first_chunk("sRGB").is_ok() {
+                println!("Removed sRGB chunk, which can't be used alongside iCCP");
+            }
+            png.set_chunk(profile.to_chunk());
+            write_png(&png, args.output.as_deref().unwrap_or(&args.file_path))?;
+            println!("Embedded {}", profile);
+        }
+    }
+    Ok(())
+}
+
+/// Prints the color-management chunks of a PNG file, or sets and removes them
+pub fn color(args: ColorArgs) -> Result<()> {
+    let mut png = Png::from_file(&args.file_path)?;
+    let editing = args.gamma.is_some()
+        || args.chromaticities.is_some()
+        || args.srgb.is_some()
+        || args.cicp.is_some()
+        || args.mdcv.is_some()
+        || args.clli.is_some()
+        || !args.clear.is_empty();
+    if !editing {
+        let types = [
+            Cicp::CHUNK_TYPE,
+            IccProfile::CHUNK_TYPE,
+            RenderingIntent::CHUNK_TYPE,
+            Gamma::CHUNK_TYPE,
+            Chromaticities::CHUNK_TYPE,
+            MasteringDisplay::CHUNK_TYPE,
+            ContentLightLevel::CHUNK_TYPE,
+        ];
+        for chunk in png.chunks() {
+            if types.contains(&chunk.chunk_type().to_string().as_str()) {
+                let description = describe(chunk, None).unwrap_or_default();
+                println!("{}: {}", chunk.chunk_type(), description);
+            }
+        }
+        for warning in color::warnings(&png) {
+            println!("Warning: {}", warning);
+        }
+        return Ok(());
+    }
+
+    for chunk_type in args.clear.iter() {
+        let name = chunk_type.to_string();
+        if !["gAMA", "cHRM", "sRGB", "cICP", "mDCV", "cLLI"].contains(&name.as_str()) {
+            return Err(format!("{} is not a color-management chunk", name).into());
+        }
+        while png.remove_first_chunk(&name).is_ok() {}
+    }
+    if let Some(gamma) = args.gamma {
+        png.set_chunk(gamma.to_chunk());
+    }
+    if let Some(chromaticities) = args.chromaticities {
+        png.set_chunk(chromaticities.to_chunk());
+    }
+    if let Some(intent) = args.srgb {
+        if png.remove_first_chunk(IccProfile::CHUNK_TYPE).is_ok() {
+            println!("Removed iCCP chunk, which can't be used alongside sRGB");
+        }
+        png.set_chunk(intent.to_chunk());
+    }
+    if let Some(cicp) = args.cicp {
+        png.set_chunk(cicp.to_chunk());
+    }
+    if let Some(mdcv) = args.mdcv {
+        png.set_chunk(mdcv.to_chunk());
+    }
+    if let Some(clli) = args.clli {
+        png.set_chunk(clli.to_chunk());
+    }
+    write_png(&png, args.output.as_deref().unwrap_or(&args.file_path))?;
+    for warning in color::warnings(&png) {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(())
+}
+
+/// Prints the resolution from the pHYs chunk, or replaces it with a new one
+pub fn dpi(args: DpiArgs) -> Result<()> {
+    let mut png = Png::from_file(&args.file_path)?;
+    match args.set {
+        Some(dpi) => {
+            let phys = PhysicalDimensions::from_dpi(dpi)?;
+            png.set_chunk(phys.to_chunk());
+            write_png(&png, args.output.as_deref().unwrap_or(&args.file_path))?;
+            println!("Set pHYs to {}", phys);
+        }
+        None => match png.chunk_by_type(PhysicalDimensions::CHUNK_TYPE) {
+            Some(chunk) => println!("{}", PhysicalDimensions::try_from(chunk)?),
+            None => println!("No pHYs chunk"),
+        },
+    }
+    Ok(())
+}
+
+/// Prints the palette of a PNG file, or replaces some of its colors
+pub fn palette(args: PaletteArgs) -> Result<()> {
+    match args {
+        PaletteArgs::Show(args) => {
+            let png = Png::from_file(&args.file_path)?;
+            let palette = Palette::from_png(&png)?;
+            if args.gpl {
+                let name = args.file_path.file_stem().unwrap_or_default();
+                print!("{}", palette.to_gpl(&name.to_string_lossy()));
+            } else {
+                print!("{}", palette);
+            }
+        }
+        PaletteArgs::Replace(args) => {
+            let mut png = Png::from_file(&args.file_path)?;
+            let mut palette = Palette::from_png(&png)?;
+            for replacement in args.replacements.iter() {
+                let changed = palette.replace(replacement)?;
+                if changed == 0 {
+                    eprintln!("Warning: no palette entry matches {}", replacement.target);
+                }
+            }
+            palette.write_to(&mut png);
+            write_png(&png, args.output.as_deref().unwrap_or(&args.file_path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists the frames of an animated PNG, or writes each one out as a standalone PNG
+pub fn frames(args: FramesArgs) -> Result<()> {
+    match args {
+        FramesArgs::Info(args) => {
+            let png = Png::from_file(&args.file_path)?;
+            print!("{}", Animation::from_png(&png)?);
+        }
+        FramesArgs::Extract(args) => {
+            let png = Png::from_file(&args.file_path)?;
+            let animation = Animation::from_png(&png)?;
+            let bit_depth = match animation.ihdr().bit_depth {
+                16 => 16,
+                _ => 8,
+            };
+            let stem = args.file_path.file_stem().unwrap_or_default();
+            fs::create_dir_all(&args.output_dir)?;
+            for (i, frame) in animation.render()?.iter().enumerate() {
+                let path = args
+                    .output_dir
+                    .join(format!("{}-{:03}.png", stem.to_string_lossy(), i));
+                write_png(&frame.to_png(bit_depth), &path)?;
+                println!("Wrote {}", path.display());
+            }
+        }
+        FramesArgs::Build(args) => {
+            let frames = args
+                .frames
+                .iter()
+                .map(Png::from_file)
+                .collect::<Result<Vec<Png>>>()?;
+            let png = apng::assemble(&frames, args.delay, args.loops)?;
+            write_png(&png, &args.output)?;
+            println!("Wrote {} frames to {}", frames.len(), args.output.display());
+        }
+    }
+    Ok(())
+}
+
+/// Converts an Apple CgBI image into a standard PNG
+pub fn normalize(args: NormalizeArgs) -> Result<()> {
+    let png = Png::from_file(&args.file_path)?;
+    let normalized = cgbi::normalize(&png)?;
+    write_png(
+        &normalized,
+        args.output.as_deref().unwrap_or(&args.file_path),
+    )?;
+    println!("Converted CgBI image to a standard PNG");
+    Ok(())
+}
+
+/// Shrinks a PNG file losslessly and prints what changed
+pub fn optimize(args: OptimizeArgs) -> Result<()> {
+    if args.level > 9 {
+        return Err("Compression level must be between 0 and 9".into());
+    }
+    let png = Png::from_file(&args.file_path)?;
+    let options = OptimizeOptions {
+        level: args.level,
+        reduce: !args.no_reduce,
+    };
+    let (optimized, report) = optimize::optimize(&png, options)?;
+    write_png(
+        &optimized,
+        args.output.as_deref().unwrap_or(&args.file_path),
+    )?;
+    print!("{}", report);
+    Ok(())
+}
+
+/// Converts a PNG file to another color type and bit depth
+pub fn convert(args: ConvertArgs) -> Result<()> {
+    let png = Png::from_file(&args.file_path)?;
+    let (converted, removed) = convert::convert(&png, args.to)?;
+    write_png(
+        &converted,
+        args.output.as_deref().unwrap_or(&args.file_path),
+    )?;
+    let format = |png: &Png| -> Result<String> {
+        let ihdr = png.ihdr()?;
+        Ok(format!("{}-bit {}", ihdr.bit_depth, ihdr.color_type))
+    };
+    println!("Converted {} to {}", format(&png)?, format(&converted)?);
+    for removed in removed.iter() {
+        println!("Removed {}", removed);
+    }
+    Ok(())
+}
+
+/// Writes the decoded pixels of a PNG file for other tools to read
+pub fn export(args: ExportArgs) -> Result<()> {
+    let png = Png::from_file(&args.file_path)?;
+    let bytes = match args.format {
+        ExportFormat::Ppm => Netpbm::from_png(&png)?.to_ppm(),
+        ExportFormat::Pam => Netpbm::from_png(&png)?.to_pam(),
+        ExportFormat::Raw => {
+            let image = RawImage::from_png(&png)?;
+            println!("{}, {} bytes per row", image.ihdr(), image.stride());
+            image.data().to_vec()
+        }
+    };
+    fs::write(&args.output, &bytes)?;
+    println!("Wrote {} bytes to {}", bytes.len(), args.output.display());
+    Ok(())
+}
+
+/// Encodes a Netpbm image as a PNG file
+pub fn import(args: ImportArgs) -> Result<()> {
+    let netpbm = Netpbm::try_from(&fs::read(&args.file_path)?[..])?;
+    let png = netpbm.to_png();
+    write_png(&png, &args.output)?;
+    println!("Wrote {} to {}", png.ihdr()?, args.output.display());
+    Ok(())
+}
+
+/// Crops, rotates or flips the image of a PNG file
+pub fn transform(args: TransformArgs) -> Result<()> {
+    let transform = Transform {
+        crop: args.crop,
+        rotate: args.rotate,
+        flip: args.flip,
+    };
+    if transform == Transform::default() {
+        return Err("Nothing to do, give --crop, --rotate or --flip".into());
+    }
+    let png = Png::from_file(&args.file_path)?;
+    let (transformed, removed) = transform::transform(&png, transform)?;
+    write_png(
+        &transformed,
+        args.output.as_deref().unwrap_or(&args.file_path),
+    )?;
+    println!("Transformed image to {}", transformed.ihdr()?);
+    for removed in removed.iter() {
+        println!("Warning: removed {}", removed);
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+//! Chunked file formats the message commands can store messages in.
+//!
+//! PNG (along with MNG and JNG) and RIFF both split a file into tagged chunks after a
+//! fixed signature. [`Container`] covers what the message commands need from them: the
+//! signature, finding, adding and removing chunks, and writing the file back. How a
+//! chunk header is laid out, whether chunks carry a checksum and where new chunks may go
+//! are not part of the trait; each implementation handles them in `add_chunk` and
+//! `to_bytes`.
+
+use std::fs;
+use std::path::Path;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{Format, Png};
+use crate::riff::Riff;
+use crate::time::Time;
+use crate::{Error, Result};
+
+/// A file made of tagged chunks
+pub trait Container {
+    /// The format of the file, for messages
+    fn format_name(&self) -> String;
+
+    /// The bytes every file of this format starts with
+    fn signature(&self) -> &[u8];
+
+    /// Returns the data of the first chunk of type `chunk_type`, if there is one.
+    fn chunk_data(&self, chunk_type: &str) -> Option<&[u8]>;
+
+    /// Adds a chunk wherever the format allows chunks readers don't know about.
+    fn add_chunk(&mut self, chunk_type: ChunkType, data: Vec<u8>) -> Result<()>;
+
+    /// Removes the first chunk of type `chunk_type` and returns its data.
+    fn remove_chunk(&mut self, chunk_type: &str) -> Result<Vec<u8>>;
+
+    /// Records `time` as the modification time, if the format has a place for it.
+    fn touch(&mut self, _time: Time) {}
+
+    /// The whole file, with each chunk's header, checksum and padding
+    fn to_bytes(&self) -> Vec<u8>;
+}
+
+impl Container for Png {
+    fn format_name(&self) -> String {
+        self.format().to_string()
+    }
+
+    fn signature(&self) -> &[u8] {
+        self.header()
+    }
+
+    fn chunk_data(&self, chunk_type: &str) -> Option<&[u8]> {
+        self.chunk_by_type(chunk_type).map(|chunk| chunk.data())
+    }
+
+    fn add_chunk(&mut self, chunk_type: ChunkType, data: Vec<u8>) -> Result<()> {
+        self.append_chunk(Chunk::new(chunk_type, data));
+        Ok(())
+    }
+
+    fn remove_chunk(&mut self, chunk_type: &str) -> Result<Vec<u8>> {
+        Ok(self.remove_first_chunk(chunk_type)?.data().to_vec())
+    }
+
+    fn touch(&mut self, time: Time) {
+        self.set_chunk(time.to_chunk());
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
+
+/// Parses `bytes` as whichever supported container its signature names.
+pub fn parse(bytes: &[u8]) -> Result<Box<dyn Container>> {
+    if bytes.starts_with(&Riff::SIGNATURE) {
+        return Ok(Box::new(Riff::try_from(bytes)?));
+    }
+    if Format::detect(bytes).is_some() {
+        return Ok(Box::new(Png::try_from(bytes)?));
+    }
+    Err(Error::from("Not a PNG, MNG, JNG or RIFF file"))
+}
+
+/// Reads and parses a PNG, MNG, JNG or RIFF file.
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn Container>> {
+    parse(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_by_signature() {
+        let png = Png::from_chunks(vec![Chunk::new(
+            ChunkType::from_str("IEND").unwrap(),
+            Vec::new(),
+        )]);
+        let mut container = parse(&png.as_bytes()).unwrap();
+        assert_eq!(container.format_name(), "PNG");
+        assert_eq!(container.signature(), Png::STANDARD_HEADER);
+        container
+            .add_chunk(ChunkType::from_str("ruSt").unwrap(), b"message".to_vec())
+            .unwrap();
+        let reparsed = parse(&container.to_bytes()).unwrap();
+        assert_eq!(reparsed.chunk_data("ruSt"), Some(&b"message"[..]));
+
+        let riff = parse(b"RIFF\x04\x00\x00\x00WAVE").unwrap();
+        assert_eq!(riff.format_name(), "RIFF (WAVE)");
+        assert_eq!(riff.signature(), b"RIFF");
+        assert!(parse(b"GIF89a").is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//! Conversion between color types and bit depths.
+//!
+//! The image data is decoded to 16-bit RGBA, converted and stored again in a single IDAT
+//! chunk. Formats without an alpha channel get translucent pixels blended onto the bKGD
+//! color, or onto black if there is none. Grayscale uses the luma of each color, and
+//! indexed images get a palette of at most 256 colors, chosen by median cut when the
+//! image has more. PLTE and tRNS are rebuilt for the new format, bKGD is converted and
+//! sBIT is capped at the new bit depth; hIST is dropped. Every other chunk is kept as it is.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ancillary::{Background, SignificantBits};
+use crate::apng::AnimationControl;
+use crate::cgbi;
+use crate::chunk::Chunk;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::image::{RawImage, Rgba16Image};
+use crate::palette::Palette;
+use crate::png::{Format, Png};
+use crate::{Error, Result};
+
+/// Chunks whose contents depend on the color type and bit depth
+const FORMAT_DEPENDENT: [&str; 5] = ["PLTE", "tRNS", "bKGD", "sBIT", "hIST"];
+
+/// The format to convert to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Rgba8,
+    Rgb8,
+    Gray8,
+    Gray16,
+    /// Up to 8-bit palette indices, as few bits as the palette allows
+    Indexed,
+}
+
+impl Target {
+    fn has_alpha(self) -> bool {
+        matches!(self, Target::Rgba8 | Target::Indexed)
+    }
+
+    fn is_gray(self) -> bool {
+        matches!(self, Target::Gray8 | Target::Gray16)
+    }
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rgba8" => Ok(Target::Rgba8),
+            "rgb8" => Ok(Target::Rgb8),
+            "gray8" => Ok(Target::Gray8),
+            "gray16" => Ok(Target::Gray16),
+            "indexed" => Ok(Target::Indexed),
+            _ => Err(Error::from(format!(
+                "Unknown format {}, expected rgba8, rgb8, gray8, gray16 or indexed",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Rgba8 => f.write_str("rgba8"),
+            Target::Rgb8 => f.write_str("rgb8"),
+            Target::Gray8 => f.write_str("gray8"),
+            Target::Gray16 => f.write_str("gray16"),
+            Target::Indexed => f.write_str("indexed"),
+        }
+    }
+}
+
+/// Converts the pixels of `png` to `target`, returning the new file along with notes on
+/// the chunks that were dropped.
+pub fn convert(png: &Png, target: Target) -> Result<(Png, Vec<String>)> {
+    if png.format() != Format::Png {
+        return Err(Error::from(format!(
+            "Only PNG files can be converted, found {}",
+            png.format()
+        )));
+    }
+    if cgbi::is_cgbi(png) {
+        return Err(Error::from(
+            "Convert CgBI images with `pngme normalize` first",
+        ));
+    }
+    if png.chunk_by_type(AnimationControl::CHUNK_TYPE).is_some() {
+        return Err(Error::from(
+            "Animated PNGs can't be converted, their frames would keep the old format",
+        ));
+    }
+    let original = png.ihdr()?;
+    let mut pixels = Rgba16Image::from_png(png)?;
+    let mut background = background_color(png, &original)?;
+    let mut base = png.clone();
+    let mut removed = Vec::new();
+
+    if !target.has_alpha() {
+        let [r, g, b] = background.unwrap_or([0; 3]).map(|v| v as u32);
+        for pixel in pixels.pixels.iter_mut() {
+            let alpha = pixel[3] as u32;
+            let blend = |value: u16, under: u32| {
+                ((value as u32 * alpha + under * (65535 - alpha) + 32767) / 65535) as u16
+            };
+            *pixel = [
+                blend(pixel[0], r),
+                blend(pixel[1], g),
+                blend(pixel[2], b),
+                65535,
+            ];
+        }
+        if base.remove_first_chunk("tRNS").is_ok() {
+            removed.push("tRNS (transparency blended onto the background)".to_string());
+        }
+    }
+    if target.is_gray() {
+        for pixel in pixels.pixels.iter_mut() {
+            let y = luma([pixel[0], pixel[1], pixel[2]]);
+            *pixel = [y, y, y, pixel[3]];
+        }
+        background = background.map(|color| [luma(color); 3]);
+    }
+
+    let (image, palette) = match target {
+        Target::Indexed => {
+            let colors: Vec<[u8; 4]> = pixels
+                .pixels
+                .iter()
+                .map(|p| p.map(|v| ((v as u32 + 128) / 257) as u8))
+                .collect();
+            let palette = Palette::quantize(&colors, 256);
+            let mut image = RawImage::blank(Ihdr {
+                color_type: ColorType::Indexed,
+                bit_depth: palette.bit_depth(),
+                ..original.clone()
+            });
+            let mut index = HashMap::new();
+            for (i, &color) in colors.iter().enumerate() {
+                let (x, y) = (i % image.width(), i / image.width());
+                let entry = *index.entry(color).or_insert_with(|| palette.nearest(color));
+                image.set_sample(x, y, 0, entry as u16);
+            }
+            background = background.map(|color| {
+                let entry = palette.entries[palette.nearest([0, 1, 2, 3].map(|c| match c {
+                    3 => 255,
+                    _ => ((color[c] as u32 + 128) / 257) as u8,
+                }))];
+                [0, 1, 2].map(|c| entry[c] as u16 * 257)
+            });
+            (image, Some(palette))
+        }
+        _ => {
+            let (color_type, bit_depth) = match target {
+                Target::Rgba8 => (ColorType::Rgba, 8),
+                Target::Rgb8 => (ColorType::Rgb, 8),
+                Target::Gray8 => (ColorType::Grayscale, 8),
+                _ => (ColorType::Grayscale, 16),
+            };
+            if bit_depth == 8 {
+                background =
+                    background.map(|color| color.map(|v| ((v as u32 + 128) / 257 * 257) as u16));
+            }
+            let image = pixels.to_raw(Ihdr {
+                color_type,
+                bit_depth,
+                ..original.clone()
+            });
+            (image, None)
+        }
+    };
+
+    let converted = with_image_data(
+        &base,
+        image.ihdr(),
+        palette.as_ref(),
+        image.encode(9),
+        background,
+        &mut removed,
+    )?;
+    Ok((converted, removed))
+}
+
+/// The Rec. 709 luma of a 16-bit RGB color
+fn luma([r, g, b]: [u16; 3]) -> u16 {
+    ((2126 * r as u32 + 7152 * g as u32 + 722 * b as u32 + 5000) / 10000) as u16
+}
+
+/// The bKGD color of `png` as 16-bit RGB, if it has one
+pub fn background_color(png: &Png, ihdr: &Ihdr) -> Result<Option<[u16; 3]>> {
+    let Some(chunk) = png.chunk_by_type(Background::CHUNK_TYPE) else {
+        return Ok(None);
+    };
+    let palette = match ihdr.color_type {
+        ColorType::Indexed => Palette::from_png(png)?.entries,
+        _ => Vec::new(),
+    };
+    let color = Background::parse(chunk, ihdr)?
+        .to_rgb16(ihdr, &palette)
+        .ok_or("bKGD refers to a missing palette entry")?;
+    Ok(Some(color))
+}
+
+/// Builds a copy of `base` holding image data `data` in the format of `ihdr`. Unless the
+/// format is unchanged and there is no new `palette`, the chunks that depend on it are
+/// rewritten: PLTE and tRNS come from `palette`, sBIT is carried over for the new
+/// channels and bKGD is set to `background` if the new format can store it exactly.
+/// Notes on chunks that could not be carried over are added to `removed`.
+pub fn with_image_data(
+    base: &Png,
+    ihdr: &Ihdr,
+    palette: Option<&Palette>,
+    data: Vec<u8>,
+    background: Option<[u16; 3]>,
+    removed: &mut Vec<String>,
+) -> Result<Png> {
+    let mut png = base.clone();
+    png.set_image_data(data);
+    if palette.is_none() && *ihdr == base.ihdr()? {
+        return Ok(png);
+    }
+
+    let significant_bits = match base.chunk_by_type(SignificantBits::CHUNK_TYPE) {
+        Some(chunk) => {
+            let bits = significant_bits(chunk, &base.ihdr()?, ihdr);
+            if bits.is_none() {
+                removed.push("sBIT (invalid for the original format)".to_string());
+            }
+            bits
+        }
+        None => None,
+    };
+    png.chunks_mut().retain(|chunk| {
+        let chunk_type = chunk.chunk_type().to_string();
+        if chunk_type == "hIST" {
+            removed.push(format!("{} (color type or bit depth changed)", chunk_type));
+        }
+        !FORMAT_DEPENDENT.contains(&chunk_type.as_str())
+    });
+    let position = png
+        .chunks()
+        .iter()
+        .position(|c| c.chunk_type().to_string() == Ihdr::CHUNK_TYPE)
+        .ok_or("PNG has no IHDR chunk")?;
+    png.chunks_mut()[position] = ihdr.to_chunk();
+    if let Some(palette) = palette {
+        palette.write_to(&mut png);
+    }
+    if let Some(bits) = significant_bits {
+        png.set_chunk(bits.to_chunk());
+    }
+
+    if let Some(color) = background {
+        let entries = palette.map(|p| &p.entries[..]).unwrap_or(&[]);
+        match Background::from_rgb16(color, ihdr, entries) {
+            Some(background) => png.set_chunk(background.to_chunk()),
+            None => removed.push("bKGD (color not available in the new format)".to_string()),
+        }
+    }
+    Ok(png)
+}
+
+/// The sBIT `chunk` of an image in the `original` format, rewritten for `ihdr`. Gray takes
+/// the most precise color channel, and a new alpha channel counts as fully significant.
+fn significant_bits(chunk: &Chunk, original: &Ihdr, ihdr: &Ihdr) -> Option<SignificantBits> {
+    let bits = SignificantBits::parse(chunk, original).ok()?.bits;
+    let (color, alpha) = match original.color_type {
+        ColorType::GrayscaleAlpha | ColorType::Rgba => bits.split_at(bits.len() - 1),
+        _ => (&bits[..], &[][..]),
+    };
+    let depth = match ihdr.color_type {
+        ColorType::Indexed => 8,
+        _ => ihdr.bit_depth,
+    };
+    let gray = color.iter().copied().max()?;
+    let mut new_bits = match ihdr.color_type {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => vec![gray],
+        _ if color.len() == 1 => vec![gray; 3],
+        _ => color.to_vec(),
+    };
+    if ihdr.color_type.has_alpha() {
+        new_bits.push(alpha.first().copied().unwrap_or(depth));
+    }
+    let new_bits = new_bits.into_iter().map(|b| b.min(depth)).collect();
+    SignificantBits::new(new_bits, ihdr).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn indexed_png() -> Png {
+        let ihdr = Ihdr {
+            width: 4,
+            height: 1,
+            bit_depth: 2,
+            color_type: ColorType::Indexed,
+            interlaced: false,
+        };
+        let mut image = RawImage::blank(ihdr.clone());
+        for x in 0..4 {
+            image.set_sample(x, 0, 0, x as u16 % 3);
+        }
+        let mut png = Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            chunk("gAMA", 45455u32.to_be_bytes().to_vec()),
+            chunk("PLTE", vec![255, 0, 0, 0, 255, 0, 0, 0, 255]),
+            chunk("tRNS", vec![255, 0]),
+            chunk("bKGD", vec![2]),
+            chunk("hIST", vec![0, 2, 0, 1, 0, 1]),
+            chunk("IEND", Vec::new()),
+        ]);
+        png.set_image_data(image.encode(9));
+        png
+    }
+
+    #[test]
+    fn test_expands_palette() {
+        let png = indexed_png();
+        let (rgba, removed) = convert(&png, Target::Rgba8).unwrap();
+        let ihdr = rgba.ihdr().unwrap();
+        assert_eq!((ihdr.color_type, ihdr.bit_depth), (ColorType::Rgba, 8));
+        assert_eq!(rgba.pixel_digest().unwrap(), png.pixel_digest().unwrap());
+        assert!(rgba.chunk_by_type("PLTE").is_none());
+        assert!(rgba.chunk_by_type("tRNS").is_none());
+        assert!(rgba.chunk_by_type("gAMA").is_some());
+        assert_eq!(
+            rgba.chunk_by_type("bKGD").unwrap().data(),
+            [0, 0, 0, 0, 0, 255]
+        );
+        assert_eq!(removed, ["hIST (color type or bit depth changed)"]);
+
+        let (indexed, _) = convert(&rgba, Target::Indexed).unwrap();
+        assert_eq!(indexed.ihdr().unwrap().bit_depth, 2);
+        assert_eq!(indexed.pixel_digest().unwrap(), png.pixel_digest().unwrap());
+    }
+
+    #[test]
+    fn test_carries_significant_bits() {
+        let mut png = indexed_png();
+        png.set_chunk(chunk("sBIT", vec![5, 6, 5]));
+        let (rgba, _) = convert(&png, Target::Rgba8).unwrap();
+        assert_eq!(rgba.chunk_by_type("sBIT").unwrap().data(), [5, 6, 5, 8]);
+        let (gray, _) = convert(&rgba, Target::Gray16).unwrap();
+        assert_eq!(gray.chunk_by_type("sBIT").unwrap().data(), [6]);
+    }
+
+    #[test]
+    fn test_blends_onto_background_for_gray() {
+        let (gray, removed) = convert(&indexed_png(), Target::Gray8).unwrap();
+        let ihdr = gray.ihdr().unwrap();
+        assert_eq!((ihdr.color_type, ihdr.bit_depth), (ColorType::Grayscale, 8));
+        assert!(removed.contains(&"tRNS (transparency blended onto the background)".to_string()));
+        // Red, transparent green over the blue background, blue, red
+        let image = RawImage::from_png(&gray).unwrap();
+        let samples: Vec<u16> = (0..4).map(|x| image.sample(x, 0, 0)).collect();
+        assert_eq!(samples, [54, 18, 18, 54]);
+        assert_eq!(gray.chunk_by_type("bKGD").unwrap().data(), [0, 18]);
+    }
+
+    #[test]
+    fn test_quantizes_many_colors() {
+        let mut image = Rgba16Image::blank(64, 8);
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            *pixel = [i as u16 * 128, 65535 - i as u16 * 128, 0, 65535];
+        }
+        let (indexed, _) = convert(&image.to_png(16), Target::Indexed).unwrap();
+        let ihdr = indexed.ihdr().unwrap();
+        assert_eq!((ihdr.color_type, ihdr.bit_depth), (ColorType::Indexed, 8));
+        assert_eq!(Palette::from_png(&indexed).unwrap().len(), 256);
+        assert!(indexed.chunk_by_type("tRNS").is_none());
+    }
+}
@@ -0,0 +1,798 @@
+//! The eXIf chunk, which holds EXIF metadata in the TIFF structure used by JPEG files.
+//! https://www.cipa.jp/std/documents/e/DC-008-2012_E.pdf
+//!
+//! Tags are read into typed values and the whole structure is laid out afresh when it is
+//! written back, so edits never leave removed data behind. Offsets stored inside opaque
+//! values, such as those in some maker notes, are not relocated.
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ExifError {
+    #[error("Expected an eXIf chunk, found {0}")]
+    WrongChunkType(String),
+    #[error("EXIF data does not start with a TIFF header")]
+    InvalidHeader,
+    #[error("EXIF data is truncated")]
+    Truncated,
+    #[error("EXIF directories refer to each other in a loop")]
+    Loop,
+}
+
+/// The image file directories (IFDs) that make up the EXIF structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ifd {
+    /// IFD0, describing the image itself
+    Primary,
+    /// Camera settings, pointed to by IFD0
+    Exif,
+    /// Location data, pointed to by IFD0
+    Gps,
+    /// Interoperability information, pointed to by the EXIF IFD
+    Interop,
+    /// IFD1, describing the embedded thumbnail
+    Thumbnail,
+}
+
+impl Ifd {
+    /// Order the IFDs are written in
+    const ALL: [Ifd; 5] = [
+        Ifd::Primary,
+        Ifd::Exif,
+        Ifd::Interop,
+        Ifd::Gps,
+        Ifd::Thumbnail,
+    ];
+
+    fn index(&self) -> usize {
+        Ifd::ALL.iter().position(|ifd| ifd == self).unwrap()
+    }
+}
+
+impl fmt::Display for Ifd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Ifd::Primary => "IFD0",
+            Ifd::Exif => "EXIF",
+            Ifd::Gps => "GPS",
+            Ifd::Interop => "Interop",
+            Ifd::Thumbnail => "IFD1",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Tags that point to other IFDs or to the thumbnail; they are recreated when writing
+const EXIF_POINTER: u16 = 0x8769;
+const GPS_POINTER: u16 = 0x8825;
+const INTEROP_POINTER: u16 = 0xA005;
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const THUMBNAIL_LENGTH: u16 = 0x0202;
+const STRIP_OFFSETS: u16 = 0x0111;
+
+pub const ORIENTATION: u16 = 0x0112;
+
+const TAG_NAMES: [(Ifd, u16, &str); 67] = [
+    (Ifd::Primary, 0x010E, "ImageDescription"),
+    (Ifd::Primary, 0x010F, "Make"),
+    (Ifd::Primary, 0x0110, "Model"),
+    (Ifd::Primary, 0x0112, "Orientation"),
+    (Ifd::Primary, 0x011A, "XResolution"),
+    (Ifd::Primary, 0x011B, "YResolution"),
+    (Ifd::Primary, 0x0128, "ResolutionUnit"),
+    (Ifd::Primary, 0x0131, "Software"),
+    (Ifd::Primary, 0x0132, "DateTime"),
+    (Ifd::Primary, 0x013B, "Artist"),
+    (Ifd::Primary, 0x013E, "WhitePoint"),
+    (Ifd::Primary, 0x013F, "PrimaryChromaticities"),
+    (Ifd::Primary, 0x0213, "YCbCrPositioning"),
+    (Ifd::Primary, 0x8298, "Copyright"),
+    (Ifd::Primary, 0xA401, "CustomRendered"),
+    (Ifd::Exif, 0x829A, "ExposureTime"),
+    (Ifd::Exif, 0x829D, "FNumber"),
+    (Ifd::Exif, 0x8822, "ExposureProgram"),
+    (Ifd::Exif, 0x8827, "ISOSpeedRatings"),
+    (Ifd::Exif, 0x9000, "ExifVersion"),
+    (Ifd::Exif, 0x9003, "DateTimeOriginal"),
+    (Ifd::Exif, 0x9004, "DateTimeDigitized"),
+    (Ifd::Exif, 0x9010, "OffsetTime"),
+    (Ifd::Exif, 0x9011, "OffsetTimeOriginal"),
+    (Ifd::Exif, 0x9101, "ComponentsConfiguration"),
+    (Ifd::Exif, 0x9201, "ShutterSpeedValue"),
+    (Ifd::Exif, 0x9202, "ApertureValue"),
+    (Ifd::Exif, 0x9203, "BrightnessValue"),
+    (Ifd::Exif, 0x9204, "ExposureBiasValue"),
+    (Ifd::Exif, 0x9207, "MeteringMode"),
+    (Ifd::Exif, 0x9209, "Flash"),
+    (Ifd::Exif, 0x920A, "FocalLength"),
+    (Ifd::Exif, 0x927C, "MakerNote"),
+    (Ifd::Exif, 0x9286, "UserComment"),
+    (Ifd::Exif, 0x9290, "SubSecTime"),
+    (Ifd::Exif, 0xA000, "FlashpixVersion"),
+    (Ifd::Exif, 0xA001, "ColorSpace"),
+    (Ifd::Exif, 0xA002, "PixelXDimension"),
+    (Ifd::Exif, 0xA003, "PixelYDimension"),
+    (Ifd::Exif, 0xA402, "ExposureMode"),
+    (Ifd::Exif, 0xA403, "WhiteBalance"),
+    (Ifd::Exif, 0xA405, "FocalLengthIn35mmFilm"),
+    (Ifd::Exif, 0xA406, "SceneCaptureType"),
+    (Ifd::Exif, 0xA420, "ImageUniqueID"),
+    (Ifd::Exif, 0xA430, "CameraOwnerName"),
+    (Ifd::Exif, 0xA431, "BodySerialNumber"),
+    (Ifd::Exif, 0xA433, "LensMake"),
+    (Ifd::Exif, 0xA434, "LensModel"),
+    (Ifd::Exif, 0xA435, "LensSerialNumber"),
+    (Ifd::Gps, 0x0000, "GPSVersionID"),
+    (Ifd::Gps, 0x0001, "GPSLatitudeRef"),
+    (Ifd::Gps, 0x0002, "GPSLatitude"),
+    (Ifd::Gps, 0x0003, "GPSLongitudeRef"),
+    (Ifd::Gps, 0x0004, "GPSLongitude"),
+    (Ifd::Gps, 0x0005, "GPSAltitudeRef"),
+    (Ifd::Gps, 0x0006, "GPSAltitude"),
+    (Ifd::Gps, 0x0007, "GPSTimeStamp"),
+    (Ifd::Gps, 0x000C, "GPSSpeedRef"),
+    (Ifd::Gps, 0x000D, "GPSSpeed"),
+    (Ifd::Gps, 0x0010, "GPSImgDirectionRef"),
+    (Ifd::Gps, 0x0011, "GPSImgDirection"),
+    (Ifd::Gps, 0x0012, "GPSMapDatum"),
+    (Ifd::Gps, 0x001D, "GPSDateStamp"),
+    (Ifd::Interop, 0x0001, "InteropIndex"),
+    (Ifd::Interop, 0x0002, "InteropVersion"),
+    (Ifd::Thumbnail, 0x0103, "Compression"),
+    (Ifd::Thumbnail, 0x0112, "Orientation"),
+];
+
+/// Name of a tag, or `None` for tags this module doesn't know
+pub fn tag_name(ifd: Ifd, tag: u16) -> Option<&'static str> {
+    TAG_NAMES
+        .iter()
+        .find(|(i, t, _)| *t == tag && (*i == ifd || (ifd == Ifd::Thumbnail && *i == Ifd::Primary)))
+        .map(|(_, _, name)| *name)
+}
+
+/// The value of a tag, one variant per TIFF field type
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(Vec<u8>),
+    Ascii(Vec<u8>),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+impl Value {
+    /// Size in bytes of a single element of each TIFF field type, indexed by type number
+    const SIZES: [usize; 13] = [0, 1, 1, 2, 4, 8, 1, 1, 2, 4, 8, 4, 8];
+
+    fn field_type(&self) -> u16 {
+        match self {
+            Value::Byte(_) => 1,
+            Value::Ascii(_) => 2,
+            Value::Short(_) => 3,
+            Value::Long(_) => 4,
+            Value::Rational(_) => 5,
+            Value::SByte(_) => 6,
+            Value::Undefined(_) => 7,
+            Value::SShort(_) => 8,
+            Value::SLong(_) => 9,
+            Value::SRational(_) => 10,
+            Value::Float(_) => 11,
+            Value::Double(_) => 12,
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            Value::Byte(v) | Value::Ascii(v) | Value::Undefined(v) => v.len(),
+            Value::Short(v) => v.len(),
+            Value::Long(v) => v.len(),
+            Value::Rational(v) => v.len(),
+            Value::SByte(v) => v.len(),
+            Value::SShort(v) => v.len(),
+            Value::SLong(v) => v.len(),
+            Value::SRational(v) => v.len(),
+            Value::Float(v) => v.len(),
+            Value::Double(v) => v.len(),
+        }
+    }
+
+    /// Decodes `count` elements of `field_type` from `bytes`, or `None` for unknown types
+    fn read(field_type: u16, bytes: &[u8], order: ByteOrder) -> Option<Value> {
+        let size = *Value::SIZES.get(field_type as usize)?;
+        let elements = bytes.chunks_exact(size.max(1));
+        let value = match field_type {
+            1 => Value::Byte(bytes.to_vec()),
+            2 => Value::Ascii(bytes.to_vec()),
+            3 => Value::Short(elements.map(|b| order.u16(b)).collect()),
+            4 => Value::Long(elements.map(|b| order.u32(b)).collect()),
+            5 => Value::Rational(
+                elements
+                    .map(|b| (order.u32(b), order.u32(&b[4..])))
+                    .collect(),
+            ),
+            6 => Value::SByte(bytes.iter().map(|&b| b as i8).collect()),
+            7 => Value::Undefined(bytes.to_vec()),
+            8 => Value::SShort(elements.map(|b| order.u16(b) as i16).collect()),
+            9 => Value::SLong(elements.map(|b| order.u32(b) as i32).collect()),
+            10 => Value::SRational(
+                elements
+                    .map(|b| (order.u32(b) as i32, order.u32(&b[4..]) as i32))
+                    .collect(),
+            ),
+            11 => Value::Float(elements.map(|b| f32::from_bits(order.u32(b))).collect()),
+            12 => Value::Double(elements.map(|b| f64::from_bits(order.u64(b))).collect()),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn write(&self, order: ByteOrder, out: &mut Vec<u8>) {
+        match self {
+            Value::Byte(v) | Value::Ascii(v) | Value::Undefined(v) => out.extend_from_slice(v),
+            Value::SByte(v) => out.extend(v.iter().map(|&b| b as u8)),
+            Value::Short(v) => v.iter().for_each(|&n| order.put_u16(out, n)),
+            Value::SShort(v) => v.iter().for_each(|&n| order.put_u16(out, n as u16)),
+            Value::Long(v) => v.iter().for_each(|&n| order.put_u32(out, n)),
+            Value::SLong(v) => v.iter().for_each(|&n| order.put_u32(out, n as u32)),
+            Value::Rational(v) => v.iter().for_each(|&(n, d)| {
+                order.put_u32(out, n);
+                order.put_u32(out, d);
+            }),
+            Value::SRational(v) => v.iter().for_each(|&(n, d)| {
+                order.put_u32(out, n as u32);
+                order.put_u32(out, d as u32);
+            }),
+            Value::Float(v) => v.iter().for_each(|&n| order.put_u32(out, n.to_bits())),
+            Value::Double(v) => v.iter().for_each(|&n| order.put_u64(out, n.to_bits())),
+        }
+    }
+
+    fn byte_len(&self) -> usize {
+        Value::SIZES[self.field_type() as usize] * self.count()
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, values: &[T]) -> fmt::Result {
+            if values.len() > 8 {
+                return write!(f, "<{} values>", values.len());
+            }
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}", value)?;
+            }
+            Ok(())
+        }
+        let fraction = |(n, d): (i64, i64)| format!("{}/{}", n, d);
+        match self {
+            Value::Ascii(text) => {
+                let end = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+                write!(f, "{}", String::from_utf8_lossy(&text[..end]))
+            }
+            Value::Undefined(bytes)
+                if bytes.len() <= 64
+                    && bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') =>
+            {
+                write!(f, "{}", String::from_utf8_lossy(bytes))
+            }
+            Value::Undefined(bytes) => write!(f, "<{} bytes>", bytes.len()),
+            Value::Byte(v) => list(f, v),
+            Value::Short(v) => list(f, v),
+            Value::Long(v) => list(f, v),
+            Value::SByte(v) => list(f, v),
+            Value::SShort(v) => list(f, v),
+            Value::SLong(v) => list(f, v),
+            Value::Float(v) => list(f, v),
+            Value::Double(v) => list(f, v),
+            Value::Rational(v) => list(
+                f,
+                &v.iter()
+                    .map(|&(n, d)| fraction((n as i64, d as i64)))
+                    .collect::<Vec<_>>(),
+            ),
+            Value::SRational(v) => list(
+                f,
+                &v.iter()
+                    .map(|&(n, d)| fraction((n as i64, d as i64)))
+                    .collect::<Vec<_>>(),
+            ),
+        }
+    }
+}
+
+/// A single tag of an IFD
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub tag: u16,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn u16(&self, b: &[u8]) -> u16 {
+        match self {
+            ByteOrder::Little => u16::from_le_bytes([b[0], b[1]]),
+            ByteOrder::Big => u16::from_be_bytes([b[0], b[1]]),
+        }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        match self {
+            ByteOrder::Little => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            ByteOrder::Big => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    }
+
+    fn u64(&self, b: &[u8]) -> u64 {
+        let bytes = [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]];
+        match self {
+            ByteOrder::Little => u64::from_le_bytes(bytes),
+            ByteOrder::Big => u64::from_be_bytes(bytes),
+        }
+    }
+
+    fn put_u16(&self, out: &mut Vec<u8>, n: u16) {
+        match self {
+            ByteOrder::Little => out.extend_from_slice(&n.to_le_bytes()),
+            ByteOrder::Big => out.extend_from_slice(&n.to_be_bytes()),
+        }
+    }
+
+    fn put_u32(&self, out: &mut Vec<u8>, n: u32) {
+        match self {
+            ByteOrder::Little => out.extend_from_slice(&n.to_le_bytes()),
+            ByteOrder::Big => out.extend_from_slice(&n.to_be_bytes()),
+        }
+    }
+
+    fn put_u64(&self, out: &mut Vec<u8>, n: u64) {
+        match self {
+            ByteOrder::Little => out.extend_from_slice(&n.to_le_bytes()),
+            ByteOrder::Big => out.extend_from_slice(&n.to_be_bytes()),
+        }
+    }
+}
+
+/// The contents of an eXIf chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exif {
+    order: ByteOrder,
+    /// Entries of each IFD present in the file, indexed like `Ifd::ALL`. Pointer tags
+    /// are left out.
+    ifds: [Option<Vec<Entry>>; 5],
+    /// JPEG data of the thumbnail described by IFD1
+    thumbnail: Option<Vec<u8>>,
+    /// Whether IFD1 held an uncompressed strip thumbnail, which is left out
+    strip_thumbnail: bool,
+}
+
+impl Exif {
+    pub const CHUNK_TYPE: &'static str = "eXIf";
+
+    /// Parses the TIFF structure of an eXIf chunk's data
+    pub fn parse(data: &[u8]) -> Result<Self, ExifError> {
+        let order = match data.get(..4) {
+            Some(b"II*\0") => ByteOrder::Little,
+            Some(b"MM\0*") => ByteOrder::Big,
+            _ => return Err(ExifError::InvalidHeader),
+        };
+        let mut exif = Exif {
+            order,
+            ifds: Default::default(),
+            thumbnail: None,
+            strip_thumbnail: false,
+        };
+        let first = data.get(4..8).ok_or(ExifError::Truncated)?;
+        let mut visited = HashSet::new();
+        let mut pending = vec![(Ifd::Primary, order.u32(first))];
+        while let Some((ifd, offset)) = pending.pop() {
+            if !visited.insert(offset) {
+                return Err(ExifError::Loop);
+            }
+            let (entries, next) = read_ifd(data, offset as usize, order)?;
+            let mut kept = Vec::new();
+            let (mut thumbnail_offset, mut thumbnail_length) = (None, None);
+            for (entry, raw) in entries {
+                match (ifd, entry.tag) {
+                    (Ifd::Primary, EXIF_POINTER) => pending.push((Ifd::Exif, raw)),
+                    (Ifd::Primary, GPS_POINTER) => pending.push((Ifd::Gps, raw)),
+                    (Ifd::Exif, INTEROP_POINTER) => pending.push((Ifd::Interop, raw)),
+                    (Ifd::Thumbnail, THUMBNAIL_OFFSET) => thumbnail_offset = Some(raw as usize),
+                    (Ifd::Thumbnail, THUMBNAIL_LENGTH) => thumbnail_length = Some(raw as usize),
+                    _ => kept.push(entry),
+                }
+            }
+            if ifd == Ifd::Primary && next != 0 {
+                pending.push((Ifd::Thumbnail, next));
+            }
+            if ifd == Ifd::Thumbnail {
+                // Uncompressed strip thumbnails would need their offsets relocated
+                if kept.iter().any(|e| e.tag == STRIP_OFFSETS) {
+                    exif.strip_thumbnail = true;
+                    continue;
+                }
+                if let (Some(offset), Some(length)) = (thumbnail_offset, thumbnail_length) {
+                    let jpeg = offset
+                        .checked_add(length)
+                        .and_then(|end| data.get(offset..end))
+                        .ok_or(ExifError::Truncated)?;
+                    exif.thumbnail = Some(jpeg.to_vec());
+                }
+            }
+            exif.ifds[ifd.index()] = Some(kept);
+        }
+        Ok(exif)
+    }
+
+    /// Whether IFD1 held a thumbnail stored as uncompressed strips. Such thumbnails are
+    /// not kept, so writing the EXIF data back leaves them out.
+    pub fn has_strip_thumbnail(&self) -> bool {
+        self.strip_thumbnail
+    }
+
+    /// Whether the data is stored big-endian
+    pub fn is_big_endian(&self) -> bool {
+        self.order == ByteOrder::Big
+    }
+
+    /// Every tag of every IFD, in the order they are written
+    pub fn entries(&self) -> impl Iterator<Item = (Ifd, &Entry)> {
+        Ifd::ALL.iter().flat_map(move |&ifd| {
+            self.ifds[ifd.index()]
+                .iter()
+                .flatten()
+                .map(move |entry| (ifd, entry))
+        })
+    }
+
+    /// Finds a tag by the name given by [`tag_name`], such as `Model` or `GPSLatitude`
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.entries()
+            .find(|(ifd, entry)| tag_name(*ifd, entry.tag) == Some(name))
+            .map(|(_, entry)| &entry.value)
+    }
+
+    /// Removes `tag` from `ifd`, returning whether it was there
+    pub fn remove_tag(&mut self, ifd: Ifd, tag: u16) -> bool {
+        match self.ifds[ifd.index()].as_mut() {
+            Some(entries) => {
+                let before = entries.len();
+                entries.retain(|e| e.tag != tag);
+                entries.len() != before
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the GPS IFD, returning the number of tags it held
+    pub fn remove_gps(&mut self) -> usize {
+        self.ifds[Ifd::Gps.index()]
+            .take()
+            .map_or(0, |entries| entries.len())
+    }
+
+    /// Removes the orientation tag from the image and its thumbnail
+    pub fn remove_orientation(&mut self) -> bool {
+        let primary = self.remove_tag(Ifd::Primary, ORIENTATION);
+        let thumbnail = self.remove_tag(Ifd::Thumbnail, ORIENTATION);
+        primary || thumbnail
+    }
+
+    /// Lays out the TIFF structure
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let order = self.order;
+        let present = |ifd: Ifd| self.ifds[ifd.index()].is_some();
+        // Interop is only reachable through the EXIF IFD
+        let written: Vec<Ifd> = Ifd::ALL
+            .iter()
+            .copied()
+            .filter(|&ifd| present(ifd) && (ifd != Ifd::Interop || present(Ifd::Exif)))
+            .collect();
+
+        // Pointer values are 4 bytes whatever they hold, so sizes can be worked out with
+        // placeholder offsets before the real ones are known. The last offset is where
+        // the thumbnail data goes.
+        let entries_with = |ifd: Ifd, offsets: &[u32; 6]| -> Vec<Entry> {
+            let mut pointers = Vec::new();
+            let mut point_to = |target: Ifd, tag| {
+                if written.contains(&target) {
+                    pointers.push((tag, offsets[target.index()]));
+                }
+            };
+            match ifd {
+                Ifd::Primary => {
+                    point_to(Ifd::Exif, EXIF_POINTER);
+                    point_to(Ifd::Gps, GPS_POINTER);
+                }
+                Ifd::Exif => point_to(Ifd::Interop, INTEROP_POINTER),
+                _ => {}
+            }
+            if let (Ifd::Thumbnail, Some(thumbnail)) = (ifd, self.thumbnail.as_ref()) {
+                pointers.push((THUMBNAIL_OFFSET, offsets[5]));
+                pointers.push((THUMBNAIL_LENGTH, thumbnail.len() as u32));
+            }
+            let mut entries = self.ifds[ifd.index()].clone().unwrap_or_default();
+            entries.extend(pointers.into_iter().map(|(tag, offset)| Entry {
+                tag,
+                value: Value::Long(vec![offset]),
+            }));
+            entries.sort_by_key(|e| e.tag);
+            entries
+        };
+
+        let mut offsets = [0u32; 6];
+        let mut position = 8;
+        for &ifd in written.iter() {
+            offsets[ifd.index()] = position as u32;
+            let entries = entries_with(ifd, &offsets);
+            position += 6 + 12 * entries.len();
+            position += entries
+                .iter()
+                .map(|e| e.value.byte_len())
+                .filter(|&len| len > 4)
+                .map(|len| len + len % 2)
+                .sum::<usize>();
+        }
+        offsets[5] = position as u32;
+
+        let mut out = Vec::with_capacity(position + self.thumbnail.as_ref().map_or(0, Vec::len));
+        match order {
+            ByteOrder::Little => out.extend_from_slice(b"II*\0"),
+            ByteOrder::Big => out.extend_from_slice(b"MM\0*"),
+        }
+        order.put_u32(&mut out, 8);
+        for &ifd in written.iter() {
+            let entries = entries_with(ifd, &offsets);
+            let start = out.len();
+            let mut data_offset = start + 6 + 12 * entries.len();
+            let mut values = Vec::new();
+            order.put_u16(&mut out, entries.len() as u16);
+            for entry in entries.iter() {
+                order.put_u16(&mut out, entry.tag);
+                order.put_u16(&mut out, entry.value.field_type());
+                order.put_u32(&mut out, entry.value.count() as u32);
+                let mut bytes = Vec::new();
+                entry.value.write(order, &mut bytes);
+                if bytes.len() <= 4 {
+                    bytes.resize(4, 0);
+                    out.extend_from_slice(&bytes);
+                } else {
+                    order.put_u32(&mut out, data_offset as u32);
+                    if bytes.len() % 2 == 1 {
+                        bytes.push(0);
+                    }
+                    data_offset += bytes.len();
+                    values.extend(bytes);
+                }
+            }
+            let next = match ifd {
+                Ifd::Primary if written.contains(&Ifd::Thumbnail) => {
+                    offsets[Ifd::Thumbnail.index()]
+                }
+                _ => 0,
+            };
+            order.put_u32(&mut out, next);
+            out.extend(values);
+        }
+        if let Some(thumbnail) = self.thumbnail.as_ref() {
+            out.extend_from_slice(thumbnail);
+        }
+        out
+    }
+
+    /// Builds the eXIf chunk for this data
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(Self::CHUNK_TYPE).unwrap(),
+            self.to_bytes(),
+        )
+    }
+}
+
+/// Reads the entries of the IFD at `offset` along with the raw 4-byte value field of each,
+/// which holds the target offset for pointer tags, and the offset of the next IFD.
+fn read_ifd(
+    data: &[u8],
+    offset: usize,
+    order: ByteOrder,
+) -> Result<(Vec<(Entry, u32)>, u32), ExifError> {
+    let slice = |start: usize, len: usize| data.get(start..start + len).ok_or(ExifError::Truncated);
+    let count = order.u16(slice(offset, 2)?) as usize;
+    let mut entries = Vec::with_capacity(count);
+    for i in 0..count {
+        let raw = slice(offset + 2 + 12 * i, 12)?;
+        let tag = order.u16(raw);
+        let field_type = order.u16(&raw[2..]);
+        let count = order.u32(&raw[4..]) as usize;
+        let value_field = order.u32(&raw[8..]);
+        let size = match Value::SIZES.get(field_type as usize) {
+            Some(&size) if size > 0 => size,
+            // Readers must skip tags of types they don't know
+            _ => continue,
+        };
+        let len = size.checked_mul(count).ok_or(ExifError::Truncated)?;
+        let bytes = if len <= 4 {
+            &raw[8..8 + len]
+        } else {
+            slice(value_field as usize, len)?
+        };
+        if let Some(value) = Value::read(field_type, bytes, order) {
+            entries.push((Entry { tag, value }, value_field));
+        }
+    }
+    let next = order.u32(slice(offset + 2 + 12 * count, 4)?);
+    Ok((entries, next))
+}
+
+impl TryFrom<&Chunk> for Exif {
+    type Error = ExifError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, ExifError> {
+        if chunk.chunk_type().to_string() != Self::CHUNK_TYPE {
+            return Err(ExifError::WrongChunkType(chunk.chunk_type().to_string()));
+        }
+        Exif::parse(chunk.data())
+    }
+}
+
+impl fmt::Display for Exif {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "EXIF ({}-endian) {{",
+            if self.is_big_endian() {
+                "big"
+            } else {
+                "little"
+            }
+        )?;
+        for (ifd, entry) in self.entries() {
+            match tag_name(ifd, entry.tag) {
+                Some(name) => writeln!(f, "  {} {}: {}", ifd, name, entry.value)?,
+                None => writeln!(f, "  {} 0x{:04X}: {}", ifd, entry.tag, entry.value)?,
+            }
+        }
+        if let Some(thumbnail) = self.thumbnail.as_ref() {
+            writeln!(f, "  Thumbnail: {} bytes", thumbnail.len())?;
+        }
+        if self.strip_thumbnail {
+            writeln!(
+                f,
+                "  Thumbnail: uncompressed strips, dropped if the EXIF data is edited"
+            )?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Big-endian EXIF with Make, Orientation and a GPS IFD holding a latitude
+    fn testing_data() -> Vec<u8> {
+        let mut data = b"MM\0*\0\0\0\x08".to_vec();
+        // IFD0 at 8: three entries, then the next IFD offset, ending at 50
+        data.extend_from_slice(&[0, 3]);
+        data.extend_from_slice(&[0x01, 0x0F, 0, 2, 0, 0, 0, 6, 0, 0, 0, 50]);
+        data.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+        data.extend_from_slice(&[0x88, 0x25, 0, 4, 0, 0, 0, 1, 0, 0, 0, 56]);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(b"Canon\0");
+        // GPS IFD at 56: two entries, ending at 86
+        data.extend_from_slice(&[0, 2]);
+        data.extend_from_slice(&[0, 1, 0, 2, 0, 0, 0, 2, b'N', 0, 0, 0]);
+        data.extend_from_slice(&[0, 2, 0, 5, 0, 0, 0, 3, 0, 0, 0, 86]);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        for (n, d) in [(51u32, 1u32), (30, 1), (2619, 100)] {
+            data.extend_from_slice(&n.to_be_bytes());
+            data.extend_from_slice(&d.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_big_endian() {
+        let exif = Exif::parse(&testing_data()).unwrap();
+        assert!(exif.is_big_endian());
+        assert_eq!(exif.get("Make"), Some(&Value::Ascii(b"Canon\0".to_vec())));
+        assert_eq!(exif.get("Orientation"), Some(&Value::Short(vec![6])));
+        assert_eq!(
+            exif.get("GPSLatitude").unwrap().to_string(),
+            "51/1, 30/1, 2619/100"
+        );
+        assert_eq!(exif.get("GPSLatitudeRef").unwrap().to_string(), "N");
+        assert_eq!(exif.entries().count(), 4);
+    }
+
+    #[test]
+    fn test_round_trip_both_byte_orders() {
+        let mut exif = Exif::parse(&testing_data()).unwrap();
+        assert_eq!(Exif::parse(&exif.to_bytes()).unwrap(), exif);
+
+        exif.order = ByteOrder::Little;
+        exif.ifds[Ifd::Exif.index()] = Some(vec![Entry {
+            tag: 0x920A,
+            value: Value::Rational(vec![(50, 1)]),
+        }]);
+        exif.ifds[Ifd::Thumbnail.index()] = Some(vec![Entry {
+            tag: 0x0103,
+            value: Value::Short(vec![6]),
+        }]);
+        exif.thumbnail = Some(b"\xff\xd8 jpeg \xff\xd9".to_vec());
+        let bytes = exif.to_bytes();
+        assert_eq!(&bytes[..4], b"II*\0");
+        assert_eq!(Exif::parse(&bytes).unwrap(), exif);
+    }
+
+    #[test]
+    fn test_remove_gps_and_orientation() {
+        let mut exif = Exif::parse(&testing_data()).unwrap();
+        assert_eq!(exif.remove_gps(), 2);
+        assert!(exif.remove_orientation());
+        assert!(!exif.remove_orientation());
+
+        let bytes = exif.to_bytes();
+        let reparsed = Exif::parse(&bytes).unwrap();
+        assert_eq!(reparsed.get("GPSLatitude"), None);
+        assert_eq!(reparsed.get("Orientation"), None);
+        assert_eq!(reparsed.entries().count(), 1);
+        // Nothing of the latitude is left behind in the data
+        assert!(!bytes.windows(4).any(|w| w == 2619u32.to_be_bytes()));
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        assert_eq!(
+            Exif::parse(b"JFIF\0\0\0\x08"),
+            Err(ExifError::InvalidHeader)
+        );
+        let data = testing_data();
+        assert_eq!(Exif::parse(&data[..60]), Err(ExifError::Truncated));
+        assert_eq!(Exif::parse(b"II*\0\x08"), Err(ExifError::Truncated));
+        assert_eq!(Exif::parse(b"MM\0*"), Err(ExifError::Truncated));
+
+        // IFD0's next-IFD offset pointing back at itself
+        let mut looped = data.clone();
+        looped[46..50].copy_from_slice(&[0, 0, 0, 8]);
+        assert_eq!(Exif::parse(&looped), Err(ExifError::Loop));
+    }
+
+    #[test]
+    fn test_strip_thumbnail_is_reported() {
+        let mut data = testing_data();
+        let ifd1 = data.len() as u32;
+        data[46..50].copy_from_slice(&ifd1.to_be_bytes());
+        // IFD1 with a single StripOffsets entry
+        data.extend_from_slice(&[0, 1]);
+        data.extend_from_slice(&[0x01, 0x11, 0, 4, 0, 0, 0, 1, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+
+        let exif = Exif::parse(&data).unwrap();
+        assert!(exif.has_strip_thumbnail());
+        assert!(exif.to_string().contains("uncompressed strips"));
+        assert!(!Exif::parse(&testing_data()).unwrap().has_strip_thumbnail());
+    }
+}
@@ -0,0 +1,290 @@
+//! The HDR metadata chunks: mDCV (mastering display color volume) and cLLI (content light
+//! level information), with the value encodings of SMPTE ST 2086 and CTA-861.3.
+//! https://www.w3.org/TR/png-3/#mDCV-chunk
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::color::{check_chunk, ColorError};
+
+/// mDCV chromaticity coordinates are stored in units of 0.00002
+const CHROMATICITY_UNIT: f64 = 0.00002;
+/// mDCV and cLLI luminances are stored in units of 0.0001 cd/m² (nits)
+const LUMINANCE_UNIT: f64 = 0.0001;
+
+fn u16_at(data: &[u8], index: usize) -> u16 {
+    u16::from_be_bytes([data[index], data[index + 1]])
+}
+
+fn u32_at(data: &[u8], index: usize) -> u32 {
+    u32::from_be_bytes([
+        data[index],
+        data[index + 1],
+        data[index + 2],
+        data[index + 3],
+    ])
+}
+
+fn nits(value: u32) -> f64 {
+    value as f64 * LUMINANCE_UNIT
+}
+
+/// Parses comma-separated numbers, checking how many there are
+fn parse_list(s: &str, count: usize, what: &str) -> Result<Vec<f64>, ColorError> {
+    let invalid = || ColorError::InvalidValue(format!("{} {}", what, s));
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<f64>, ColorError>>()?;
+    if values.len() != count || values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+        return Err(invalid());
+    }
+    Ok(values)
+}
+
+/// The mDCV chunk: the color volume of the display the content was mastered on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MasteringDisplay {
+    /// Red, green and blue primaries as (x, y) in units of 0.00002
+    pub primaries: [(u16, u16); 3],
+    /// White point as (x, y) in units of 0.00002
+    pub white_point: (u16, u16),
+    /// In units of 0.0001 cd/m²
+    pub max_luminance: u32,
+    /// In units of 0.0001 cd/m²
+    pub min_luminance: u32,
+}
+
+impl MasteringDisplay {
+    pub const CHUNK_TYPE: &'static str = "mDCV";
+
+    /// Checks the ranges ST 2086 allows: chromaticities up to 1.0, and a maximum
+    /// luminance of at least 1 nit that is above the minimum.
+    fn validate(self) -> Result<Self, ColorError> {
+        let points = self
+            .primaries
+            .iter()
+            .chain(std::iter::once(&self.white_point));
+        if points.into_iter().any(|&(x, y)| x > 50000 || y > 50000) {
+            return Err(ColorError::InvalidValue(
+                "mDCV chromaticity above 1.0".to_string(),
+            ));
+        }
+        if self.max_luminance < 10000 || self.min_luminance >= self.max_luminance {
+            return Err(ColorError::InvalidValue(format!(
+                "mDCV luminance range {:.4}-{} nits",
+                nits(self.min_luminance),
+                nits(self.max_luminance)
+            )));
+        }
+        Ok(self)
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = Vec::with_capacity(24);
+        for (x, y) in self
+            .primaries
+            .iter()
+            .chain(std::iter::once(&self.white_point))
+        {
+            data.extend_from_slice(&x.to_be_bytes());
+            data.extend_from_slice(&y.to_be_bytes());
+        }
+        data.extend_from_slice(&self.max_luminance.to_be_bytes());
+        data.extend_from_slice(&self.min_luminance.to_be_bytes());
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for MasteringDisplay {
+    type Error = ColorError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, ColorError> {
+        let data = check_chunk(chunk, Self::CHUNK_TYPE, 24)?;
+        let point = |i: usize| (u16_at(data, i * 4), u16_at(data, i * 4 + 2));
+        MasteringDisplay {
+            primaries: [point(0), point(1), point(2)],
+            white_point: point(3),
+            max_luminance: u32_at(data, 16),
+            min_luminance: u32_at(data, 20),
+        }
+        .validate()
+    }
+}
+
+impl FromStr for MasteringDisplay {
+    type Err = ColorError;
+
+    /// Parses red, green, blue and white x,y followed by the maximum and minimum
+    /// luminance in nits, all comma-separated
+    fn from_str(s: &str) -> Result<Self, ColorError> {
+        let values = parse_list(s, 10, "mastering display")?;
+        let point = |i: usize| {
+            (
+                (values[i * 2] / CHROMATICITY_UNIT)
+                    .round()
+                    .min(u16::MAX as f64) as u16,
+                (values[i * 2 + 1] / CHROMATICITY_UNIT)
+                    .round()
+                    .min(u16::MAX as f64) as u16,
+            )
+        };
+        let luminance = |v: f64| (v / LUMINANCE_UNIT).round().min(u32::MAX as f64) as u32;
+        MasteringDisplay {
+            primaries: [point(0), point(1), point(2)],
+            white_point: point(3),
+            max_luminance: luminance(values[8]),
+            min_luminance: luminance(values[9]),
+        }
+        .validate()
+    }
+}
+
+impl fmt::Display for MasteringDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = ["red", "green", "blue", "white"];
+        let points = self
+            .primaries
+            .iter()
+            .chain(std::iter::once(&self.white_point));
+        for (name, (x, y)) in names.iter().zip(points) {
+            write!(
+                f,
+                "{} ({:.4}, {:.4}), ",
+                name,
+                *x as f64 * CHROMATICITY_UNIT,
+                *y as f64 * CHROMATICITY_UNIT
+            )?;
+        }
+        write!(
+            f,
+            "luminance {:.4}-{} nits",
+            nits(self.min_luminance),
+            nits(self.max_luminance)
+        )
+    }
+}
+
+/// The cLLI chunk: the brightest pixel and brightest frame average of the content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentLightLevel {
+    /// Maximum content light level in units of 0.0001 cd/m², 0 if unknown
+    pub max_cll: u32,
+    /// Maximum frame-average light level in units of 0.0001 cd/m², 0 if unknown
+    pub max_fall: u32,
+}
+
+impl ContentLightLevel {
+    pub const CHUNK_TYPE: &'static str = "cLLI";
+
+    /// A frame's average can't be brighter than its brightest pixel
+    fn validate(self) -> Result<Self, ColorError> {
+        if self.max_cll != 0 && self.max_fall > self.max_cll {
+            return Err(ColorError::InvalidValue(format!(
+                "cLLI MaxFALL {} nits above MaxCLL {} nits",
+                nits(self.max_fall),
+                nits(self.max_cll)
+            )));
+        }
+        Ok(self)
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.max_cll.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.max_fall.to_be_bytes());
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for ContentLightLevel {
+    type Error = ColorError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, ColorError> {
+        let data = check_chunk(chunk, Self::CHUNK_TYPE, 8)?;
+        ContentLightLevel {
+            max_cll: u32_at(data, 0),
+            max_fall: u32_at(data, 4),
+        }
+        .validate()
+    }
+}
+
+impl FromStr for ContentLightLevel {
+    type Err = ColorError;
+
+    /// Parses `MaxCLL,MaxFALL` in nits
+    fn from_str(s: &str) -> Result<Self, ColorError> {
+        let values = parse_list(s, 2, "content light level")?;
+        let luminance = |v: f64| (v / LUMINANCE_UNIT).round().min(u32::MAX as f64) as u32;
+        ContentLightLevel {
+            max_cll: luminance(values[0]),
+            max_fall: luminance(values[1]),
+        }
+        .validate()
+    }
+}
+
+impl fmt::Display for ContentLightLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = |value: u32| match value {
+            0 => "unknown".to_string(),
+            _ => format!("{} nits", nits(value)),
+        };
+        write!(
+            f,
+            "MaxCLL {}, MaxFALL {}",
+            level(self.max_cll),
+            level(self.max_fall)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const P3_1000: &str = "0.68,0.32,0.265,0.69,0.15,0.06,0.3127,0.329,1000,0.005";
+
+    #[test]
+    fn test_mastering_display() {
+        let mdcv = MasteringDisplay::from_str(P3_1000).unwrap();
+        assert_eq!(mdcv.primaries[0], (34000, 16000));
+        assert_eq!(mdcv.max_luminance, 10_000_000);
+        assert_eq!(mdcv.min_luminance, 50);
+        assert_eq!(MasteringDisplay::try_from(&mdcv.to_chunk()).unwrap(), mdcv);
+        assert_eq!(
+            mdcv.to_string(),
+            "red (0.6800, 0.3200), green (0.2650, 0.6900), blue (0.1500, 0.0600), \
+             white (0.3127, 0.3290), luminance 0.0050-1000 nits"
+        );
+    }
+
+    #[test]
+    fn test_mastering_display_ranges() {
+        assert!(MasteringDisplay::from_str(
+            "1.2,0.32,0.265,0.69,0.15,0.06,0.3127,0.329,1000,0.005"
+        )
+        .is_err());
+        assert!(
+            MasteringDisplay::from_str("0.68,0.32,0.265,0.69,0.15,0.06,0.3127,0.329,1,2").is_err()
+        );
+        assert!(MasteringDisplay::from_str("0.68,0.32").is_err());
+        let chunk = Chunk::new(ChunkType::from_str("mDCV").unwrap(), vec![0; 23]);
+        assert!(MasteringDisplay::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_content_light_level() {
+        let clli = ContentLightLevel::from_str("1000,400").unwrap();
+        assert_eq!(ContentLightLevel::try_from(&clli.to_chunk()).unwrap(), clli);
+        assert_eq!(clli.to_string(), "MaxCLL 1000 nits, MaxFALL 400 nits");
+        assert!(ContentLightLevel::from_str("400,1000").is_err());
+        assert_eq!(
+            ContentLightLevel::from_str("0,400").unwrap().to_string(),
+            "MaxCLL unknown, MaxFALL 400 nits"
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! The iCCP chunk, which embeds a zlib-compressed ICC color profile.
+//! https://www.color.org/specification/ICC.1-2022-05.pdf
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::ColorType;
+use crate::image::{deflate, inflate};
+use crate::text::is_valid_keyword;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum IccError {
+    #[error("Expected an iCCP chunk, found {0}")]
+    WrongChunkType(String),
+    #[error("iCCP profile name must be 1 to 79 printable Latin-1 characters")]
+    InvalidName,
+    #[error("Unknown iCCP compression method {0}")]
+    UnknownCompression(u8),
+    #[error("Compressed ICC profile is corrupt")]
+    CorruptCompression,
+    #[error("ICC profile is too short to hold a header")]
+    TooShort,
+    #[error("ICC header gives the profile size as {header} bytes but it is {actual}")]
+    SizeMismatch { header: u32, actual: usize },
+    #[error("ICC profile is missing the 'acsp' signature")]
+    MissingSignature,
+    #[error("ICC version {0} profiles can't be used in PNG")]
+    UnsupportedVersion(u8),
+    #[error("Unknown ICC profile class '{0}'")]
+    UnknownClass(String),
+    #[error("PNG only allows RGB and GRAY ICC profiles, found '{0}'")]
+    UnsupportedColorSpace(String),
+    #[error("A {profile} profile can't describe a {color_type} image")]
+    ColorTypeMismatch {
+        profile: String,
+        color_type: ColorType,
+    },
+}
+
+const HEADER_LENGTH: usize = 128;
+
+/// Largest profile an iCCP chunk is decompressed to. Real profiles are a few megabytes
+/// at most; anything larger is treated as a deflate bomb.
+const MAX_PROFILE_LENGTH: usize = 32 << 20;
+
+const CLASSES: [(&[u8; 4], &str); 7] = [
+    (b"scnr", "input"),
+    (b"mntr", "display"),
+    (b"prtr", "output"),
+    (b"link", "device link"),
+    (b"spac", "color space"),
+    (b"abst", "abstract"),
+    (b"nmcl", "named color"),
+];
+
+/// The fields of an ICC profile header that matter to PNG
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IccHeader {
+    pub size: u32,
+    pub major_version: u8,
+    pub minor_version: u8,
+    /// Profile class signature, such as `mntr`
+    pub class: [u8; 4],
+    /// Data color space signature, `RGB ` or `GRAY`
+    pub color_space: [u8; 4],
+}
+
+impl IccHeader {
+    /// Parses and validates the header of an ICC profile
+    pub fn parse(profile: &[u8]) -> Result<Self, IccError> {
+        if profile.len() < HEADER_LENGTH {
+            return Err(IccError::TooShort);
+        }
+        let size = u32::from_be_bytes([profile[0], profile[1], profile[2], profile[3]]);
+        if size as usize != profile.len() {
+            return Err(IccError::SizeMismatch {
+                header: size,
+                actual: profile.len(),
+            });
+        }
+        if &profile[36..40] != b"acsp" {
+            return Err(IccError::MissingSignature);
+        }
+        let major_version = profile[8];
+        if !(2..=4).contains(&major_version) {
+            return Err(IccError::UnsupportedVersion(major_version));
+        }
+        let signature = |start: usize| -> [u8; 4] {
+            [
+                profile[start],
+                profile[start + 1],
+                profile[start + 2],
+                profile[start + 3],
+            ]
+        };
+        let class = signature(12);
+        if !CLASSES.iter().any(|(s, _)| **s == class) {
+            return Err(IccError::UnknownClass(
+                String::from_utf8_lossy(&class).into(),
+            ));
+        }
+        let color_space = signature(16);
+        if &color_space != b"RGB " && &color_space != b"GRAY" {
+            return Err(IccError::UnsupportedColorSpace(
+                String::from_utf8_lossy(&color_space).into(),
+            ));
+        }
+        Ok(IccHeader {
+            size,
+            major_version,
+            minor_version: profile[9] >> 4,
+            class,
+            color_space,
+        })
+    }
+
+    pub fn is_gray(&self) -> bool {
+        &self.color_space == b"GRAY"
+    }
+
+    /// Checks that the profile's color space suits images of `color_type`
+    pub fn check_color_type(&self, color_type: ColorType) -> Result<(), IccError> {
+        let gray_image = matches!(color_type, ColorType::Grayscale | ColorType::GrayscaleAlpha);
+        if self.is_gray() != gray_image {
+            return Err(IccError::ColorTypeMismatch {
+                profile: String::from_utf8_lossy(&self.color_space).trim().into(),
+                color_type,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for IccHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let class = CLASSES
+            .iter()
+            .find(|(s, _)| **s == self.class)
+            .map_or("unknown", |(_, name)| *name);
+        write!(
+            f,
+            "ICC v{}.{} {} {} profile",
+            self.major_version,
+            self.minor_version,
+            class,
+            String::from_utf8_lossy(&self.color_space).trim()
+        )
+    }
+}
+
+/// Reads the profile description from the `desc` tag, in either the version 2
+/// `textDescriptionType` or the version 4 `multiLocalizedUnicodeType` format.
+pub fn description(profile: &[u8]) -> Option<String> {
+    let u32_at = |offset: usize| -> Option<u32> {
+        let bytes = profile.get(offset..offset + 4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let tag_count = u32_at(HEADER_LENGTH)? as usize;
+    let (offset, size) = (0..tag_count.min(1024)).find_map(|i| {
+        let entry = HEADER_LENGTH + 4 + 12 * i;
+        match profile.get(entry..entry + 4)? {
+            b"desc" => Some((u32_at(entry + 4)? as usize, u32_at(entry + 8)? as usize)),
+            _ => None,
+        }
+    })?;
+    let tag = profile.get(offset..offset.checked_add(size)?)?;
+    match tag.get(..4)? {
+        b"desc" => {
+            let length = u32_at(offset + 8)? as usize;
+            let ascii = tag.get(12..12 + length)?;
+            let end = ascii.iter().position(|&b| b == 0).unwrap_or(ascii.len());
+            Some(String::from_utf8_lossy(&ascii[..end]).into_owned())
+        }
+        b"mluc" => {
+            // The first record is used; it is normally English
+            if u32_at(offset + 8)? == 0 {
+                return None;
+            }
+            let length = u32_at(offset + 20)? as usize;
+            let start = u32_at(offset + 24)? as usize;
+            let units: Vec<u16> = tag
+                .get(start..start + length)?
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .collect();
+            let text = String::from_utf16_lossy(&units);
+            Some(text.trim_end_matches('\0').to_string())
+        }
+        _ => None,
+    }
+}
+
+/// The contents of an iCCP chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IccProfile {
+    /// Name of the profile within the PNG, a Latin-1 keyword
+    pub name: String,
+    /// The uncompressed ICC profile
+    pub profile: Vec<u8>,
+}
+
+impl IccProfile {
+    pub const CHUNK_TYPE: &'static str = "iCCP";
+
+    /// Wraps an ICC profile, validating its header. The name defaults to the profile's
+    /// description when that is a valid keyword.
+    pub fn new(name: Option<&str>, profile: Vec<u8>) -> Result<Self, IccError> {
+        IccHeader::parse(&profile)?;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => description(&profile)
+                .filter(|d| is_valid_keyword(d))
+                .unwrap_or_else(|| "ICC profile".to_string()),
+        };
+        if !is_valid_keyword(&name) {
+            return Err(IccError::InvalidName);
+        }
+        Ok(IccProfile { name, profile })
+    }
+
+    pub fn header(&self) -> Result<IccHeader, IccError> {
+        IccHeader::parse(&self.profile)
+    }
+
+    pub fn description(&self) -> Option<String> {
+        description(&self.profile)
+    }
+
+    /// Builds the iCCP chunk, compressing the profile
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data: Vec<u8> = self.name.chars().map(|c| c as u8).collect();
+        data.push(0);
+        data.push(0);
+        data.extend(deflate(&self.profile, 9));
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for IccProfile {
+    type Error = IccError;
+
+    /// Decompresses the profile without validating it, so damaged profiles can still be
+    /// extracted and inspected.
+    fn try_from(chunk: &Chunk) -> Result<Self, IccError> {
+        if chunk.chunk_type().to_string() != Self::CHUNK_TYPE {
+            return Err(IccError::WrongChunkType(chunk.chunk_type().to_string()));
+        }
+        let data = chunk.data();
+        let separator = data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(IccError::InvalidName)?;
+        let name: String = data[..separator].iter().map(|&b| b as char).collect();
+        if !is_valid_keyword(&name) {
+            return Err(IccError::InvalidName);
+        }
+        match data.get(separator + 1) {
+            Some(0) => {}
+            Some(&method) => return Err(IccError::UnknownCompression(method)),
+            None => return Err(IccError::CorruptCompression),
+        }
+        let profile = inflate(&data[separator + 2..], MAX_PROFILE_LENGTH)
+            .map_err(|_| IccError::CorruptCompression)?;
+        Ok(IccProfile { name, profile })
+    }
+}
+
+impl fmt::Display for IccProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.header() {
+            Ok(header) => write!(f, "{}", header)?,
+            Err(e) => write!(f, "invalid ICC profile ({})", e)?,
+        }
+        match self.description() {
+            Some(description) => write!(f, " \"{}\"", description),
+            None => write!(f, " named \"{}\"", self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal version 2 display profile with a `desc` tag
+    fn testing_profile(color_space: &[u8; 4]) -> Vec<u8> {
+        let mut profile = vec![0u8; HEADER_LENGTH];
+        profile[8] = 2;
+        profile[9] = 0x10;
+        profile[12..16].copy_from_slice(b"mntr");
+        profile[16..20].copy_from_slice(color_space);
+        profile[36..40].copy_from_slice(b"acsp");
+        profile.extend_from_slice(&1u32.to_be_bytes());
+        profile.extend_from_slice(b"desc");
+        profile.extend_from_slice(&144u32.to_be_bytes());
+        let text = b"Test RGB\0";
+        let size = 12 + text.len() as u32;
+        profile.extend_from_slice(&size.to_be_bytes());
+        profile.extend_from_slice(b"desc\0\0\0\0");
+        profile.extend_from_slice(&(text.len() as u32).to_be_bytes());
+        profile.extend_from_slice(text);
+        let length = profile.len() as u32;
+        profile[..4].copy_from_slice(&length.to_be_bytes());
+        profile
+    }
+
+    #[test]
+    fn test_header_and_description() {
+        let profile = IccProfile::new(None, testing_profile(b"RGB ")).unwrap();
+        assert_eq!(profile.name, "Test RGB");
+        assert_eq!(
+            profile.to_string(),
+            "ICC v2.1 display RGB profile \"Test RGB\""
+        );
+        let header = profile.header().unwrap();
+        assert!(header.check_color_type(ColorType::Rgba).is_ok());
+        assert!(header.check_color_type(ColorType::Grayscale).is_err());
+    }
+
+    #[test]
+    fn test_chunk_round_trip() {
+        let profile = IccProfile::new(Some("Custom"), testing_profile(b"GRAY")).unwrap();
+        let chunk = profile.to_chunk();
+        assert!(chunk.length() < profile.profile.len() as u32);
+        assert_eq!(IccProfile::try_from(&chunk).unwrap(), profile);
+
+        // to_chunk does not validate, so it can build an oversized profile
+        let bomb = IccProfile {
+            name: "Bomb".to_string(),
+            profile: vec![0; MAX_PROFILE_LENGTH + 1],
+        };
+        assert_eq!(
+            IccProfile::try_from(&bomb.to_chunk()),
+            Err(IccError::CorruptCompression)
+        );
+    }
+
+    #[test]
+    fn test_mluc_description() {
+        let mut profile = testing_profile(b"RGB ");
+        profile.truncate(144);
+        let text: Vec<u8> = "Wide ©"
+            .encode_utf16()
+            .flat_map(|u| u.to_be_bytes())
+            .collect();
+        profile.extend_from_slice(b"mluc\0\0\0\0");
+        profile.extend_from_slice(&1u32.to_be_bytes());
+        profile.extend_from_slice(&12u32.to_be_bytes());
+        profile.extend_from_slice(b"enUS");
+        profile.extend_from_slice(&(text.len() as u32).to_be_bytes());
+        profile.extend_from_slice(&28u32.to_be_bytes());
+        profile.extend_from_slice(&text);
+        let size = (profile.len() - 144) as u32;
+        profile[140..144].copy_from_slice(&size.to_be_bytes());
+        assert_eq!(description(&profile).unwrap(), "Wide ©");
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        let profile = testing_profile(b"RGB ");
+        assert_eq!(IccHeader::parse(&profile[..100]), Err(IccError::TooShort));
+        assert!(matches!(
+            IccHeader::parse(&profile[..140]),
+            Err(IccError::SizeMismatch { .. })
+        ));
+        let mut cmyk = profile.clone();
+        cmyk[16..20].copy_from_slice(b"CMYK");
+        assert!(matches!(
+            IccHeader::parse(&cmyk),
+            Err(IccError::UnsupportedColorSpace(_))
+        ));
+        let mut v5 = profile;
+        v5[8] = 5;
+        assert_eq!(IccHeader::parse(&v5), Err(IccError::UnsupportedVersion(5)));
+    }
+}
@@ -0,0 +1,233 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum IhdrError {
+    #[error("Expected an IHDR chunk, found {0}")]
+    WrongChunkType(String),
+    #[error("IHDR data must be 13 bytes long, found {0}")]
+    InvalidLength(usize),
+    #[error("Image width and height must be non-zero")]
+    ZeroDimension,
+    #[error("Invalid color type {0}")]
+    InvalidColorType(u8),
+    #[error("Bit depth {bit_depth} is not allowed for color type {color_type}")]
+    InvalidBitDepth { color_type: u8, bit_depth: u8 },
+    #[error("Unknown compression, filter or interlace method")]
+    UnknownMethod,
+}
+
+/// The color types allowed by the PNG spec
+/// http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IHDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    /// The value stored in the IHDR color type byte
+    pub fn value(&self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    /// Number of samples that make up a single pixel
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// Bit depths the spec allows for this color type
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba)
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = IhdrError;
+
+    fn try_from(value: u8) -> Result<Self, IhdrError> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(IhdrError::InvalidColorType(value)),
+        }
+    }
+}
+
+impl fmt::Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale+alpha",
+            ColorType::Rgba => "RGBA",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The contents of the IHDR chunk, which must be the first chunk of every PNG
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+
+impl Ihdr {
+    pub const CHUNK_TYPE: &'static str = "IHDR";
+
+    /// Number of bits used by a single pixel
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    /// Distance in bytes between corresponding bytes of neighbouring pixels, as used by
+    /// the scanline filters. Always at least 1.
+    pub fn filter_bpp(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    /// Number of bytes in an unfiltered scanline `width` pixels wide
+    pub fn row_bytes(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// Builds the IHDR chunk for this header
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(13);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(self.color_type.value());
+        data.push(0);
+        data.push(0);
+        data.push(self.interlaced as u8);
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = IhdrError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, IhdrError> {
+        if chunk.chunk_type().to_string() != Self::CHUNK_TYPE {
+            return Err(IhdrError::WrongChunkType(chunk.chunk_type().to_string()));
+        }
+        let data = chunk.data();
+        if data.len() != 13 {
+            return Err(IhdrError::InvalidLength(data.len()));
+        }
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        if width == 0 || height == 0 {
+            return Err(IhdrError::ZeroDimension);
+        }
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])?;
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(IhdrError::InvalidBitDepth {
+                color_type: data[9],
+                bit_depth,
+            });
+        }
+        if data[10] != 0 || data[11] != 0 || data[12] > 1 {
+            return Err(IhdrError::UnknownMethod);
+        }
+        Ok(Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced: data[12] == 1,
+        })
+    }
+}
+
+impl fmt::Display for Ihdr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}, {}-bit {}{}",
+            self.width,
+            self.height,
+            self.bit_depth,
+            self.color_type,
+            if self.interlaced { ", interlaced" } else { "" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr_chunk(data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_ihdr_from_chunk() {
+        let chunk = ihdr_chunk(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 0]);
+        let ihdr = Ihdr::try_from(&chunk).unwrap();
+        assert_eq!(ihdr.width, 50);
+        assert_eq!(ihdr.height, 40);
+        assert_eq!(ihdr.bit_depth, 8);
+        assert_eq!(ihdr.color_type, ColorType::Rgba);
+        assert!(!ihdr.interlaced);
+    }
+
+    #[test]
+    fn test_ihdr_round_trip() {
+        let chunk = ihdr_chunk(&[0, 0, 1, 0, 0, 0, 0, 7, 4, 3, 0, 0, 1]);
+        let ihdr = Ihdr::try_from(&chunk).unwrap();
+        assert_eq!(ihdr.to_chunk().as_bytes(), chunk.as_bytes());
+    }
+
+    #[test]
+    fn test_ihdr_invalid_bit_depth() {
+        let chunk = ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 4, 2, 0, 0, 0]);
+        assert!(Ihdr::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_ihdr_row_bytes() {
+        let chunk = ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 2, 3, 0, 0, 0]);
+        let ihdr = Ihdr::try_from(&chunk).unwrap();
+        assert_eq!(ihdr.row_bytes(5), 2);
+        assert_eq!(ihdr.filter_bpp(), 1);
+    }
+}
@@ -0,0 +1,801 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::palette::Palette;
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// Origin and spacing of the seven Adam7 passes as (x, y, dx, dy)
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// How the filter type of each scanline is chosen when encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// The same filter type, 0 to 4, for every row
+    Fixed(u8),
+    /// Per row, the filter with the smallest sum of absolute differences
+    MinSum,
+    /// No filtering for indexed and sub-byte images and `MinSum` otherwise, as the spec
+    /// recommends
+    Recommended,
+}
+
+impl FilterStrategy {
+    /// Every strategy worth trying when looking for the smallest output
+    pub const CANDIDATES: [FilterStrategy; 6] = [
+        FilterStrategy::Fixed(0),
+        FilterStrategy::Fixed(1),
+        FilterStrategy::Fixed(2),
+        FilterStrategy::Fixed(3),
+        FilterStrategy::Fixed(4),
+        FilterStrategy::MinSum,
+    ];
+}
+
+impl fmt::Display for FilterStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterStrategy::Fixed(0) => f.write_str("none"),
+            FilterStrategy::Fixed(1) => f.write_str("sub"),
+            FilterStrategy::Fixed(2) => f.write_str("up"),
+            FilterStrategy::Fixed(3) => f.write_str("average"),
+            FilterStrategy::Fixed(_) => f.write_str("paeth"),
+            FilterStrategy::MinSum => f.write_str("min-sum"),
+            FilterStrategy::Recommended => f.write_str("recommended"),
+        }
+    }
+}
+
+/// Decompresses a zlib stream, failing rather than produce more than `limit` bytes.
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    ZlibDecoder::new(data)
+        .take((limit as u64).saturating_add(1))
+        .read_to_end(&mut decoded)?;
+    if decoded.len() > limit {
+        return Err(Error::from(format!(
+            "Data decompresses to more than {} bytes",
+            limit
+        )));
+    }
+    Ok(decoded)
+}
+
+/// How many decompressed bytes past the end of the last scanline are read before
+/// giving up on the image data, which keeps deflate bombs from exhausting memory.
+pub const INFLATE_SLACK: usize = 16 << 20;
+
+/// Decompresses the zlib stream at the start of `data`, also returning how many bytes of
+/// `data` the stream occupied. Anything past that point is ignored by decoders.
+///
+/// Decompression stops once more than `limit` bytes have been produced, in which case the
+/// stream length is `None` and only the first `limit + 1` bytes are returned.
+pub fn inflate_prefix(data: &[u8], limit: usize) -> Result<(Vec<u8>, Option<usize>)> {
+    let cap = limit.saturating_add(1);
+    let mut decompress = Decompress::new(true);
+    let mut decoded = Vec::with_capacity((data.len() * 4 + 64).min(cap));
+    loop {
+        if decoded.len() >= cap {
+            decoded.truncate(cap);
+            return Ok((decoded, None));
+        }
+        if decoded.len() == decoded.capacity() {
+            decoded.reserve_exact(decoded.capacity().min(cap - decoded.len()));
+        }
+        let (read, written) = (decompress.total_in(), decompress.total_out());
+        let status = decompress.decompress_vec(
+            &data[read as usize..],
+            &mut decoded,
+            FlushDecompress::None,
+        )?;
+        if status == Status::StreamEnd && decoded.len() <= limit {
+            return Ok((decoded, Some(decompress.total_in() as usize)));
+        }
+        let stalled = decompress.total_in() == read && decompress.total_out() == written;
+        if stalled && decoded.len() < decoded.capacity() {
+            return Err(Error::from("Compressed image data is truncated"));
+        }
+    }
+}
+
+/// Decompresses the image data of an image described by `ihdr`, also returning how many
+/// bytes of `data` the zlib stream occupied. Fails rather than keep going once the output
+/// runs [`INFLATE_SLACK`] bytes past the last scanline.
+pub fn inflate_image_data(data: &[u8], ihdr: &Ihdr) -> Result<(Vec<u8>, usize)> {
+    let limit = filtered_len(ihdr)?.saturating_add(INFLATE_SLACK);
+    match inflate_prefix(data, limit)? {
+        (filtered, Some(used)) => Ok((filtered, used)),
+        (_, None) => Err(Error::from(
+            "Image data decompresses to far more than the image needs",
+        )),
+    }
+}
+
+/// Compresses `data` into a zlib stream at the given level (0-9).
+pub fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+    encoder
+        .write_all(data)
+        .expect("writing to a Vec cannot fail");
+    encoder.finish().expect("writing to a Vec cannot fail")
+}
+
+/// The decoded samples of a PNG: unfiltered, de-interlaced scanlines stored one after
+/// another without filter bytes, in the bit depth and color type given by the IHDR.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawImage {
+    ihdr: Ihdr,
+    data: Vec<u8>,
+}
+
+impl RawImage {
+    /// Creates a `RawImage` from packed scanlines matching `ihdr`.
+    pub fn new(ihdr: Ihdr, data: Vec<u8>) -> Result<Self> {
+        let expected = raw_len(&ihdr)?;
+        if data.len() != expected {
+            return Err(Error::from(format!(
+                "Image data is {} bytes, expected {}",
+                data.len(),
+                expected
+            )));
+        }
+        Ok(RawImage { ihdr, data })
+    }
+
+    /// Decodes the image stored in the IDAT chunks of `png`.
+    pub fn from_png(png: &Png) -> Result<Self> {
+        let ihdr = png.ihdr()?;
+        let (filtered, _) = inflate_image_data(&png.image_data(), &ihdr)?;
+        Self::from_filtered(ihdr, &filtered)
+    }
+
+    /// Decodes decompressed IDAT contents: filtered scanlines, possibly Adam7 interlaced.
+    pub fn from_filtered(ihdr: Ihdr, filtered: &[u8]) -> Result<Self> {
+        // The header is untrusted, so make sure the data is there before allocating
+        if filtered.len() < filtered_len(&ihdr)? {
+            return Err(Error::from("Image data is truncated"));
+        }
+        if !ihdr.interlaced {
+            let data = unfilter(&ihdr, ihdr.width, ihdr.height, filtered)?;
+            return RawImage::new(ihdr, data);
+        }
+
+        let mut image = RawImage::blank(ihdr.clone());
+        let mut offset = 0;
+        for (pass_x, pass_y, dx, dy) in ADAM7 {
+            let (width, height) = pass_size(&ihdr, pass_x, pass_y, dx, dy);
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let length = (ihdr.row_bytes(width as u32) + 1) * height;
+            let end = offset + length;
+            if end > filtered.len() {
+                return Err(Error::from("Image data is truncated"));
+            }
+            let pass = RawImage {
+                ihdr: Ihdr {
+                    width: width as u32,
+                    height: height as u32,
+                    interlaced: false,
+                    ..ihdr.clone()
+                },
+                data: unfilter(&ihdr, width as u32, height as u32, &filtered[offset..end])?,
+            };
+            for y in 0..height {
+                for x in 0..width {
+                    image.copy_pixel(&pass, x, y, pass_x + x * dx, pass_y + y * dy);
+                }
+            }
+            offset = end;
+        }
+        Ok(image)
+    }
+
+    /// Creates an image with every sample set to zero.
+    ///
+    /// Panics if the size overflows; decoders check an untrusted header against the
+    /// decompressed data with [`filtered_len`] first.
+    pub fn blank(ihdr: Ihdr) -> Self {
+        let data = vec![0; raw_len(&ihdr).expect("image size overflows")];
+        RawImage { ihdr, data }
+    }
+
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    pub fn width(&self) -> usize {
+        self.ihdr.width as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.ihdr.height as usize
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Number of bytes in one row of this image
+    pub fn stride(&self) -> usize {
+        self.ihdr.row_bytes(self.ihdr.width)
+    }
+
+    /// Returns the value of `channel` of the pixel at (`x`, `y`) in the image's bit depth.
+    pub fn sample(&self, x: usize, y: usize, channel: usize) -> u16 {
+        let row = &self.data[y * self.stride()..(y + 1) * self.stride()];
+        let index = x * self.ihdr.color_type.channels() + channel;
+        match self.ihdr.bit_depth {
+            16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]),
+            8 => row[index] as u16,
+            bits => {
+                let bits = bits as usize;
+                let bit = index * bits;
+                let shift = 8 - bits - bit % 8;
+                ((row[bit / 8] >> shift) & ((1 << bits) - 1) as u8) as u16
+            }
+        }
+    }
+
+    /// Sets `channel` of the pixel at (`x`, `y`). `value` must fit in the image's bit depth.
+    pub fn set_sample(&mut self, x: usize, y: usize, channel: usize, value: u16) {
+        let stride = self.stride();
+        let row = &mut self.data[y * stride..(y + 1) * stride];
+        let index = x * self.ihdr.color_type.channels() + channel;
+        match self.ihdr.bit_depth {
+            16 => row[2 * index..2 * index + 2].copy_from_slice(&value.to_be_bytes()),
+            8 => row[index] = value as u8,
+            bits => {
+                let bits = bits as usize;
+                let bit = index * bits;
+                let shift = 8 - bits - bit % 8;
+                let mask = (((1 << bits) - 1) << shift) as u8;
+                row[bit / 8] = (row[bit / 8] & !mask) | (((value as u8) << shift) & mask);
+            }
+        }
+    }
+
+    /// Compresses this image into the contents of an IDAT stream, interlacing it if the
+    /// IHDR asks for it.
+    pub fn encode(&self, level: u32) -> Vec<u8> {
+        deflate(&self.filtered(), level)
+    }
+
+    /// Like [`RawImage::encode`], choosing scanline filters with `strategy`.
+    pub fn encode_with(&self, strategy: FilterStrategy, level: u32) -> Vec<u8> {
+        deflate(&self.filtered_with(strategy), level)
+    }
+
+    /// Filters the scanlines of this image, producing the uncompressed IDAT contents.
+    pub fn filtered(&self) -> Vec<u8> {
+        self.filtered_with(FilterStrategy::Recommended)
+    }
+
+    /// Filters the scanlines of this image with the given strategy.
+    pub fn filtered_with(&self, strategy: FilterStrategy) -> Vec<u8> {
+        if !self.ihdr.interlaced {
+            return filter(&self.ihdr, self.ihdr.width, &self.data, strategy);
+        }
+
+        let mut filtered = Vec::new();
+        for (pass_x, pass_y, dx, dy) in ADAM7 {
+            let (width, height) = pass_size(&self.ihdr, pass_x, pass_y, dx, dy);
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let mut pass = RawImage::blank(Ihdr {
+                width: width as u32,
+                height: height as u32,
+                interlaced: false,
+                ..self.ihdr.clone()
+            });
+            for y in 0..height {
+                for x in 0..width {
+                    pass.copy_pixel(self, pass_x + x * dx, pass_y + y * dy, x, y);
+                }
+            }
+            filtered.extend(filter(&self.ihdr, width as u32, &pass.data, strategy));
+        }
+        filtered
+    }
+
+    fn copy_pixel(&mut self, from: &RawImage, from_x: usize, from_y: usize, x: usize, y: usize) {
+        for channel in 0..self.ihdr.color_type.channels() {
+            let value = from.sample(from_x, from_y, channel);
+            self.set_sample(x, y, channel, value);
+        }
+    }
+}
+
+/// An image expanded to 16-bit RGBA, a format every PNG can be converted to without loss.
+/// Palettes are looked up and tRNS transparency is applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rgba16Image {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels in row-major order
+    pub pixels: Vec<[u16; 4]>,
+}
+
+impl Rgba16Image {
+    /// Decodes the image data of `png` and expands it to 16-bit RGBA.
+    pub fn from_png(png: &Png) -> Result<Self> {
+        let image = RawImage::from_png(png)?;
+        let ihdr = image.ihdr().clone();
+        let trns = png.chunk_by_type("tRNS").map(|c| c.data().to_vec());
+        let max = ((1u32 << ihdr.bit_depth) - 1) as u16;
+        let scale = |value: u16| (value as u32 * 65535 / max as u32) as u16;
+
+        let palette: Vec<[u16; 4]> = match ihdr.color_type {
+            ColorType::Indexed => Palette::from_png(png)?
+                .entries
+                .iter()
+                .map(|entry| entry.map(|v| v as u16 * 257))
+                .collect(),
+            _ => Vec::new(),
+        };
+        // Color key from tRNS for grayscale and RGB images, in the image's bit depth
+        let key: Option<Vec<u16>> = trns.filter(|_| !ihdr.color_type.has_alpha()).and_then(|t| {
+            let samples: Vec<u16> = t
+                .chunks_exact(2)
+                .map(|s| u16::from_be_bytes([s[0], s[1]]))
+                .collect();
+            match ihdr.color_type {
+                ColorType::Grayscale if samples.len() == 1 => Some(samples),
+                ColorType::Rgb if samples.len() == 3 => Some(samples),
+                _ => None,
+            }
+        });
+
+        let mut pixels = Vec::with_capacity(image.width() * image.height());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let sample = |channel| image.sample(x, y, channel);
+                let pixel = match ihdr.color_type {
+                    ColorType::Indexed => *palette
+                        .get(sample(0) as usize)
+                        .ok_or("Pixel refers to a palette entry that does not exist")?,
+                    ColorType::Grayscale | ColorType::Rgb => {
+                        let channels: Vec<u16> =
+                            (0..ihdr.color_type.channels()).map(sample).collect();
+                        let alpha = if key.as_ref() == Some(&channels) {
+                            0
+                        } else {
+                            65535
+                        };
+                        match channels[..] {
+                            [gray] => [scale(gray), scale(gray), scale(gray), alpha],
+                            _ => [
+                                scale(channels[0]),
+                                scale(channels[1]),
+                                scale(channels[2]),
+                                alpha,
+                            ],
+                        }
+                    }
+                    ColorType::GrayscaleAlpha => {
+                        let gray = scale(sample(0));
+                        [gray, gray, gray, scale(sample(1))]
+                    }
+                    ColorType::Rgba => [0, 1, 2, 3].map(|c| scale(sample(c))),
+                };
+                pixels.push(pixel);
+            }
+        }
+        Ok(Rgba16Image {
+            width: ihdr.width,
+            height: ihdr.height,
+            pixels,
+        })
+    }
+
+    /// Creates a fully transparent image.
+    pub fn blank(width: u32, height: u32) -> Self {
+        Rgba16Image {
+            width,
+            height,
+            pixels: vec![[0; 4]; width as usize * height as usize],
+        }
+    }
+
+    /// Encodes the image as a non-interlaced RGBA PNG with 8 or 16 bits per sample.
+    pub fn to_png(&self, bit_depth: u8) -> Png {
+        let ihdr = Ihdr {
+            width: self.width,
+            height: self.height,
+            bit_depth,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+        let image = self.to_raw(ihdr.clone());
+        let mut png = Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        png.set_image_data(image.encode(9));
+        png
+    }
+
+    /// Stores the pixels in the grayscale or truecolor format of `ihdr`, rounding each
+    /// sample to its bit depth. Grayscale takes the red channel, so the image should
+    /// already be gray.
+    pub fn to_raw(&self, ihdr: Ihdr) -> RawImage {
+        let max = (1u32 << ihdr.bit_depth) - 1;
+        let channels: &[usize] = match ihdr.color_type {
+            ColorType::Grayscale => &[0],
+            ColorType::GrayscaleAlpha => &[0, 3],
+            ColorType::Rgb => &[0, 1, 2],
+            _ => &[0, 1, 2, 3],
+        };
+        let mut image = RawImage::blank(ihdr);
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            let (x, y) = (i % image.width(), i / image.width());
+            for (channel, &source) in channels.iter().enumerate() {
+                let value = (pixel[source] as u32 * max + 32767) / 65535;
+                image.set_sample(x, y, channel, value as u16);
+            }
+        }
+        image
+    }
+}
+
+/// Size of the decompressed IDAT contents for an image described by `ihdr`, including
+/// the filter byte of every scanline of every pass.
+/// Fails if the size does not fit in memory.
+pub fn filtered_len(ihdr: &Ihdr) -> Result<usize> {
+    let passes = match ihdr.interlaced {
+        false => vec![(ihdr.width as usize, ihdr.height as usize)],
+        true => ADAM7
+            .iter()
+            .map(|&(x, y, dx, dy)| pass_size(ihdr, x, y, dx, dy))
+            .filter(|&(width, height)| width > 0 && height > 0)
+            .collect(),
+    };
+    passes
+        .into_iter()
+        .try_fold(0usize, |total, (width, height)| {
+            (ihdr.row_bytes(width as u32) + 1)
+                .checked_mul(height)
+                .and_then(|length| total.checked_add(length))
+        })
+        .ok_or_else(|| Error::from("Image dimensions are too large"))
+}
+
+/// Size in bytes of the unfiltered samples, without filter bytes.
+fn raw_len(ihdr: &Ihdr) -> Result<usize> {
+    ihdr.row_bytes(ihdr.width)
+        .checked_mul(ihdr.height as usize)
+        .ok_or_else(|| Error::from("Image dimensions are too large"))
+}
+
+fn pass_size(ihdr: &Ihdr, x: usize, y: usize, dx: usize, dy: usize) -> (usize, usize) {
+    let width = (ihdr.width as usize + dx - 1 - x) / dx;
+    let height = (ihdr.height as usize + dy - 1 - y) / dy;
+    (width, height)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reverses the scanline filters of a `width` x `height` image.
+fn unfilter(ihdr: &Ihdr, width: u32, height: u32, filtered: &[u8]) -> Result<Vec<u8>> {
+    let stride = ihdr.row_bytes(width);
+    let bpp = ihdr.filter_bpp();
+    if filtered.len() < (stride + 1) * height as usize {
+        return Err(Error::from("Image data is truncated"));
+    }
+
+    let mut data = vec![0u8; stride * height as usize];
+    for y in 0..height as usize {
+        let line = &filtered[y * (stride + 1)..(y + 1) * (stride + 1)];
+        let (previous, current) = data.split_at_mut(y * stride);
+        let previous = if y == 0 {
+            None
+        } else {
+            Some(&previous[(y - 1) * stride..])
+        };
+        let current = &mut current[..stride];
+        for i in 0..stride {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = previous.map_or(0, |row| row[i]);
+            let c = if i >= bpp {
+                previous.map_or(0, |row| row[i - bpp])
+            } else {
+                0
+            };
+            let x = line[i + 1];
+            current[i] = match line[0] {
+                0 => x,
+                1 => x.wrapping_add(a),
+                2 => x.wrapping_add(b),
+                3 => x.wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                4 => x.wrapping_add(paeth(a, b, c)),
+                other => return Err(Error::from(format!("Unknown filter type {}", other))),
+            };
+        }
+    }
+    Ok(data)
+}
+
+/// Applies `filter_type` to one scanline, given the previous (unfiltered) scanline.
+fn filter_row(filter_type: u8, row: &[u8], previous: Option<&[u8]>, bpp: usize) -> Vec<u8> {
+    let mut line = Vec::with_capacity(row.len() + 1);
+    line.push(filter_type);
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = previous.map_or(0, |p| p[i]);
+        let c = if i >= bpp {
+            previous.map_or(0, |p| p[i - bpp])
+        } else {
+            0
+        };
+        let predicted = match filter_type {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        };
+        line.push(row[i].wrapping_sub(predicted));
+    }
+    line
+}
+
+/// Filters the rows of a `width` pixel wide image.
+fn filter(ihdr: &Ihdr, width: u32, data: &[u8], strategy: FilterStrategy) -> Vec<u8> {
+    let stride = ihdr.row_bytes(width);
+    let bpp = ihdr.filter_bpp();
+    let fixed = match strategy {
+        FilterStrategy::Fixed(filter_type) => Some(filter_type.min(4)),
+        FilterStrategy::MinSum => None,
+        FilterStrategy::Recommended => {
+            let adaptive = ihdr.color_type != ColorType::Indexed && ihdr.bit_depth >= 8;
+            (!adaptive).then_some(0)
+        }
+    };
+
+    let mut filtered = Vec::with_capacity(data.len() + data.len() / stride.max(1));
+    for (y, row) in data.chunks(stride).enumerate() {
+        let previous = if y == 0 {
+            None
+        } else {
+            Some(&data[(y - 1) * stride..y * stride])
+        };
+        if let Some(filter_type) = fixed {
+            filtered.extend(filter_row(filter_type, row, previous, bpp));
+            continue;
+        }
+        let best = (0..5)
+            .map(|filter_type| filter_row(filter_type, row, previous, bpp))
+            .min_by_key(|line| {
+                line[1..]
+                    .iter()
+                    .map(|&v| (v as i8).unsigned_abs() as u64)
+                    .sum::<u64>()
+            })
+            .unwrap();
+        filtered.extend(best);
+    }
+    filtered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_ihdr(color_type: ColorType, bit_depth: u8, interlaced: bool) -> Ihdr {
+        Ihdr {
+            width: 13,
+            height: 11,
+            bit_depth,
+            color_type,
+            interlaced,
+        }
+    }
+
+    fn gradient(ihdr: Ihdr) -> RawImage {
+        let mut image = RawImage::blank(ihdr);
+        let max = (1u32 << image.ihdr().bit_depth) - 1;
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                for channel in 0..image.ihdr().color_type.channels() {
+                    let value = ((x * 7 + y * 3 + channel * 5) as u32 % (max + 1)) as u16;
+                    image.set_sample(x, y, channel, value);
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_set_and_get_sample() {
+        let mut image = RawImage::blank(test_ihdr(ColorType::Indexed, 2, false));
+        image.set_sample(5, 3, 0, 3);
+        image.set_sample(6, 3, 0, 1);
+        assert_eq!(image.sample(5, 3, 0), 3);
+        assert_eq!(image.sample(6, 3, 0), 1);
+        assert_eq!(image.sample(4, 3, 0), 0);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let image = gradient(test_ihdr(ColorType::Rgba, 16, false));
+        let decoded = RawImage::from_filtered(image.ihdr().clone(), &image.filtered()).unwrap();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn test_filter_strategies() {
+        let image = gradient(test_ihdr(ColorType::Rgb, 8, false));
+        for strategy in FilterStrategy::CANDIDATES {
+            let filtered = image.filtered_with(strategy);
+            if let FilterStrategy::Fixed(filter_type) = strategy {
+                assert!(filtered
+                    .chunks(image.stride() + 1)
+                    .all(|line| line[0] == filter_type));
+            }
+            let decoded = RawImage::from_filtered(image.ihdr().clone(), &filtered).unwrap();
+            assert_eq!(decoded, image);
+        }
+    }
+
+    #[test]
+    fn test_interlaced_round_trip() {
+        for (color_type, bit_depth) in [(ColorType::Indexed, 4), (ColorType::Rgb, 8)] {
+            let image = gradient(test_ihdr(color_type, bit_depth, true));
+            let compressed = image.encode(6);
+            let decoded = RawImage::from_filtered(
+                image.ihdr().clone(),
+                &inflate(&compressed, filtered_len(image.ihdr()).unwrap()).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(decoded, image);
+        }
+    }
+
+    #[test]
+    fn test_inflate_prefix() {
+        let mut compressed = deflate(b"some image data", 6);
+        let length = compressed.len();
+        compressed.extend_from_slice(b"extra");
+        let (decoded, used) = inflate_prefix(&compressed, 64).unwrap();
+        assert_eq!(decoded, b"some image data");
+        assert_eq!(used, Some(length));
+        assert!(inflate_prefix(&compressed[..length - 5], 64).is_err());
+
+        let (decoded, used) = inflate_prefix(&deflate(&[0; 1 << 20], 9), 100).unwrap();
+        assert_eq!((decoded.len(), used), (101, None));
+    }
+
+    #[test]
+    fn test_filtered_len() {
+        for interlaced in [false, true] {
+            let image = gradient(test_ihdr(ColorType::Indexed, 2, interlaced));
+            assert_eq!(filtered_len(image.ihdr()).unwrap(), image.filtered().len());
+        }
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let image = gradient(test_ihdr(ColorType::Grayscale, 8, false));
+        let filtered = image.filtered();
+        let decoded =
+            RawImage::from_filtered(image.ihdr().clone(), &filtered[..filtered.len() - 1]);
+        assert!(decoded.is_err());
+    }
+
+    #[test]
+    fn test_huge_header_is_rejected_before_allocating() {
+        for interlaced in [false, true] {
+            let ihdr = Ihdr {
+                width: u32::MAX,
+                height: u32::MAX,
+                ..test_ihdr(ColorType::Rgba, 16, interlaced)
+            };
+            assert!(RawImage::from_filtered(ihdr.clone(), &[0; 64]).is_err());
+            let png = png_of(
+                &gradient(test_ihdr(ColorType::Rgba, 16, interlaced)),
+                Vec::new(),
+            );
+            let mut chunks = png.chunks().to_vec();
+            chunks[0] = ihdr.to_chunk();
+            assert!(Rgba16Image::from_png(&Png::from_chunks(chunks)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_deflate_bomb_is_rejected() {
+        let image = gradient(test_ihdr(ColorType::Grayscale, 8, false));
+        let mut png = png_of(&image, Vec::new());
+        let expected = filtered_len(image.ihdr()).unwrap();
+        png.set_image_data(deflate(&vec![0; expected + INFLATE_SLACK + 1], 9));
+        assert!(RawImage::from_png(&png).is_err());
+        png.set_image_data(deflate(&vec![0; expected + INFLATE_SLACK], 9));
+        assert!(RawImage::from_png(&png).is_ok());
+    }
+
+    fn png_of(image: &RawImage, extra: Vec<Chunk>) -> Png {
+        let mut chunks = vec![image.ihdr().to_chunk()];
+        chunks.extend(extra);
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        let mut png = Png::from_chunks(chunks);
+        png.set_image_data(image.encode(6));
+        png
+    }
+
+    #[test]
+    fn test_rgba16_expansion() {
+        let rgb = gradient(test_ihdr(ColorType::Rgb, 8, false));
+        let mut rgba = RawImage::blank(test_ihdr(ColorType::Rgba, 16, true));
+        for y in 0..rgb.height() {
+            for x in 0..rgb.width() {
+                for channel in 0..3 {
+                    rgba.set_sample(x, y, channel, rgb.sample(x, y, channel) * 257);
+                }
+                rgba.set_sample(x, y, 3, 65535);
+            }
+        }
+        let expanded = Rgba16Image::from_png(&png_of(&rgb, Vec::new())).unwrap();
+        assert_eq!(
+            Rgba16Image::from_png(&png_of(&rgba, Vec::new())).unwrap(),
+            expanded
+        );
+
+        // A tRNS color key makes matching pixels transparent
+        let key = [0, 7, 0, 12, 0, 17].to_vec();
+        let keyed = png_of(
+            &rgb,
+            vec![Chunk::new(ChunkType::from_str("tRNS").unwrap(), key)],
+        );
+        let keyed = Rgba16Image::from_png(&keyed).unwrap();
+        let transparent = keyed.pixels.iter().filter(|p| p[3] == 0).count();
+        assert!(transparent > 0 && transparent < keyed.pixels.len());
+        assert!(keyed.pixels.iter().all(|p| p[3] == 0 || p[3] == 65535));
+    }
+
+    #[test]
+    fn test_rgba16_palette() {
+        let image = gradient(test_ihdr(ColorType::Indexed, 2, false));
+        let colors = vec![0, 0, 0, 255, 0, 0, 0, 255, 0, 255, 255, 255];
+        let plte = |entries: usize| {
+            Chunk::new(
+                ChunkType::from_str("PLTE").unwrap(),
+                colors[..entries * 3].to_vec(),
+            )
+        };
+        let trns = Chunk::new(ChunkType::from_str("tRNS").unwrap(), vec![0]);
+        let expanded = Rgba16Image::from_png(&png_of(&image, vec![plte(4), trns])).unwrap();
+        assert_eq!(expanded.pixels[0], [0, 0, 0, 0]);
+        assert_eq!(expanded.pixels[1], [65535, 65535, 65535, 65535]);
+
+        // Index 3 is past the end of a three-entry palette
+        assert!(Rgba16Image::from_png(&png_of(&image, vec![plte(3)])).is_err());
+    }
+}
@@ -1,13 +1,69 @@
-// mod args;
+
+use structopt::StructOpt;
+
+use args::{Cli, PngMeArgs};
+
+mod analyze;
+mod ancillary;
+mod apng;
+mod args;
+mod cgbi;
 mod chunk;
 mod chunk_type;
-// mod commands;
+mod color;
+mod commands;
+mod container;
+mod convert;
+mod exif;
+mod hdr;
+mod icc;
+mod ihdr;
+mod image;
+mod netpbm;
+mod optimize;
+mod palette;
 mod png;
+mod riff;
+mod stego;
+mod strip;
+mod text;
+mod time;
+mod transform;
+mod transplant;
+mod xmp;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T,Error>;
 
 fn main()-> Result<()>
 {
-    todo!()
-}
\ No newline at end of file
+    let cli = Cli::from_args();
+    commands::set_touch(cli.touch);
+    match cli.command {
+        PngMeArgs::Encode(args) => commands::encode(args),
+        PngMeArgs::Decode(args) => commands::decode(args),
+        PngMeArgs::Remove(args) => commands::remove(args),
+        PngMeArgs::Print(args) => commands::print_chunks(args),
+        PngMeArgs::Embed(args) => commands::embed(args),
+        PngMeArgs::Extract(args) => commands::extract(args),
+        PngMeArgs::Capacity(args) => commands::capacity(args),
+        PngMeArgs::Analyze(args) => commands::analyze(args),
+        PngMeArgs::Trailer(args) => commands::trailer(args),
+        PngMeArgs::Same(args) => commands::same(args),
+        PngMeArgs::Strip(args) => commands::strip(args),
+        PngMeArgs::Transplant(args) => commands::transplant(args),
+        PngMeArgs::Exif(args) => commands::exif(args),
+        PngMeArgs::Xmp(args) => commands::xmp(args),
+        PngMeArgs::Icc(args) => commands::icc(args),
+        PngMeArgs::Color(args) => commands::color(args),
+        PngMeArgs::Dpi(args) => commands::dpi(args),
+        PngMeArgs::Palette(args) => commands::palette(args),
+        PngMeArgs::Frames(args) => commands::frames(args),
+        PngMeArgs::Normalize(args) => commands::normalize(args),
+        PngMeArgs::Optimize(args) => commands::optimize(args),
+        PngMeArgs::Convert(args) => commands::convert(args),
+        PngMeArgs::Export(args) => commands::export(args),
+        PngMeArgs::Import(args) => commands::import(args),
+        PngMeArgs::Transform(args) => commands::transform(args),
+    }
+}
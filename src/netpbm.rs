@@ -0,0 +1,396 @@
+//! Netpbm images, for looking at decoded pixels with other tools.
+//! https://netpbm.sourceforge.net/doc/pam.html
+//!
+//! PGM (`P5`) and PPM (`P6`) have a short text header with the width, height and
+//! maximum sample value, followed by binary grayscale or RGB samples. PAM (`P7`) names
+//! each header field and allows one to four channels, so it can also hold alpha.
+//! Samples take one byte when the maximum is below 256 and two big-endian bytes
+//! otherwise.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::image::{RawImage, Rgba16Image};
+use crate::png::Png;
+use crate::{Error, Result};
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum NetpbmError {
+    #[error("Not a PGM, PPM or PAM file")]
+    UnknownFormat,
+    #[error("Invalid header: {0}")]
+    InvalidHeader(String),
+    #[error("{0} channels are not supported, expected 1 to 4")]
+    UnsupportedDepth(usize),
+    #[error("Maximum sample value {0} is outside 1 to 65535")]
+    InvalidMaxval(u32),
+    #[error("Pixel data is {actual} bytes, expected {expected}")]
+    Truncated { actual: usize, expected: usize },
+    #[error("Sample {sample} is larger than the maximum {maxval}")]
+    SampleOutOfRange { sample: u16, maxval: u16 },
+}
+
+/// The file formats `pngme export` writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// RGB, without alpha
+    Ppm,
+    /// Grayscale or RGB, with alpha if the image has any transparency
+    Pam,
+    /// The unfiltered scanlines in the format given by IHDR, without a header
+    Raw,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ppm" => Ok(ExportFormat::Ppm),
+            "pam" => Ok(ExportFormat::Pam),
+            "raw" => Ok(ExportFormat::Raw),
+            _ => Err(Error::from(format!(
+                "Unknown format {}, expected ppm, pam or raw",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::Ppm => f.write_str("ppm"),
+            ExportFormat::Pam => f.write_str("pam"),
+            ExportFormat::Raw => f.write_str("raw"),
+        }
+    }
+}
+
+/// A decoded Netpbm image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Netpbm {
+    pub width: u32,
+    pub height: u32,
+    /// 1 for grayscale, 2 for grayscale with alpha, 3 for RGB and 4 for RGBA
+    pub channels: usize,
+    pub maxval: u16,
+    /// Samples in row-major order, `channels` per pixel
+    pub samples: Vec<u16>,
+}
+
+impl Netpbm {
+    /// Decodes the image data of `png`, keeping grayscale images gray and adding an alpha
+    /// channel when the color type or tRNS calls for one. Samples are 16-bit for 16-bit
+    /// images and 8-bit otherwise.
+    pub fn from_png(png: &Png) -> Result<Self> {
+        let ihdr = png.ihdr()?;
+        let image = Rgba16Image::from_png(png)?;
+        let gray = matches!(
+            ihdr.color_type,
+            ColorType::Grayscale | ColorType::GrayscaleAlpha
+        );
+        let alpha = ihdr.color_type.has_alpha() || png.chunk_by_type("tRNS").is_some();
+        let channels: &[usize] = match (gray, alpha) {
+            (true, false) => &[0],
+            (true, true) => &[0, 3],
+            (false, false) => &[0, 1, 2],
+            (false, true) => &[0, 1, 2, 3],
+        };
+        let maxval = match ihdr.bit_depth {
+            16 => u16::MAX,
+            _ => 255,
+        };
+        let samples = image
+            .pixels
+            .iter()
+            .flat_map(|pixel| channels.iter().map(move |&c| pixel[c]))
+            .map(|v| match maxval {
+                u16::MAX => v,
+                _ => ((v as u32 + 128) / 257) as u16,
+            })
+            .collect();
+        Ok(Netpbm {
+            width: image.width,
+            height: image.height,
+            channels: channels.len(),
+            maxval,
+            samples,
+        })
+    }
+
+    /// Encodes the image as a PNG with the matching color type, in 16 bits when the
+    /// maximum sample value needs more than 8.
+    pub fn to_png(&self) -> Png {
+        let bit_depth = if self.maxval > 255 { 16 } else { 8 };
+        let ihdr = Ihdr {
+            width: self.width,
+            height: self.height,
+            bit_depth,
+            color_type: match self.channels {
+                1 => ColorType::Grayscale,
+                2 => ColorType::GrayscaleAlpha,
+                3 => ColorType::Rgb,
+                _ => ColorType::Rgba,
+            },
+            interlaced: false,
+        };
+        let max = (1u32 << bit_depth) - 1;
+        let maxval = self.maxval as u32;
+        let mut image = RawImage::blank(ihdr.clone());
+        for (i, pixel) in self.samples.chunks_exact(self.channels).enumerate() {
+            let (x, y) = (i % image.width(), i / image.width());
+            for (channel, &sample) in pixel.iter().enumerate() {
+                let value = (sample as u32 * max + maxval / 2) / maxval;
+                image.set_sample(x, y, channel, value as u16);
+            }
+        }
+        let mut png = Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        png.set_image_data(image.encode(9));
+        png
+    }
+
+    /// The image as a binary PPM. Grayscale is repeated in each color and alpha is
+    /// dropped.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut bytes =
+            format!("P6\n{} {}\n{}\n", self.width, self.height, self.maxval).into_bytes();
+        for pixel in self.samples.chunks_exact(self.channels) {
+            let rgb = match pixel.len() {
+                1 | 2 => [pixel[0]; 3],
+                _ => [pixel[0], pixel[1], pixel[2]],
+            };
+            for sample in rgb {
+                self.push_sample(&mut bytes, sample);
+            }
+        }
+        bytes
+    }
+
+    /// The image as a PAM with every channel
+    pub fn to_pam(&self) -> Vec<u8> {
+        let tuple_type = match self.channels {
+            1 => "GRAYSCALE",
+            2 => "GRAYSCALE_ALPHA",
+            3 => "RGB",
+            _ => "RGB_ALPHA",
+        };
+        let mut bytes = format!(
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+            self.width, self.height, self.channels, self.maxval, tuple_type
+        )
+        .into_bytes();
+        for &sample in self.samples.iter() {
+            self.push_sample(&mut bytes, sample);
+        }
+        bytes
+    }
+
+    fn push_sample(&self, bytes: &mut Vec<u8>, sample: u16) {
+        if self.maxval > 255 {
+            bytes.extend_from_slice(&sample.to_be_bytes());
+        } else {
+            bytes.push(sample as u8);
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Netpbm {
+    type Error = NetpbmError;
+
+    fn try_from(bytes: &[u8]) -> std::result::Result<Self, NetpbmError> {
+        let (width, height, channels, maxval, data) = match bytes.get(..2) {
+            Some(b"P5") | Some(b"P6") => {
+                let mut header = Header { bytes, position: 2 };
+                let width = header.number()?;
+                let height = header.number()?;
+                let maxval = header.number()?;
+                // A single whitespace byte separates the header from the samples
+                let data = bytes.get(header.position + 1..).unwrap_or_default();
+                let channels = if bytes[1] == b'5' { 1 } else { 3 };
+                (width, height, channels, maxval, data)
+            }
+            Some(b"P7") => parse_pam_header(bytes)?,
+            _ => return Err(NetpbmError::UnknownFormat),
+        };
+        if !(1..=4).contains(&channels) {
+            return Err(NetpbmError::UnsupportedDepth(channels));
+        }
+        let maxval = u16::try_from(maxval)
+            .ok()
+            .filter(|&maxval| maxval > 0)
+            .ok_or(NetpbmError::InvalidMaxval(maxval))?;
+        if width == 0 || height == 0 {
+            return Err(NetpbmError::InvalidHeader("Image is empty".to_string()));
+        }
+
+        let sample_bytes = if maxval > 255 { 2 } else { 1 };
+        let expected = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(channels * sample_bytes))
+            .ok_or_else(|| NetpbmError::InvalidHeader("Image is too large".to_string()))?;
+        if data.len() < expected {
+            return Err(NetpbmError::Truncated {
+                actual: data.len(),
+                expected,
+            });
+        }
+        let samples: Vec<u16> = data[..expected]
+            .chunks_exact(sample_bytes)
+            .map(|s| match s {
+                [high, low] => u16::from_be_bytes([*high, *low]),
+                _ => s[0] as u16,
+            })
+            .collect();
+        if let Some(&sample) = samples.iter().find(|&&sample| sample > maxval) {
+            return Err(NetpbmError::SampleOutOfRange { sample, maxval });
+        }
+        Ok(Netpbm {
+            width,
+            height,
+            channels,
+            maxval,
+            samples,
+        })
+    }
+}
+
+/// Reads the whitespace-separated numbers of a PGM or PPM header
+struct Header<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Header<'_> {
+    /// The next number, skipping whitespace and `#` comments
+    fn number(&mut self) -> std::result::Result<u32, NetpbmError> {
+        loop {
+            match self.bytes.get(self.position) {
+                Some(b'#') => {
+                    while !matches!(self.bytes.get(self.position), Some(b'\n') | None) {
+                        self.position += 1;
+                    }
+                }
+                Some(byte) if byte.is_ascii_whitespace() => self.position += 1,
+                _ => break,
+            }
+        }
+        let start = self.position;
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(u8::is_ascii_digit)
+        {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.position])
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| NetpbmError::InvalidHeader("Expected a number".to_string()))
+    }
+}
+
+/// Reads the header of a PAM file, returning the width, height, depth, maximum sample
+/// value and the bytes after it
+fn parse_pam_header(
+    bytes: &[u8],
+) -> std::result::Result<(u32, u32, usize, u32, &[u8]), NetpbmError> {
+    let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+    let mut rest = &bytes[2..];
+    loop {
+        let end = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| NetpbmError::InvalidHeader("Missing ENDHDR".to_string()))?;
+        let line = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+        rest = &rest[end + 1..];
+        if line == "ENDHDR" {
+            break;
+        }
+        let (key, value) = line.split_once([' ', '\t']).unwrap_or((&line, ""));
+        let number = || {
+            value
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| NetpbmError::InvalidHeader(format!("Invalid {}", key)))
+        };
+        match key {
+            "WIDTH" => width = Some(number()?),
+            "HEIGHT" => height = Some(number()?),
+            "DEPTH" => depth = Some(number()? as usize),
+            "MAXVAL" => maxval = Some(number()?),
+            // The depth already says how many channels there are
+            _ => {}
+        }
+    }
+    let missing = |field: &str| NetpbmError::InvalidHeader(format!("Missing {}", field));
+    Ok((
+        width.ok_or_else(|| missing("WIDTH"))?,
+        height.ok_or_else(|| missing("HEIGHT"))?,
+        depth.ok_or_else(|| missing("DEPTH"))?,
+        maxval.ok_or_else(|| missing("MAXVAL"))?,
+        rest,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pam_round_trip() {
+        let mut image = Rgba16Image::blank(3, 2);
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            *pixel = [i as u16 * 10000, 500, 65535, i as u16 * 13000];
+        }
+        let png = image.to_png(16);
+        let netpbm = Netpbm::from_png(&png).unwrap();
+        assert_eq!((netpbm.channels, netpbm.maxval), (4, 65535));
+
+        let pam = netpbm.to_pam();
+        assert!(pam.starts_with(b"P7\nWIDTH 3\nHEIGHT 2\nDEPTH 4\nMAXVAL 65535\n"));
+        let parsed = Netpbm::try_from(&pam[..]).unwrap();
+        assert_eq!(parsed, netpbm);
+        assert_eq!(
+            parsed.to_png().pixel_digest().unwrap(),
+            png.pixel_digest().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ppm_and_pgm() {
+        let ppm = b"P6\n# made by hand\n2 1\n15\n\x0f\x00\x00\x00\x07\x0f";
+        let netpbm = Netpbm::try_from(&ppm[..]).unwrap();
+        assert_eq!((netpbm.width, netpbm.channels, netpbm.maxval), (2, 3, 15));
+        let image = RawImage::from_png(&netpbm.to_png()).unwrap();
+        let pixel = |x| [0, 1, 2].map(|c| image.sample(x, 0, c));
+        assert_eq!(pixel(0), [255, 0, 0]);
+        assert_eq!(pixel(1), [0, 119, 255]);
+        assert_eq!(netpbm.to_ppm(), b"P6\n2 1\n15\n\x0f\x00\x00\x00\x07\x0f");
+
+        let pgm = Netpbm::try_from(&b"P5 1 1 255\n\x80"[..]).unwrap();
+        assert_eq!(pgm.to_ppm(), b"P6\n1 1\n255\n\x80\x80\x80");
+        assert_eq!(
+            Netpbm::try_from(&b"P5 2 1 255\n\x80"[..]),
+            Err(NetpbmError::Truncated {
+                actual: 1,
+                expected: 2
+            })
+        );
+        assert_eq!(
+            Netpbm::try_from(&b"P5 1 1 7\n\x08"[..]),
+            Err(NetpbmError::SampleOutOfRange {
+                sample: 8,
+                maxval: 7
+            })
+        );
+    }
+}
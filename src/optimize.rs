@@ -0,0 +1,379 @@
+//! Lossless size optimization.
+//!
+//! The decoded pixels never change. The image data is re-filtered with each strategy in
+//! turn, recompressed and stored in a single IDAT chunk. Where every pixel survives the
+//! trip, it is also stored in a smaller format: without alpha when fully opaque, as
+//! grayscale when red, green and blue always match, with 8 bits per sample instead of 16
+//! and as a palette when there are at most 256 colors. Chunks that carry no information
+//! are dropped; every other chunk, including message chunks, and any data after IEND are
+//! kept as they are.
+//!
+//! Messages hidden in the image data itself with `pngme embed` do not survive.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::apng::AnimationControl;
+use crate::cgbi;
+use crate::convert;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::image::{FilterStrategy, RawImage, Rgba16Image};
+use crate::palette::Palette;
+use crate::png::{Format, Png};
+use crate::{Error, Result};
+
+/// Chunks the spec allows at most once, of which decoders only read the first
+const SINGLE_INSTANCE: [&str; 18] = [
+    "gAMA", "cHRM", "sRGB", "iCCP", "cICP", "mDCV", "cLLI", "sBIT", "bKGD", "hIST", "tRNS", "pHYs",
+    "tIME", "eXIf", "oFFs", "pCAL", "sCAL", "acTL",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptimizeOptions {
+    /// zlib compression level, 0 to 9
+    pub level: u32,
+    /// Whether the color type and bit depth may change
+    pub reduce: bool,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        OptimizeOptions {
+            level: 9,
+            reduce: true,
+        }
+    }
+}
+
+/// What [`optimize`] changed
+#[derive(Debug, Clone)]
+pub struct OptimizeReport {
+    pub size_before: usize,
+    pub size_after: usize,
+    pub ihdr_before: Ihdr,
+    pub ihdr_after: Ihdr,
+    /// The filter strategy of the new image data, or `None` if the original compressed
+    /// data was smaller and was kept
+    pub filter: Option<FilterStrategy>,
+    /// Chunks that were dropped, each with the reason
+    pub removed: Vec<String>,
+}
+
+impl fmt::Display for OptimizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let saved = self.size_before.saturating_sub(self.size_after);
+        writeln!(
+            f,
+            "Size: {} -> {} bytes ({:.1}% smaller)",
+            self.size_before,
+            self.size_after,
+            saved as f64 * 100.0 / self.size_before.max(1) as f64
+        )?;
+        let format = |ihdr: &Ihdr| format!("{}-bit {}", ihdr.bit_depth, ihdr.color_type);
+        if self.ihdr_before == self.ihdr_after {
+            writeln!(f, "Format: {} (unchanged)", format(&self.ihdr_after))?;
+        } else {
+            writeln!(
+                f,
+                "Format: {} -> {}",
+                format(&self.ihdr_before),
+                format(&self.ihdr_after)
+            )?;
+        }
+        match self.filter {
+            Some(filter) => writeln!(f, "Filter: {}", filter)?,
+            None => writeln!(f, "Filter: kept the original image data")?,
+        }
+        for removed in self.removed.iter() {
+            writeln!(f, "Removed {}", removed)?;
+        }
+        Ok(())
+    }
+}
+
+/// A way of storing the image's pixels
+struct Candidate {
+    image: RawImage,
+    /// The palette of a rebuilt indexed image. `None` for truecolor and grayscale images,
+    /// and for the original format, whose PLTE and tRNS are kept.
+    palette: Option<Palette>,
+}
+
+/// Returns the smallest lossless encoding of `png` found, along with a report.
+pub fn optimize(png: &Png, options: OptimizeOptions) -> Result<(Png, OptimizeReport)> {
+    if png.format() != Format::Png {
+        return Err(Error::from(format!(
+            "Only PNG files can be optimized, found {}",
+            png.format()
+        )));
+    }
+    if cgbi::is_cgbi(png) {
+        return Err(Error::from(
+            "Convert CgBI images with `pngme normalize` first",
+        ));
+    }
+    let ihdr = png.ihdr()?;
+    let pixels = Rgba16Image::from_png(png)?;
+
+    let mut base = png.clone();
+    let mut removed = remove_redundant(&mut base, &ihdr, &pixels)?;
+    base.set_image_data(base.image_data());
+
+    let mut candidates = vec![Candidate {
+        image: RawImage::from_png(png)?,
+        palette: None,
+    }];
+    // Animation frames share the IHDR but keep their own data, so the format stays
+    if options.reduce && png.chunk_by_type(AnimationControl::CHUNK_TYPE).is_none() {
+        candidates.extend(reductions(&ihdr, &pixels));
+    }
+
+    let background = convert::background_color(&base, &ihdr)?;
+    let mut best = (base.as_bytes().len(), base.clone(), None, Vec::new());
+    for candidate in candidates.iter() {
+        let (filter, data) = FilterStrategy::CANDIDATES
+            .iter()
+            .map(|&strategy| {
+                (
+                    strategy,
+                    candidate.image.encode_with(strategy, options.level),
+                )
+            })
+            .min_by_key(|(_, data)| data.len())
+            .unwrap();
+        let mut notes = Vec::new();
+        let result = convert::with_image_data(
+            &base,
+            candidate.image.ihdr(),
+            candidate.palette.as_ref(),
+            data,
+            background,
+            &mut notes,
+        )?;
+        let size = result.as_bytes().len();
+        if size < best.0 {
+            best = (size, result, Some(filter), notes);
+        }
+    }
+
+    let (size_after, optimized, filter, notes) = best;
+    removed.extend(notes);
+    let report = OptimizeReport {
+        size_before: png.as_bytes().len(),
+        size_after,
+        ihdr_after: optimized.ihdr()?,
+        ihdr_before: ihdr,
+        filter,
+        removed,
+    };
+    Ok((optimized, report))
+}
+
+/// Drops repeats of chunks that may only appear once and transparency that applies to
+/// no pixel, returning what was removed.
+fn remove_redundant(png: &mut Png, ihdr: &Ihdr, pixels: &Rgba16Image) -> Result<Vec<String>> {
+    let mut removed = Vec::new();
+    let mut seen = Vec::new();
+    png.chunks_mut().retain(|chunk| {
+        let chunk_type = chunk.chunk_type().to_string();
+        if !SINGLE_INSTANCE.contains(&chunk_type.as_str()) {
+            return true;
+        }
+        if seen.contains(&chunk_type) {
+            removed.push(format!("duplicate {}", chunk_type));
+            return false;
+        }
+        seen.push(chunk_type);
+        true
+    });
+
+    if png.chunk_by_type("tRNS").is_some() {
+        if ihdr.color_type == ColorType::Indexed {
+            let trns = png.chunk_by_type("tRNS").map(|c| c.length());
+            Palette::from_png(png)?.write_to(png);
+            if png.chunk_by_type("tRNS").map(|c| c.length()) != trns {
+                removed.push("opaque tRNS entries".to_string());
+            }
+        } else if pixels.pixels.iter().all(|p| p[3] == 65535) {
+            png.remove_first_chunk("tRNS")?;
+            removed.push("tRNS matching no pixel".to_string());
+        }
+    }
+    Ok(removed)
+}
+
+/// The smaller formats the pixels can be stored in without loss
+fn reductions(ihdr: &Ihdr, pixels: &Rgba16Image) -> Vec<Candidate> {
+    let opaque = pixels.pixels.iter().all(|p| p[3] == 65535);
+    let gray = pixels.pixels.iter().all(|p| p[0] == p[1] && p[1] == p[2]);
+    let eight_bit = pixels.pixels.iter().flatten().all(|v| v % 257 == 0);
+
+    let color_type = match (gray, opaque) {
+        (true, true) => ColorType::Grayscale,
+        (false, true) => ColorType::Rgb,
+        (true, false) => ColorType::GrayscaleAlpha,
+        (false, false) => ColorType::Rgba,
+    };
+    let bit_depth = match (color_type, eight_bit) {
+        (_, false) => 16,
+        (ColorType::Grayscale, true) => [1, 2, 4, 8]
+            .into_iter()
+            .find(|&depth| {
+                let step = 65535 / ((1 << depth) - 1);
+                pixels.pixels.iter().all(|p| p[0] % step == 0)
+            })
+            .unwrap_or(8),
+        _ => 8,
+    };
+
+    let mut candidates = Vec::new();
+    if (color_type, bit_depth) != (ihdr.color_type, ihdr.bit_depth) {
+        candidates.push(Candidate {
+            image: pixels.to_raw(Ihdr {
+                color_type,
+                bit_depth,
+                ..ihdr.clone()
+            }),
+            palette: None,
+        });
+    }
+
+    if eight_bit {
+        if let Some(candidate) = palette_candidate(ihdr, pixels) {
+            candidates.push(candidate);
+        }
+    }
+    candidates
+}
+
+/// An indexed image holding each distinct color once, if there are at most 256
+fn palette_candidate(ihdr: &Ihdr, pixels: &Rgba16Image) -> Option<Candidate> {
+    let colors: Vec<[u8; 4]> = pixels
+        .pixels
+        .iter()
+        .map(|pixel| pixel.map(|v| (v / 257) as u8))
+        .collect();
+    let palette = Palette::exact(&colors, 256)?;
+    let index: HashMap<[u8; 4], u16> = palette
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(i, &color)| (color, i as u16))
+        .collect();
+
+    let mut image = RawImage::blank(Ihdr {
+        color_type: ColorType::Indexed,
+        bit_depth: palette.bit_depth(),
+        ..ihdr.clone()
+    });
+    for (i, color) in colors.iter().enumerate() {
+        let (x, y) = (i % image.width(), i / image.width());
+        image.set_sample(x, y, 0, index[color]);
+    }
+    Some(Candidate {
+        image,
+        palette: Some(palette),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    /// A 16-bit RGBA image of `width` x 8 pixels scattered with the given 8-bit colors
+    fn rgba16_png(width: u32, colors: &[[u8; 4]]) -> Png {
+        let mut image = Rgba16Image::blank(width, 8);
+        let mut state = 1u64;
+        for pixel in image.pixels.iter_mut() {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            *pixel = colors[(state >> 33) as usize % colors.len()].map(|v| v as u16 * 257);
+        }
+        let mut png = image.to_png(16);
+        png.set_chunk(chunk("gAMA", 45455u32.to_be_bytes().to_vec()));
+        png.append_chunk(chunk("ruSt", b"message".to_vec()));
+        png
+    }
+
+    #[test]
+    fn test_reduces_to_palette_and_keeps_messages() {
+        let mut colors = vec![[0, 255, 0, 128], [0, 0, 255, 255]];
+        colors.extend((1..15).map(|i| [i * 16, 255 - i * 16, i * 8, 255]));
+        let mut png = rgba16_png(64, &colors);
+        png.set_chunk(chunk("bKGD", vec![0, 0, 0, 0, 0xff, 0xff]));
+        png.chunks_mut().insert(2, chunk("gAMA", vec![0, 0, 0, 1]));
+        png.set_trailer(b"after".to_vec());
+
+        let (optimized, report) = optimize(&png, OptimizeOptions::default()).unwrap();
+        assert_eq!(optimized.ihdr().unwrap().color_type, ColorType::Indexed);
+        assert_eq!(optimized.ihdr().unwrap().bit_depth, 4);
+        assert_eq!(
+            optimized.pixel_digest().unwrap(),
+            png.pixel_digest().unwrap()
+        );
+        assert!(report.size_after < report.size_before);
+        assert_eq!(report.size_after, optimized.as_bytes().len());
+
+        let types: Vec<String> = optimized
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(
+            types,
+            ["IHDR", "gAMA", "PLTE", "tRNS", "bKGD", "IDAT", "ruSt", "IEND"]
+        );
+        assert_eq!(
+            optimized.chunk_by_type("gAMA").unwrap().data(),
+            [0, 0, 0xb1, 0x8f]
+        );
+        assert_eq!(optimized.chunk_by_type("tRNS").unwrap().data(), [128]);
+        let plte = optimized.chunk_by_type("PLTE").unwrap().data();
+        let background = optimized.chunk_by_type("bKGD").unwrap().data()[0] as usize;
+        assert_eq!(plte[background * 3..background * 3 + 3], [0, 0, 255]);
+        assert_eq!(optimized.trailer(), b"after");
+        assert!(report.removed.contains(&"duplicate gAMA".to_string()));
+    }
+
+    #[test]
+    fn test_reduces_opaque_gray() {
+        let png = rgba16_png(300, &[[0, 0, 0, 255], [255, 255, 255, 255]]);
+        let (optimized, _) = optimize(&png, OptimizeOptions::default()).unwrap();
+        let ihdr = optimized.ihdr().unwrap();
+        assert_eq!((ihdr.color_type, ihdr.bit_depth), (ColorType::Grayscale, 1));
+        assert_eq!(
+            optimized.pixel_digest().unwrap(),
+            png.pixel_digest().unwrap()
+        );
+
+        let options = OptimizeOptions {
+            reduce: false,
+            ..OptimizeOptions::default()
+        };
+        let (optimized, report) = optimize(&png, options).unwrap();
+        assert_eq!(optimized.ihdr().unwrap(), png.ihdr().unwrap());
+        assert_eq!(report.ihdr_after, report.ihdr_before);
+    }
+
+    #[test]
+    fn test_keeps_sixteen_bit_precision() {
+        let mut image = Rgba16Image::blank(4, 4);
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            *pixel = [i as u16 * 1000 + 1, 7, 7, 65535];
+        }
+        let png = image.to_png(16);
+        let (optimized, _) = optimize(&png, OptimizeOptions::default()).unwrap();
+        let ihdr = optimized.ihdr().unwrap();
+        assert_eq!((ihdr.color_type, ihdr.bit_depth), (ColorType::Rgb, 16));
+        assert_eq!(
+            optimized.pixel_digest().unwrap(),
+            png.pixel_digest().unwrap()
+        );
+    }
+}
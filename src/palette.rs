@@ -0,0 +1,520 @@
+//! The PLTE chunk, with the alpha values from tRNS merged in for indexed images.
+//! https://www.w3.org/TR/png-3/#11PLTE
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::ancillary::Transparency;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::ColorType;
+use crate::png::Png;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum PaletteError {
+    #[error("Indexed image has no PLTE chunk")]
+    Missing,
+    #[error("PLTE length {0} is not a multiple of 3 between 3 and 768")]
+    InvalidLength(usize),
+    #[error("{0} images must not have a PLTE chunk")]
+    NotAllowed(ColorType),
+    #[error("{entries} palette entries don't fit in {bit_depth}-bit indices")]
+    TooManyEntries { entries: usize, bit_depth: u8 },
+    #[error("tRNS has {alpha} alpha values for {entries} palette entries")]
+    TooMuchAlpha { alpha: usize, entries: usize },
+    #[error("Palette index {index} is out of range for {entries} entries")]
+    IndexOutOfRange { index: usize, entries: usize },
+    #[error("Only indexed images can give palette entries an alpha value")]
+    AlphaNotAllowed,
+    #[error("Invalid color {0}, expected #RRGGBB or #RRGGBBAA")]
+    InvalidColor(String),
+    #[error("Invalid replacement {0}, expected INDEX=#RRGGBB or #RRGGBB=#RRGGBB")]
+    InvalidReplacement(String),
+}
+
+/// An RGB color with optional alpha, written as `#RRGGBB` or `#RRGGBBAA`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub rgb: [u8; 3],
+    pub alpha: Option<u8>,
+}
+
+impl FromStr for Color {
+    type Err = PaletteError;
+
+    fn from_str(s: &str) -> Result<Self, PaletteError> {
+        let invalid = || PaletteError::InvalidColor(s.to_string());
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+            .collect::<Result<Vec<u8>, PaletteError>>()?;
+        Ok(Color {
+            rgb: [bytes[0], bytes[1], bytes[2]],
+            alpha: bytes.get(3).copied(),
+        })
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b] = self.rgb;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)?;
+        if let Some(a) = self.alpha {
+            write!(f, "{:02x}", a)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which palette entries a [`Replacement`] applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Index(usize),
+    /// Every entry with this color, and this alpha if one is given
+    Color(Color),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Index(index) => write!(f, "index {}", index),
+            Target::Color(color) => write!(f, "{}", color),
+        }
+    }
+}
+
+/// A palette edit parsed from `INDEX=#RRGGBB[AA]` or `#RRGGBB[AA]=#RRGGBB[AA]`.
+/// Without an alpha value on the right the entries keep their current alpha.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Replacement {
+    pub target: Target,
+    pub color: Color,
+}
+
+impl FromStr for Replacement {
+    type Err = PaletteError;
+
+    fn from_str(s: &str) -> Result<Self, PaletteError> {
+        let (target, color) = s
+            .split_once('=')
+            .ok_or_else(|| PaletteError::InvalidReplacement(s.to_string()))?;
+        let target = match target.parse::<usize>() {
+            Ok(index) => Target::Index(index),
+            Err(_) => Target::Color(target.parse()?),
+        };
+        Ok(Replacement {
+            target,
+            color: color.parse()?,
+        })
+    }
+}
+
+/// The colors of an indexed image, or the suggested palette of a truecolor one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    /// RGBA entries; alpha is 255 unless tRNS says otherwise
+    pub entries: Vec<[u8; 4]>,
+    /// Whether tRNS holds the alpha values, which is only the case for indexed images
+    indexed: bool,
+}
+
+impl Palette {
+    pub const CHUNK_TYPE: &'static str = "PLTE";
+
+    /// A palette for an indexed image
+    pub fn new(entries: Vec<[u8; 4]>) -> Self {
+        Palette {
+            entries,
+            indexed: true,
+        }
+    }
+
+    /// Reads PLTE and, for indexed images, the alpha values in tRNS. The number of
+    /// entries is checked against the bit depth.
+    pub fn from_png(png: &Png) -> Result<Self, PaletteError> {
+        let ihdr = png.ihdr().ok();
+        let color_type = ihdr.as_ref().map(|ihdr| ihdr.color_type);
+        let indexed = color_type == Some(ColorType::Indexed);
+        let plte = match png.chunk_by_type(Self::CHUNK_TYPE) {
+            Some(plte) => plte.data(),
+            None => return Err(PaletteError::Missing),
+        };
+        if plte.is_empty() || plte.len() % 3 != 0 || plte.len() > 768 {
+            return Err(PaletteError::InvalidLength(plte.len()));
+        }
+        let entries = plte.len() / 3;
+        match ihdr {
+            Some(ihdr)
+                if matches!(
+                    ihdr.color_type,
+                    ColorType::Grayscale | ColorType::GrayscaleAlpha
+                ) =>
+            {
+                return Err(PaletteError::NotAllowed(ihdr.color_type))
+            }
+            Some(ihdr) if indexed && entries > 1 << ihdr.bit_depth => {
+                return Err(PaletteError::TooManyEntries {
+                    entries,
+                    bit_depth: ihdr.bit_depth,
+                })
+            }
+            _ => {}
+        }
+
+        let alpha = match png.chunk_by_type("tRNS") {
+            Some(trns) if indexed => trns.data(),
+            _ => &[],
+        };
+        if alpha.len() > entries {
+            return Err(PaletteError::TooMuchAlpha {
+                alpha: alpha.len(),
+                entries,
+            });
+        }
+        Ok(Palette {
+            entries: plte
+                .chunks_exact(3)
+                .enumerate()
+                .map(|(i, rgb)| [rgb[0], rgb[1], rgb[2], alpha.get(i).copied().unwrap_or(255)])
+                .collect(),
+            indexed,
+        })
+    }
+
+    /// A palette holding each distinct color of `pixels` once, or `None` if there are
+    /// more than `max`. Translucent colors come first so tRNS stays short.
+    pub fn exact(pixels: &[[u8; 4]], max: usize) -> Option<Self> {
+        let mut entries = Vec::new();
+        let mut seen = HashSet::new();
+        for &pixel in pixels.iter() {
+            if seen.insert(pixel) {
+                if entries.len() == max {
+                    return None;
+                }
+                entries.push(pixel);
+            }
+        }
+        entries.sort_by_key(|entry| entry[3] == 255);
+        Some(Palette::new(entries))
+    }
+
+    /// A palette of at most `max` colors for `pixels`. Images with more colors are
+    /// reduced by median cut: the set of colors is split at the weighted median of its
+    /// widest channel until there are `max` boxes, and each box becomes the average of
+    /// its colors.
+    pub fn quantize(pixels: &[[u8; 4]], max: usize) -> Self {
+        if let Some(palette) = Palette::exact(pixels, max) {
+            return palette;
+        }
+        let mut counts: HashMap<[u8; 4], usize> = HashMap::new();
+        for &pixel in pixels.iter() {
+            *counts.entry(pixel).or_default() += 1;
+        }
+        let mut colors: Vec<([u8; 4], usize)> = counts.into_iter().collect();
+        colors.sort_unstable();
+
+        let mut boxes = vec![colors];
+        while boxes.len() < max {
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, colors)| colors.len() > 1)
+                .flat_map(|(i, colors)| (0..4).map(move |c| (i, c, channel_range(colors, c))))
+                .max_by_key(|&(_, _, range)| range);
+            let Some((i, channel, _)) = widest else {
+                break;
+            };
+            let mut colors = boxes.swap_remove(i);
+            colors.sort_by_key(|(color, _)| color[channel]);
+            let total: usize = colors.iter().map(|(_, count)| count).sum();
+            let mut seen = 0;
+            let median = colors
+                .iter()
+                .position(|(_, count)| {
+                    seen += count;
+                    seen * 2 >= total
+                })
+                .unwrap_or(0);
+            let rest = colors.split_off((median + 1).min(colors.len() - 1));
+            boxes.push(colors);
+            boxes.push(rest);
+        }
+
+        let mut entries: Vec<[u8; 4]> = boxes
+            .iter()
+            .map(|colors| {
+                let total: usize = colors.iter().map(|(_, count)| count).sum();
+                [0, 1, 2, 3].map(|c| {
+                    let sum: usize = colors.iter().map(|(color, n)| color[c] as usize * n).sum();
+                    ((sum + total / 2) / total) as u8
+                })
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry[3] == 255);
+        Palette::new(entries)
+    }
+
+    /// The index of the entry closest to `color`
+    pub fn nearest(&self, color: [u8; 4]) -> usize {
+        let distance = |entry: &[u8; 4]| -> i32 {
+            (0..4)
+                .map(|c| (entry[c] as i32 - color[c] as i32).pow(2))
+                .sum()
+        };
+        (0..self.len())
+            .min_by_key(|&i| distance(&self.entries[i]))
+            .unwrap_or(0)
+    }
+
+    /// The smallest bit depth whose indices reach every entry
+    pub fn bit_depth(&self) -> u8 {
+        match self.len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Goes with len, though nothing needs it yet
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Applies `replacement`, returning how many entries changed
+    pub fn replace(&mut self, replacement: &Replacement) -> Result<usize, PaletteError> {
+        if replacement.color.alpha.is_some_and(|a| a != 255) && !self.indexed {
+            return Err(PaletteError::AlphaNotAllowed);
+        }
+        let entries = self.len();
+        let matches = |i: usize, entry: &[u8; 4]| match replacement.target {
+            Target::Index(index) => i == index,
+            Target::Color(color) => {
+                entry[..3] == color.rgb && color.alpha.is_none_or(|a| a == entry[3])
+            }
+        };
+        if let Target::Index(index) = replacement.target {
+            if index >= entries {
+                return Err(PaletteError::IndexOutOfRange { index, entries });
+            }
+        }
+        let mut changed = 0;
+        for (i, entry) in self.entries.iter_mut().enumerate() {
+            if matches(i, entry) {
+                let [r, g, b] = replacement.color.rgb;
+                *entry = [r, g, b, replacement.color.alpha.unwrap_or(entry[3])];
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Writes the palette back as PLTE and, for indexed images, a tRNS chunk holding the
+    /// alpha values up to the last translucent entry. tRNS is removed when every entry
+    /// is opaque.
+    pub fn write_to(&self, png: &mut Png) {
+        let plte: Vec<u8> = self.entries.iter().flat_map(|e| e[..3].to_vec()).collect();
+        png.set_chunk(Chunk::new(
+            ChunkType::from_str(Self::CHUNK_TYPE).unwrap(),
+            plte,
+        ));
+        if !self.indexed {
+            return;
+        }
+
+        let opaque_tail = self
+            .entries
+            .iter()
+            .rev()
+            .take_while(|entry| entry[3] == 255)
+            .count();
+        let trns: Vec<u8> = self.entries[..self.len() - opaque_tail]
+            .iter()
+            .map(|entry| entry[3])
+            .collect();
+        if trns.is_empty() {
+            let _ = png.remove_first_chunk("tRNS");
+            return;
+        }
+        let chunk = Transparency::PaletteAlpha(trns).to_chunk();
+        // tRNS has to follow PLTE, so put it right after it
+        if png.chunk_by_type("tRNS").is_some() {
+            png.set_chunk(chunk);
+        } else {
+            let position = png
+                .chunks()
+                .iter()
+                .position(|c| c.chunk_type().to_string() == Self::CHUNK_TYPE)
+                .unwrap();
+            png.chunks_mut().insert(position + 1, chunk);
+        }
+    }
+
+    /// The palette as a GIMP palette file
+    pub fn to_gpl(&self, name: &str) -> String {
+        let mut gpl = format!("GIMP Palette\nName: {}\nColumns: 16\n#\n", name);
+        for (i, [r, g, b, a]) in self.entries.iter().enumerate() {
+            gpl += &format!("{:3} {:3} {:3}\tIndex {}", r, g, b, i);
+            if *a != 255 {
+                gpl += &format!(" (alpha {})", a);
+            }
+            gpl.push('\n');
+        }
+        gpl
+    }
+}
+
+/// The spread of `channel` among `colors`
+fn channel_range(colors: &[([u8; 4], usize)], channel: usize) -> u8 {
+    let values = colors.iter().map(|(color, _)| color[channel]);
+    values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+}
+
+impl fmt::Display for Palette {
+    /// A table with one row per entry
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Index    R    G    B    A  Hex")?;
+        for (i, [r, g, b, a]) in self.entries.iter().enumerate() {
+            let color = Color {
+                rgb: [*r, *g, *b],
+                alpha: Some(*a).filter(|&a| a != 255),
+            };
+            writeln!(f, "{:5} {:4} {:4} {:4} {:4}  {}", i, r, g, b, a, color)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::Ihdr;
+
+    fn png(color_type: ColorType, bit_depth: u8, plte: &[u8], trns: Option<&[u8]>) -> Png {
+        let ihdr = Ihdr {
+            width: 1,
+            height: 1,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+        let mut chunks = vec![
+            ihdr.to_chunk(),
+            Chunk::new(ChunkType::from_str("PLTE").unwrap(), plte.to_vec()),
+        ];
+        if let Some(trns) = trns {
+            chunks.push(Chunk::new(
+                ChunkType::from_str("tRNS").unwrap(),
+                trns.to_vec(),
+            ));
+        }
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_from_png_merges_alpha() {
+        let png = png(ColorType::Indexed, 2, &[1, 2, 3, 4, 5, 6], Some(&[0]));
+        let palette = Palette::from_png(&png).unwrap();
+        assert_eq!(palette.entries, vec![[1, 2, 3, 0], [4, 5, 6, 255]]);
+
+        // In truecolor images tRNS is a color key rather than palette alpha
+        let png = self::png(ColorType::Rgb, 8, &[1, 2, 3], Some(&[0, 0, 0, 0, 0, 0]));
+        assert_eq!(
+            Palette::from_png(&png).unwrap().entries,
+            vec![[1, 2, 3, 255]]
+        );
+    }
+
+    #[test]
+    fn test_from_png_checks() {
+        let three = [0; 9];
+        assert_eq!(
+            Palette::from_png(&png(ColorType::Indexed, 1, &three, None)),
+            Err(PaletteError::TooManyEntries {
+                entries: 3,
+                bit_depth: 1
+            })
+        );
+        assert_eq!(
+            Palette::from_png(&png(ColorType::Indexed, 2, &three, Some(&[0; 4]))),
+            Err(PaletteError::TooMuchAlpha {
+                alpha: 4,
+                entries: 3
+            })
+        );
+        assert_eq!(
+            Palette::from_png(&png(ColorType::Grayscale, 8, &three, None)),
+            Err(PaletteError::NotAllowed(ColorType::Grayscale))
+        );
+        assert_eq!(
+            Palette::from_png(&png(ColorType::Indexed, 8, &[0; 4], None)),
+            Err(PaletteError::InvalidLength(4))
+        );
+    }
+
+    #[test]
+    fn test_replace_and_write() {
+        let mut png = png(ColorType::Indexed, 8, &[0, 0, 0, 9, 9, 9, 0, 0, 0], None);
+        let mut palette = Palette::from_png(&png).unwrap();
+        let by_color = Replacement::from_str("#000000=#ff000080").unwrap();
+        assert_eq!(palette.replace(&by_color).unwrap(), 2);
+        let by_index = Replacement::from_str("2=#00ff00").unwrap();
+        assert_eq!(palette.replace(&by_index).unwrap(), 1);
+        assert!(palette
+            .replace(&Replacement::from_str("3=#00ff00").unwrap())
+            .is_err());
+
+        palette.write_to(&mut png);
+        assert_eq!(png.chunks()[2].chunk_type().to_string(), "tRNS");
+        assert_eq!(png.chunk_by_type("tRNS").unwrap().data(), &[128, 255, 128]);
+        assert_eq!(Palette::from_png(&png).unwrap(), palette);
+
+        palette
+            .replace(&Replacement::from_str("#ff0000=#ff0000ff").unwrap())
+            .unwrap();
+        palette
+            .replace(&Replacement::from_str("2=#00ff00ff").unwrap())
+            .unwrap();
+        palette.write_to(&mut png);
+        assert!(png.chunk_by_type("tRNS").is_none());
+    }
+
+    #[test]
+    fn test_gpl() {
+        let palette = Palette::new(vec![[255, 0, 0, 255], [0, 0, 255, 0]]);
+        assert_eq!(
+            palette.to_gpl("test"),
+            "GIMP Palette\nName: test\nColumns: 16\n#\n\
+             255   0   0\tIndex 0\n  0   0 255\tIndex 1 (alpha 0)\n"
+        );
+    }
+    #[test]
+    fn test_quantize() {
+        let pixels = [[9, 9, 9, 255], [0, 0, 0, 0], [9, 9, 9, 255]];
+        let exact = Palette::quantize(&pixels, 256);
+        assert_eq!(exact.entries, [[0, 0, 0, 0], [9, 9, 9, 255]]);
+        assert_eq!(exact.bit_depth(), 1);
+
+        let pixels: Vec<[u8; 4]> = (0..=255)
+            .flat_map(|v| [[v, 0, 0, 255], [0, v, 0, 255]])
+            .collect();
+        assert!(Palette::exact(&pixels, 256).is_none());
+        let palette = Palette::quantize(&pixels, 16);
+        assert_eq!(palette.len(), 16);
+        assert_eq!(palette.bit_depth(), 4);
+        let nearest = palette.entries[palette.nearest([250, 0, 0, 255])];
+        assert!(nearest[0] > 200 && nearest[1] < 20, "{:?}", nearest);
+    }
+}
@@ -5,20 +5,105 @@ use std::io::{BufReader, Read};
 use std::path::Path;
 use std::str::FromStr;
 
-use crate::chunk;
 use crate::{Error, Result};
+use crate::ancillary;
+use crate::apng::{Animation, AnimationControl, FrameControl, FrameData};
+use crate::cgbi;
 use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
+use crate::color::{self, Chromaticities, Cicp, Gamma, RenderingIntent};
+use crate::hdr::{ContentLightLevel, MasteringDisplay};
+use crate::icc::IccProfile;
+use crate::time::Time;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::image::Rgba16Image;
+use crate::palette::Palette;
+use crate::text::InternationalText;
+use sha2::{Digest, Sha256};
+
+/// The part of the chunk sequence a chunk sits in, as constrained by the PNG spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// After IHDR and before PLTE and the image data
+    BeforePlte,
+    /// After PLTE and before the image data
+    BeforeIdat,
+    /// After the image data and before IEND
+    AfterIdat,
+}
+
+impl Placement {
+    /// Where chunks of a registered type must appear, or `None` if they may appear
+    /// anywhere between IHDR and IEND.
+    pub fn required(chunk_type: &ChunkType) -> Option<Placement> {
+        match chunk_type.to_string().as_str() {
+            "cHRM" | "gAMA" | "iCCP" | "sBIT" | "sRGB" | "cICP" | "mDCV" | "cLLI" => {
+                Some(Placement::BeforePlte)
+            }
+            "tRNS" | "bKGD" | "hIST" | "pHYs" | "sPLT" | "eXIf" | "acTL" | "oFFs" | "pCAL"
+            | "sCAL" | "sTER" => Some(Placement::BeforeIdat),
+            _ => None,
+        }
+    }
+}
+
+/// The file formats that share the PNG chunk layout, told apart by their signatures.
+/// MNG holds a multi-image animation and ends with MEND; JNG holds a JPEG-compressed
+/// image with an optional PNG-style alpha channel.
+/// http://www.libpng.org/pub/mng/spec/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Mng,
+    Jng,
+}
+
+impl Format {
+    /// The eight bytes every file of this format starts with
+    pub fn signature(&self) -> [u8; 8] {
+        match self {
+            Format::Png => Png::STANDARD_HEADER,
+            Format::Mng => [138, 77, 78, 71, 13, 10, 26, 10],
+            Format::Jng => [139, 74, 78, 71, 13, 10, 26, 10],
+        }
+    }
 
-#[allow(unused_imports)]
-#[allow(dead_code)]
-/// A PNG container as described by the PNG spec
+    /// The format whose signature starts `bytes`, if any
+    pub fn detect(bytes: &[u8]) -> Option<Format> {
+        [Format::Png, Format::Mng, Format::Jng]
+            .into_iter()
+            .find(|format| bytes.starts_with(&format.signature()))
+    }
+
+    /// The chunk that ends a file of this format
+    pub fn end_chunk(&self) -> &'static str {
+        match self {
+            Format::Mng => "MEND",
+            Format::Png | Format::Jng => "IEND",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Png => f.write_str("PNG"),
+            Format::Mng => f.write_str("MNG"),
+            Format::Jng => f.write_str("JNG"),
+        }
+    }
+}
+
+/// A PNG container as described by the PNG spec. MNG and JNG files use the same
+/// container with a different signature, see [`Format`].
 /// http://www.libpng.org/pub/png/spec/1.2/PNG-Contents.html
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Png {
     // Write me!
     chunks:Vec<Chunk>,
     header:[u8;8],
+    /// Bytes found after the IEND chunk, which decoders ignore
+    trailer:Vec<u8>,
 }
 
 impl Png {
@@ -27,7 +112,12 @@ impl Png {
 
     /// Creates a `Png` from a list of chunks using the correct header
     pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
-        Png{chunks:chunks,header:Png::STANDARD_HEADER}
+        Png{chunks,header:Png::STANDARD_HEADER,trailer:Vec::new()}
+    }
+
+    /// Creates an MNG, JNG or PNG file from a list of chunks
+    pub fn from_chunks_with_format(format: Format, chunks: Vec<Chunk>) -> Self {
+        Png{chunks,header:format.signature(),trailer:Vec::new()}
     }
 
     /// Creates a `Png` from a file path
@@ -39,9 +129,15 @@ impl Png {
         Png::try_from(bytes.as_ref())
     }
 
-    /// Appends a chunk to the end of this `Png` file's `Chunk` list.
+    /// Appends a chunk to the end of this `Png` file's `Chunk` list, keeping IEND
+    /// (MEND for MNG) as the final chunk if there is one.
     pub fn append_chunk(&mut self, chunk: Chunk) {
-        self.chunks.push(chunk);
+        match self.chunks.last() {
+            Some(last) if last.chunk_type().to_string() == self.format().end_chunk() => {
+                self.chunks.insert(self.chunks.len() - 1, chunk)
+            }
+            _ => self.chunks.push(chunk),
+        }
     }
 
     /// Searches for a `Chunk` with the specified `chunk_type` and removes the first
@@ -56,7 +152,12 @@ impl Png {
 
     /// The header of this PNG.
     pub fn header(&self) -> &[u8; 8] {
-        &Png::STANDARD_HEADER
+        &self.header
+    }
+
+    /// Whether this is a PNG, MNG or JNG file, going by its header.
+    pub fn format(&self) -> Format {
+        Format::detect(self.header()).unwrap_or(Format::Png)
     }
 
     /// Lists the `Chunk`s stored in this `Png`
@@ -70,14 +171,162 @@ impl Png {
         self.chunks.iter().find(|c| c.chunk_type().to_string() == chunk_type)
     }
 
+    /// Mutable access to the `Chunk`s stored in this `Png`
+    pub fn chunks_mut(&mut self) -> &mut Vec<Chunk> {
+        &mut self.chunks
+    }
+
+    /// Parses the IHDR chunk, which must be the first chunk of the file, or the second
+    /// after the CgBI chunk of an Apple-optimized image.
+    pub fn ihdr(&self) -> Result<Ihdr> {
+        let chunk = self
+            .chunks
+            .iter()
+            .find(|c| c.chunk_type().to_string() != cgbi::CHUNK_TYPE)
+            .ok_or("PNG has no chunks")?;
+        Ok(Ihdr::try_from(chunk)?)
+    }
+
+    /// Returns the zlib stream formed by concatenating the data of every IDAT chunk.
+    pub fn image_data(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect()
+    }
+
+    /// Replaces all IDAT chunks with a single IDAT chunk holding `data`, placed where the
+    /// first IDAT chunk used to be.
+    pub fn set_image_data(&mut self, data: Vec<u8>) {
+        self.set_image_chunks(vec![data]);
+    }
+
+    /// Replaces all IDAT chunks with one IDAT chunk for each element of `parts`, placed
+    /// where the first IDAT chunk used to be.
+    pub fn set_image_chunks(&mut self, parts: Vec<Vec<u8>>) {
+        let position = self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type().to_string() == "IDAT")
+            .or_else(|| self.chunks.iter().position(|c| c.chunk_type().to_string() == "IEND"))
+            .unwrap_or(self.chunks.len());
+        self.chunks.retain(|c| c.chunk_type().to_string() != "IDAT");
+        let idats = parts
+            .into_iter()
+            .map(|data| Chunk::new(ChunkType::from_str("IDAT").unwrap(), data));
+        self.chunks.splice(position..position, idats);
+    }
+
+    /// Replaces the first chunk of the same type as `chunk`, or inserts it where the spec
+    /// requires, defaulting to just before the image data.
+    pub fn set_chunk(&mut self, chunk: Chunk) {
+        let chunk_type = chunk.chunk_type().to_string();
+        if let Some(i) = self.chunks.iter().position(|c| c.chunk_type().to_string() == chunk_type) {
+            self.chunks[i] = chunk;
+            return;
+        }
+        let placement = Placement::required(chunk.chunk_type()).unwrap_or(Placement::BeforeIdat);
+        self.insert_chunk(chunk, placement);
+    }
+
+    /// Inserts `chunk` at the end of the part of the file given by `placement`. An MNG
+    /// has no single image to place chunks around, so they go at the end of the file.
+    pub fn insert_chunk(&mut self, chunk: Chunk, placement: Placement) {
+        if self.format() == Format::Mng {
+            return self.append_chunk(chunk);
+        }
+        let find = |chunk_type: &str| {
+            self.chunks.iter().position(|c| c.chunk_type().to_string() == chunk_type)
+        };
+        let end = self.chunks.len();
+        let iend = find("IEND").unwrap_or(end);
+        let idat = find("IDAT").unwrap_or(iend);
+        let position = match placement {
+            Placement::BeforePlte => find("PLTE").unwrap_or(idat),
+            Placement::BeforeIdat => idat,
+            Placement::AfterIdat => iend,
+        };
+        self.chunks.insert(position, chunk);
+    }
+
+    /// The part of the file the chunk at `index` sits in.
+    pub fn placement_of(&self, index: usize) -> Placement {
+        let find = |chunk_type: &str| {
+            self.chunks.iter().position(|c| c.chunk_type().to_string() == chunk_type)
+        };
+        match (find("PLTE"), find("IDAT")) {
+            (Some(plte), _) if index < plte => Placement::BeforePlte,
+            (_, Some(idat)) if index > idat => Placement::AfterIdat,
+            _ => Placement::BeforeIdat,
+        }
+    }
+
+    /// Finds the first iTXt chunk with the given keyword. Text chunks that fail to parse
+    /// are skipped.
+    pub fn international_text(&self, keyword: &str) -> Option<InternationalText> {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == InternationalText::CHUNK_TYPE)
+            .filter_map(|c| InternationalText::try_from(c).ok())
+            .find(|text| text.keyword == keyword)
+    }
+
+    /// Replaces the first iTXt chunk with the same keyword as `text`, or adds one before
+    /// the image data.
+    pub fn set_international_text(&mut self, text: InternationalText) {
+        let existing = self.chunks.iter().position(|c| {
+            c.chunk_type().to_string() == InternationalText::CHUNK_TYPE
+                && InternationalText::try_from(c).is_ok_and(|t| t.keyword == text.keyword)
+        });
+        match existing {
+            Some(i) => self.chunks[i] = text.to_chunk(),
+            None => self.insert_chunk(text.to_chunk(), Placement::BeforeIdat),
+        }
+    }
+
+    /// SHA-256 of the decoded pixels, expanded to 16-bit RGBA, and the image dimensions.
+    /// Two files with the same digest show the same image regardless of their ancillary
+    /// chunks, IDAT split, compression, filtering, interlacing or pixel format. The color
+    /// of fully transparent pixels is hashed too, since it can still hide data.
+    pub fn pixel_digest(&self) -> Result<[u8; 32]> {
+        let image = Rgba16Image::from_png(self)?;
+        let mut hasher = Sha256::new();
+        hasher.update(image.width.to_be_bytes());
+        hasher.update(image.height.to_be_bytes());
+        for pixel in image.pixels.iter() {
+            for sample in pixel {
+                hasher.update(sample.to_be_bytes());
+            }
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// The bytes that follow the IEND chunk, empty for a well-formed file.
+    pub fn trailer(&self) -> &[u8] {
+        &self.trailer
+    }
+
+    /// Replaces the bytes written after the IEND chunk.
+    pub fn set_trailer(&mut self, trailer: Vec<u8>) {
+        self.trailer = trailer;
+    }
+
+    /// Removes and returns the bytes after the IEND chunk.
+    pub fn take_trailer(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.trailer)
+    }
+
     /// Returns this `Png` as a byte sequence.
-    /// These bytes will contain the header followed by the bytes of all of the chunks.
+    /// These bytes will contain the header followed by the bytes of all of the chunks
+    /// and then any trailing data.
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend_from_slice(&self.header);
         for chunk in self.chunks.iter(){
             bytes.extend_from_slice(&chunk.as_bytes());
         }
+        bytes.extend_from_slice(&self.trailer);
         bytes
     }
 }
@@ -86,13 +335,93 @@ impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(bytes: &[u8]) -> Result<Png> {
-        todo!()
+        let format = Format::detect(bytes).ok_or("Invalid PNG header")?;
+
+        let mut chunks = Vec::new();
+        let mut offset = Png::STANDARD_HEADER.len();
+        while offset < bytes.len() {
+            let chunk = Chunk::try_from(&bytes[offset..])?;
+            offset += Chunk::METADATA_LENGTH + chunk.length() as usize;
+            let is_end = chunk.chunk_type().to_string() == format.end_chunk();
+            chunks.push(chunk);
+            if is_end {
+                break;
+            }
+        }
+        let mut png = Png::from_chunks_with_format(format, chunks);
+        png.trailer = bytes[offset..].to_vec();
+        Ok(png)
     }
 }
 
+/// A one-line summary of the contents of chunk types that have a typed representation
+pub fn describe(chunk: &Chunk, ihdr: Option<&Ihdr>) -> Option<String> {
+    fn show<T: fmt::Display, E: fmt::Display>(result: std::result::Result<T, E>) -> String {
+        match result {
+            Ok(value) => value.to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+    let description = match chunk.chunk_type().to_string().as_str() {
+        IccProfile::CHUNK_TYPE => show(IccProfile::try_from(chunk)),
+        Gamma::CHUNK_TYPE => show(Gamma::try_from(chunk)),
+        Chromaticities::CHUNK_TYPE => show(Chromaticities::try_from(chunk)),
+        RenderingIntent::CHUNK_TYPE => show(RenderingIntent::try_from(chunk)),
+        Cicp::CHUNK_TYPE => show(Cicp::try_from(chunk)),
+        MasteringDisplay::CHUNK_TYPE => show(MasteringDisplay::try_from(chunk)),
+        ContentLightLevel::CHUNK_TYPE => show(ContentLightLevel::try_from(chunk)),
+        Time::CHUNK_TYPE => show(Time::try_from(chunk)),
+        AnimationControl::CHUNK_TYPE => show(AnimationControl::try_from(chunk)),
+        FrameControl::CHUNK_TYPE => show(FrameControl::try_from(chunk)),
+        FrameData::CHUNK_TYPE => show(FrameData::try_from(chunk)),
+        _ => return ancillary::describe(chunk, ihdr),
+    };
+    Some(description)
+}
+
 impl fmt::Display for Png {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        todo!()
+        match self.format() {
+            Format::Png => writeln!(f, "Png {{")?,
+            format => writeln!(f, "Png ({}) {{", format)?,
+        }
+        if let Ok(ihdr) = self.ihdr() {
+            writeln!(f, "  Image: {}", ihdr)?;
+        }
+        writeln!(f, "  Chunks: {}", self.chunks.len())?;
+        let ihdr = self.ihdr().ok();
+        for chunk in self.chunks.iter() {
+            write!(f, "    {} ({} bytes)", chunk.chunk_type(), chunk.length())?;
+            match describe(chunk, ihdr.as_ref()) {
+                Some(description) => writeln!(f, ": {}", description)?,
+                None => writeln!(f)?,
+            }
+        }
+        if !self.trailer.is_empty() {
+            writeln!(f, "  Trailer: {} bytes after {}", self.trailer.len(), self.format().end_chunk())?;
+        }
+        for warning in color::warnings(self).into_iter().chain(ancillary::warnings(self)) {
+            writeln!(f, "  Warning: {}", warning)?;
+        }
+        let indexed = ihdr.is_some_and(|ihdr| ihdr.color_type == ColorType::Indexed);
+        if indexed || self.chunk_by_type(Palette::CHUNK_TYPE).is_some() {
+            if let Err(e) = Palette::from_png(self) {
+                writeln!(f, "  Warning: {}", e)?;
+            }
+        }
+        if cgbi::is_cgbi(self) {
+            writeln!(
+                f,
+                "  Warning: Apple CgBI image that other decoders can't read, see `pngme normalize`"
+            )?;
+        }
+        if self.chunk_by_type(AnimationControl::CHUNK_TYPE).is_some() {
+            if let Err(e) = Animation::from_png(self) {
+                writeln!(f, "  Warning: {}", e)?;
+            }
+        }
+        writeln!(f, "}}")?;
+        Ok(())
     }
 }
 
@@ -132,8 +461,6 @@ mod tests {
     }
 
     fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk> {
-        use std::str::FromStr;
-
         let chunk_type = ChunkType::from_str(chunk_type)?;
         let data: Vec<u8> = data.bytes().collect();
 
@@ -255,6 +582,100 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_trailer() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"polyglot");
+        let mut png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.trailer(), b"polyglot");
+        assert_eq!(png.chunks().last().unwrap().chunk_type().to_string(), "IEND");
+        assert_eq!(png.as_bytes(), bytes);
+
+        assert_eq!(png.take_trailer(), b"polyglot");
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_mng_and_jng() {
+        let mng = Png::from_chunks_with_format(
+            Format::Mng,
+            vec![
+                chunk_from_strings("MHDR", "header").unwrap(),
+                chunk_from_strings("IHDR", "embedded").unwrap(),
+                chunk_from_strings("IEND", "").unwrap(),
+                chunk_from_strings("MEND", "").unwrap(),
+            ],
+        );
+        let mut bytes = mng.as_bytes();
+        assert_eq!(&bytes[..4], &[138, 77, 78, 71]);
+        bytes.extend_from_slice(b"after");
+        let mut mng = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(mng.format(), Format::Mng);
+        assert_eq!(mng.chunks().len(), 4);
+        assert_eq!(mng.trailer(), b"after");
+
+        mng.append_chunk(chunk_from_strings("ruSt", "hidden").unwrap());
+        mng.set_chunk(chunk_from_strings("tEXt", "more").unwrap());
+        let types: Vec<String> = mng.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["MHDR", "IHDR", "IEND", "ruSt", "tEXt", "MEND"]);
+        let mng = Png::try_from(mng.as_bytes().as_ref()).unwrap();
+        assert_eq!(mng.chunk_by_type("ruSt").unwrap().data_as_string().unwrap(), "hidden");
+
+        let jng = Png::from_chunks_with_format(
+            Format::Jng,
+            vec![
+                chunk_from_strings("JHDR", "header").unwrap(),
+                chunk_from_strings("JDAT", "jpeg").unwrap(),
+                chunk_from_strings("IEND", "").unwrap(),
+            ],
+        );
+        let jng = Png::try_from(jng.as_bytes().as_ref()).unwrap();
+        assert_eq!(jng.format(), Format::Jng);
+        assert_eq!(jng.header(), &[139, 74, 78, 71, 13, 10, 26, 10]);
+        assert!(jng.to_string().starts_with("Png (JNG) {"));
+    }
+
+    #[test]
+    fn test_pixel_digest_ignores_metadata_and_chunking() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let digest = png.pixel_digest().unwrap();
+
+        let mut other = Png::try_from(&PNG_FILE[..]).unwrap();
+        other.remove_first_chunk("RuSt").unwrap();
+        other.remove_first_chunk("gAMA").unwrap();
+        let data = other.image_data();
+        let (first, second) = data.split_at(data.len() / 2);
+        other.set_image_chunks(vec![first.to_vec(), second.to_vec()]);
+        assert_eq!(other.pixel_digest().unwrap(), digest);
+
+        let image = crate::image::RawImage::from_png(&png).unwrap();
+        let mut interlaced = crate::image::RawImage::new(
+            Ihdr { interlaced: true, ..image.ihdr().clone() },
+            image.data().to_vec(),
+        )
+        .unwrap();
+        other.chunks_mut()[0] = interlaced.ihdr().to_chunk();
+        other.set_image_data(interlaced.encode(1));
+        assert_eq!(other.pixel_digest().unwrap(), digest);
+
+        // An opaque pixel unlike any in the original
+        let channels = interlaced.ihdr().color_type.channels();
+        for channel in 0..channels {
+            let value = if channel + 1 == channels { 255 } else { 77 };
+            interlaced.set_sample(3, 4, channel, value);
+        }
+        other.set_image_data(interlaced.encode(1));
+        assert_ne!(other.pixel_digest().unwrap(), digest);
+
+        // The color of a fully transparent pixel still counts
+        interlaced.set_sample(3, 4, channels - 1, 0);
+        other.set_image_data(interlaced.encode(1));
+        let transparent = other.pixel_digest().unwrap();
+        interlaced.set_sample(3, 4, 0, 78);
+        other.set_image_data(interlaced.encode(1));
+        assert_ne!(other.pixel_digest().unwrap(), transparent);
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
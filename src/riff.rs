@@ -0,0 +1,318 @@
+//! RIFF, the chunked container behind WebP and WAV.
+//! https://developers.google.com/speed/webp/docs/riff_container
+//!
+//! A RIFF file is a single `RIFF` chunk whose data starts with a form type such as
+//! `WEBP` or `WAVE` followed by the sub-chunks. Each chunk has a four-character ID and a
+//! little-endian length, and odd-length data is padded to an even size. There are no
+//! checksums.
+
+use std::convert::TryFrom;
+use std::fmt;
+use thiserror::Error;
+
+use crate::chunk_type::ChunkType;
+use crate::container::Container;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum RiffError {
+    #[error("Not a RIFF file")]
+    InvalidHeader,
+    #[error("{0} chunk is truncated")]
+    Truncated(String),
+    #[error("Chunk not found")]
+    ChunkNotFound,
+    #[error("Can't read the WebP image size: {0}")]
+    InvalidWebp(String),
+}
+
+/// A chunk inside the RIFF form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiffChunk {
+    /// Four characters, which unlike PNG chunk types may include spaces, as in `VP8 `
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl RiffChunk {
+    /// Bytes a chunk occupies besides its data and padding: ID and length
+    pub const HEADER_LENGTH: usize = 8;
+
+    pub fn new(id: [u8; 4], data: Vec<u8>) -> Self {
+        RiffChunk { id, data }
+    }
+
+    pub fn id_string(&self) -> String {
+        String::from_utf8_lossy(&self.id).into_owned()
+    }
+
+    /// Length on disk, including the header and the pad byte after odd-length data
+    fn padded_length(&self) -> usize {
+        Self::HEADER_LENGTH + self.data.len() + self.data.len() % 2
+    }
+
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.id);
+        bytes.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        if self.data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+    }
+}
+
+/// A RIFF file such as a WebP image or WAV recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Riff {
+    /// The four characters after the RIFF length, such as `WEBP` or `WAVE`
+    form_type: [u8; 4],
+    chunks: Vec<RiffChunk>,
+    /// Bytes after the end of the RIFF chunk
+    trailer: Vec<u8>,
+}
+
+impl Riff {
+    pub const SIGNATURE: [u8; 4] = *b"RIFF";
+
+    pub fn new(form_type: [u8; 4], chunks: Vec<RiffChunk>) -> Self {
+        Riff {
+            form_type,
+            chunks,
+            trailer: Vec::new(),
+        }
+    }
+
+    pub fn form_type(&self) -> &[u8; 4] {
+        &self.form_type
+    }
+
+    pub fn chunks(&self) -> &[RiffChunk] {
+        &self.chunks
+    }
+
+    pub fn chunk_by_id(&self, id: &str) -> Option<&RiffChunk> {
+        self.chunks.iter().find(|c| c.id[..] == *id.as_bytes())
+    }
+
+    pub fn is_webp(&self) -> bool {
+        self.form_type == *b"WEBP"
+    }
+
+    /// Appends a chunk at the end of the form. WebP only allows chunks it doesn't know
+    /// in the extended format, so a simple WebP image gets a VP8X header first.
+    pub fn append_chunk(&mut self, chunk: RiffChunk) -> Result<(), RiffError> {
+        if self.is_webp() && self.chunk_by_id("VP8X").is_none() {
+            let header = self.extended_header()?;
+            self.chunks.insert(0, header);
+        }
+        self.chunks.push(chunk);
+        Ok(())
+    }
+
+    pub fn remove_first_chunk(&mut self, id: &str) -> Result<RiffChunk, RiffError> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|c| c.id[..] == *id.as_bytes())
+            .ok_or(RiffError::ChunkNotFound)?;
+        Ok(self.chunks.remove(index))
+    }
+
+    /// A VP8X chunk describing the simple-format image in this file: no flags other
+    /// than alpha for lossless images that use it, and the canvas size of the bitstream
+    fn extended_header(&self) -> Result<RiffChunk, RiffError> {
+        let invalid = |reason: &str| RiffError::InvalidWebp(reason.to_string());
+        let (width, height, alpha) = if let Some(vp8) = self.chunk_by_id("VP8 ") {
+            // A 3 byte frame tag, the start code, then 14-bit width and height
+            match vp8.data.get(..10) {
+                Some([_, _, _, 0x9d, 0x01, 0x2a, w0, w1, h0, h1]) => (
+                    u16::from_le_bytes([*w0, *w1]) as u32 & 0x3fff,
+                    u16::from_le_bytes([*h0, *h1]) as u32 & 0x3fff,
+                    false,
+                ),
+                _ => return Err(invalid("bad VP8 frame header")),
+            }
+        } else if let Some(vp8l) = self.chunk_by_id("VP8L") {
+            // The signature byte, then width - 1 and height - 1 in 14 bits each and
+            // the alpha hint, least significant bit first
+            match vp8l.data.get(..5) {
+                Some([0x2f, b0, b1, b2, b3]) => {
+                    let bits = u32::from_le_bytes([*b0, *b1, *b2, *b3]);
+                    (
+                        (bits & 0x3fff) + 1,
+                        (bits >> 14 & 0x3fff) + 1,
+                        bits >> 28 & 1 == 1,
+                    )
+                }
+                _ => return Err(invalid("bad VP8L header")),
+            }
+        } else {
+            return Err(invalid("no VP8 or VP8L chunk"));
+        };
+        if width == 0 || height == 0 {
+            return Err(invalid("empty image"));
+        }
+
+        let mut data = vec![if alpha { 0x10 } else { 0 }, 0, 0, 0];
+        data.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        data.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        Ok(RiffChunk::new(*b"VP8X", data))
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let size = 4 + self.chunks.iter().map(|c| c.padded_length()).sum::<usize>();
+        let mut bytes = Vec::with_capacity(8 + size + self.trailer.len());
+        bytes.extend_from_slice(self.signature());
+        bytes.extend_from_slice(&(size as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.form_type);
+        for chunk in self.chunks.iter() {
+            chunk.write_to(&mut bytes);
+        }
+        bytes.extend_from_slice(&self.trailer);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Riff {
+    type Error = RiffError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, RiffError> {
+        match bytes.get(..12) {
+            Some(header) if header[..4] == Self::SIGNATURE => {}
+            _ => return Err(RiffError::InvalidHeader),
+        }
+        let size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        let end = 8 + size;
+        if size < 4 || end > bytes.len() {
+            return Err(RiffError::Truncated("RIFF".to_string()));
+        }
+        let form_type = [bytes[8], bytes[9], bytes[10], bytes[11]];
+
+        let mut chunks = Vec::new();
+        let mut offset = 12;
+        while offset < end {
+            if offset + RiffChunk::HEADER_LENGTH > end {
+                return Err(RiffError::Truncated("RIFF".to_string()));
+            }
+            let header = &bytes[offset..offset + RiffChunk::HEADER_LENGTH];
+            let id = [header[0], header[1], header[2], header[3]];
+            let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let start = offset + RiffChunk::HEADER_LENGTH;
+            if start + length > end {
+                return Err(RiffError::Truncated(
+                    String::from_utf8_lossy(&id).into_owned(),
+                ));
+            }
+            let chunk = RiffChunk::new(id, bytes[start..start + length].to_vec());
+            // The pad byte after the last chunk is sometimes left out of the RIFF size
+            offset = (offset + chunk.padded_length()).min(end);
+            chunks.push(chunk);
+        }
+        let mut riff = Riff::new(form_type, chunks);
+        riff.trailer = bytes[end..].to_vec();
+        Ok(riff)
+    }
+}
+
+impl Container for Riff {
+    fn format_name(&self) -> String {
+        format!("RIFF ({})", String::from_utf8_lossy(self.form_type()))
+    }
+
+    fn signature(&self) -> &[u8] {
+        &Self::SIGNATURE
+    }
+
+    fn chunk_data(&self, chunk_type: &str) -> Option<&[u8]> {
+        self.chunk_by_id(chunk_type).map(|c| c.data.as_slice())
+    }
+
+    fn add_chunk(&mut self, chunk_type: ChunkType, data: Vec<u8>) -> crate::Result<()> {
+        Ok(self.append_chunk(RiffChunk::new(chunk_type.bytes(), data))?)
+    }
+
+    fn remove_chunk(&mut self, chunk_type: &str) -> crate::Result<Vec<u8>> {
+        Ok(self.remove_first_chunk(chunk_type)?.data)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
+
+impl fmt::Display for Riff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {{", self.format_name())?;
+        for chunk in self.chunks() {
+            writeln!(f, "    {} ({} bytes)", chunk.id_string(), chunk.data.len())?;
+        }
+        if !self.trailer.is_empty() {
+            writeln!(f, "  Trailer: {} bytes", self.trailer.len())?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// A simple-format lossy WebP with a 300x200 frame header and made-up image data
+    fn lossy_webp() -> Riff {
+        let mut vp8 = vec![0x50, 0x02, 0x00, 0x9d, 0x01, 0x2a];
+        vp8.extend_from_slice(&300u16.to_le_bytes());
+        vp8.extend_from_slice(&200u16.to_le_bytes());
+        vp8.extend_from_slice(&[1, 2, 3]);
+        Riff::new(*b"WEBP", vec![RiffChunk::new(*b"VP8 ", vp8)])
+    }
+
+    #[test]
+    fn test_round_trip_with_padding() {
+        let webp = lossy_webp();
+        let bytes = webp.as_bytes();
+        // 13 bytes of VP8 data plus a pad byte
+        assert_eq!(bytes.len(), 12 + 8 + 14);
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 26);
+        assert_eq!(Riff::try_from(&bytes[..]).unwrap(), webp);
+        assert_eq!(
+            Riff::try_from(&bytes[..bytes.len() - 3]),
+            Err(RiffError::Truncated("RIFF".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_append_adds_extended_header() {
+        let mut webp = lossy_webp();
+        webp.add_chunk(ChunkType::from_str("ruSt").unwrap(), b"hi".to_vec())
+            .unwrap();
+        let ids: Vec<String> = webp.chunks().iter().map(|c| c.id_string()).collect();
+        assert_eq!(ids, ["VP8X", "VP8 ", "ruSt"]);
+        assert_eq!(
+            webp.chunk_data("VP8X").unwrap(),
+            &[0, 0, 0, 0, 43, 1, 0, 199, 0, 0]
+        );
+
+        let mut webp = Riff::try_from(&webp.as_bytes()[..]).unwrap();
+        assert_eq!(webp.remove_chunk("ruSt").unwrap(), b"hi");
+        assert!(webp.remove_chunk("ruSt").is_err());
+    }
+
+    #[test]
+    fn test_lossless_alpha_and_wave() {
+        // 16x8 with the alpha hint set
+        let bits: u32 = 15 | 7 << 14 | 1 << 28;
+        let mut vp8l = vec![0x2f];
+        vp8l.extend_from_slice(&bits.to_le_bytes());
+        let mut webp = Riff::new(*b"WEBP", vec![RiffChunk::new(*b"VP8L", vp8l)]);
+        webp.append_chunk(RiffChunk::new(*b"ruSt", Vec::new()))
+            .unwrap();
+        assert_eq!(webp.chunks()[0].data, [0x10, 0, 0, 0, 15, 0, 0, 7, 0, 0]);
+
+        let mut wave = Riff::new(*b"WAVE", vec![RiffChunk::new(*b"fmt ", vec![0; 16])]);
+        wave.append_chunk(RiffChunk::new(*b"ruSt", b"odd".to_vec()))
+            .unwrap();
+        assert_eq!(wave.chunks().len(), 2);
+        let parsed = Riff::try_from(&wave.as_bytes()[..]).unwrap();
+        assert_eq!(parsed.chunk_data("ruSt"), Some(&b"odd"[..]));
+    }
+}
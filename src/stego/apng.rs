@@ -0,0 +1,218 @@
+//! Hiding places spread across the frames of an animated PNG.
+//!
+//! * `frame-chunks` splits the payload into private ancillary chunks, one placed after
+//!   each frame. Players skip chunks they don't know, so the animation is untouched.
+//! * `frame-lsb` writes the payload into the low bit of the color samples of each frame
+//!   in turn, re-encoding only the frames it changes.
+//!
+//! The private chunks carry no sequence number, so `frame-chunks` leaves fcTL and fdAT
+//! alone; `frame-lsb` renumbers them after replacing the frame data.
+
+use crate::apng::{set_frame_data, Animation, FrameControl};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::ColorType;
+use crate::image::RawImage;
+use crate::png::Png;
+use crate::stego::{bits, frame, unframe};
+use crate::{Error, Result};
+use std::str::FromStr;
+
+/// Private, safe-to-copy chunk holding one piece of a `frame-chunks` payload
+pub const CHUNK_TYPE: &str = "fmSg";
+
+/// Hides `payload` in private chunks between the frames of the animation in `png`,
+/// replacing any pieces of an earlier payload.
+pub fn embed_chunks(png: &mut Png, payload: &[u8]) -> Result<()> {
+    let frames = Animation::from_png(png)?.frames.len();
+    let framed = frame(payload);
+    let mut pieces = framed.chunks(framed.len().div_ceil(frames));
+
+    let mut chunks = Vec::with_capacity(png.chunks().len() + frames);
+    let mut seen_frame = false;
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type().to_string();
+        if chunk_type == CHUNK_TYPE {
+            continue;
+        }
+        // Each piece follows the data of the frame it was assigned to
+        let frame_ended =
+            (chunk_type == FrameControl::CHUNK_TYPE && seen_frame) || chunk_type == "IEND";
+        if frame_ended {
+            if let Some(piece) = pieces.next() {
+                chunks.push(Chunk::new(
+                    ChunkType::from_str(CHUNK_TYPE).unwrap(),
+                    piece.to_vec(),
+                ));
+            }
+        }
+        seen_frame |= chunk_type == FrameControl::CHUNK_TYPE;
+        chunks.push(chunk.clone());
+    }
+    *png.chunks_mut() = chunks;
+    Ok(())
+}
+
+/// Reads a payload hidden by [`embed_chunks`].
+pub fn extract_chunks(png: &Png) -> Result<Vec<u8>> {
+    let data: Vec<u8> = png
+        .chunks()
+        .iter()
+        .filter(|c| c.chunk_type().to_string() == CHUNK_TYPE)
+        .flat_map(|c| c.data().iter().copied())
+        .collect();
+    unframe(bits(&data))
+}
+
+/// Hides `payload` in the least significant bit of the color samples of every frame.
+pub fn embed_lsb(png: &mut Png, payload: &[u8]) -> Result<()> {
+    let animation = Animation::from_png(png)?;
+    let mut images = decode_frames(&animation)?;
+    let framed = frame(payload);
+    let available: usize = images.iter().map(carrier_count).sum();
+    if available < framed.len() * 8 {
+        return Err(Error::from(format!(
+            "Message needs {} bytes but the frames can only carry {}",
+            payload.len(),
+            payload_bytes(available)
+        )));
+    }
+
+    let mut message = bits(&framed).peekable();
+    let mut data = Vec::with_capacity(images.len());
+    for (image, frame) in images.iter_mut().zip(animation.frames.iter()) {
+        if message.peek().is_none() {
+            data.push(frame.data.clone());
+            continue;
+        }
+        for (x, y, channel) in carriers(image) {
+            let Some(bit) = message.next() else {
+                break;
+            };
+            let sample = image.sample(x, y, channel);
+            image.set_sample(x, y, channel, sample & !1 | bit as u16);
+        }
+        data.push(image.encode(9));
+    }
+    set_frame_data(png, data)?;
+    Ok(())
+}
+
+/// Reads a payload hidden by [`embed_lsb`].
+pub fn extract_lsb(png: &Png) -> Result<Vec<u8>> {
+    let images = decode_frames(&Animation::from_png(png)?)?;
+    unframe(images.iter().flat_map(|image| {
+        carriers(image).map(move |(x, y, channel)| image.sample(x, y, channel) & 1 == 1)
+    }))
+}
+
+/// Payload bytes the frames of `png` can carry with `frame-lsb`
+pub fn lsb_capacity(png: &Png) -> Result<usize> {
+    let images = decode_frames(&Animation::from_png(png)?)?;
+    Ok(payload_bytes(images.iter().map(carrier_count).sum()))
+}
+
+/// Decodes every frame, checking the samples are wide enough to hide bits in
+fn decode_frames(animation: &Animation) -> Result<Vec<RawImage>> {
+    let ihdr = animation.ihdr();
+    if ihdr.color_type == ColorType::Indexed || ihdr.bit_depth < 8 {
+        return Err(Error::from(format!(
+            "Frame LSB embedding needs 8 or 16-bit samples, found {}-bit {}",
+            ihdr.bit_depth, ihdr.color_type
+        )));
+    }
+    animation
+        .frames
+        .iter()
+        .map(|frame| animation.decode_raw(frame))
+        .collect()
+}
+
+/// The color channels of an image, leaving alpha alone
+fn color_channels(image: &RawImage) -> usize {
+    let color_type = image.ihdr().color_type;
+    color_type.channels() - color_type.has_alpha() as usize
+}
+
+fn carrier_count(image: &RawImage) -> usize {
+    image.width() * image.height() * color_channels(image)
+}
+
+/// Every sample that carries a bit, in embedding order
+fn carriers(image: &RawImage) -> impl Iterator<Item = (usize, usize, usize)> {
+    let (width, channels) = (image.width(), color_channels(image));
+    (0..image.height()).flat_map(move |y| {
+        (0..width).flat_map(move |x| (0..channels).map(move |channel| (x, y, channel)))
+    })
+}
+
+fn payload_bytes(bits: usize) -> usize {
+    (bits / 8).saturating_sub(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng::{assemble, Delay};
+    use crate::image::Rgba16Image;
+
+    /// A three-frame 4x3 RGBA animation
+    fn animated_png() -> Png {
+        let frames: Vec<Png> = (0..3u16)
+            .map(|i| {
+                let mut image = Rgba16Image::blank(4, 3);
+                for pixel in image.pixels.iter_mut() {
+                    *pixel = [i * 0x4000, 0x8080, 0xffff, 0xffff];
+                }
+                image.to_png(8)
+            })
+            .collect();
+        assemble(&frames, Delay::from_str("100ms").unwrap(), 0).unwrap()
+    }
+
+    #[test]
+    fn test_chunks_round_trip() {
+        let mut png = animated_png();
+        embed_chunks(&mut png, b"between the frames").unwrap();
+        embed_chunks(&mut png, b"replaced").unwrap();
+        let png = Png::try_from(&png.as_bytes()[..]).unwrap();
+        let pieces = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == CHUNK_TYPE)
+            .count();
+        assert_eq!(pieces, 3);
+        assert_eq!(extract_chunks(&png).unwrap(), b"replaced");
+        assert_eq!(Animation::from_png(&png).unwrap().frames.len(), 3);
+    }
+
+    #[test]
+    fn test_lsb_round_trip() {
+        let mut png = animated_png();
+        let before = Animation::from_png(&png).unwrap().render().unwrap();
+        // 36 color samples per frame, so the message spills into the second frame
+        embed_lsb(&mut png, b"hi!").unwrap();
+        let png = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(extract_lsb(&png).unwrap(), b"hi!");
+
+        let animation = Animation::from_png(&png).unwrap();
+        let after = animation.render().unwrap();
+        assert_eq!(after.len(), 3);
+        for (a, b) in before.iter().zip(after.iter()) {
+            for (p, q) in a.pixels.iter().zip(b.pixels.iter()) {
+                assert!(p
+                    .iter()
+                    .zip(q.iter())
+                    .all(|(s, t)| s.abs_diff(*t) <= 0x0101));
+            }
+        }
+        assert_eq!(before[2].pixels, after[2].pixels);
+    }
+
+    #[test]
+    fn test_lsb_too_large() {
+        let mut png = animated_png();
+        assert_eq!(lsb_capacity(&png).unwrap(), 3 * 36 / 8 - 4);
+        assert!(embed_lsb(&mut png, &[0; 10]).is_err());
+    }
+}
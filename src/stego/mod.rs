@@ -0,0 +1,191 @@
+//! Ways of hiding a message inside the image itself rather than in a chunk of its own.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::png::Png;
+use crate::{Error, Result};
+
+pub mod apng;
+pub mod palette;
+pub mod zlib;
+
+/// The embedding methods understood by `pngme embed` and `pngme extract`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Chooses between near-identical palette entries of an indexed-color image
+    Palette,
+    /// Appends the payload after the end of the IDAT zlib stream
+    ZlibTrailer,
+    /// Stores the payload in a deflate block that decompresses past the last scanline
+    StoredBlock,
+    /// Encodes the payload in the lengths of the IDAT chunks
+    IdatSplit,
+    /// Appends the payload after the IEND chunk
+    AfterIend,
+    /// Splits the payload into private chunks between the frames of an animation
+    FrameChunks,
+    /// Spreads the payload over the low bits of the samples of every animation frame
+    FrameLsb,
+}
+
+impl Method {
+    /// Describes what the hidden data survives and what destroys it
+    pub fn fragility(&self) -> &'static str {
+        match self {
+            Method::Palette => {
+                "Survives chunk stripping and lossless recompression; destroyed by palette \
+                 reordering, color reduction or any lossy re-encoding"
+            }
+            Method::ZlibTrailer => {
+                "Survives chunk stripping; destroyed by re-encoding or recompressing the \
+                 image data and by tools that merge or rewrite IDAT chunks"
+            }
+            Method::StoredBlock => {
+                "Survives chunk stripping and IDAT re-chunking; destroyed by re-encoding or \
+                 recompressing the image data"
+            }
+            Method::IdatSplit => {
+                "Survives chunk stripping; destroyed by re-encoding and by any tool that \
+                 merges or re-splits IDAT chunks"
+            }
+            Method::AfterIend => {
+                "Survives byte-for-byte copies only; dropped by almost any tool that \
+                 rewrites the file and by `pngme trailer strip`"
+            }
+            Method::FrameChunks => {
+                "Survives playback and tools that copy unknown chunks; dropped by chunk \
+                 stripping and by editors that rebuild the animation"
+            }
+            Method::FrameLsb => {
+                "Survives chunk stripping and lossless recompression; destroyed by lossy \
+                 re-encoding, color reduction or flattening the animation to one image"
+            }
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "palette" => Ok(Method::Palette),
+            "zlib-trailer" => Ok(Method::ZlibTrailer),
+            "stored-block" => Ok(Method::StoredBlock),
+            "idat-split" => Ok(Method::IdatSplit),
+            "after-iend" => Ok(Method::AfterIend),
+            "frame-chunks" => Ok(Method::FrameChunks),
+            "frame-lsb" => Ok(Method::FrameLsb),
+            _ => Err(Error::from(format!("Unknown embedding method: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Method::Palette => f.write_str("palette"),
+            Method::ZlibTrailer => f.write_str("zlib-trailer"),
+            Method::StoredBlock => f.write_str("stored-block"),
+            Method::IdatSplit => f.write_str("idat-split"),
+            Method::AfterIend => f.write_str("after-iend"),
+            Method::FrameChunks => f.write_str("frame-chunks"),
+            Method::FrameLsb => f.write_str("frame-lsb"),
+        }
+    }
+}
+
+/// Hides `payload` in `png` with the given method.
+pub fn embed(png: &mut Png, method: Method, payload: &[u8]) -> Result<()> {
+    match method {
+        Method::Palette => palette::embed(png, payload),
+        Method::ZlibTrailer => zlib::embed_trailer(png, payload),
+        Method::StoredBlock => zlib::embed_stored_block(png, payload),
+        Method::IdatSplit => zlib::embed_idat_split(png, payload),
+        Method::AfterIend => {
+            png.set_trailer(frame(payload));
+            Ok(())
+        }
+        Method::FrameChunks => apng::embed_chunks(png, payload),
+        Method::FrameLsb => apng::embed_lsb(png, payload),
+    }
+}
+
+/// Reads a payload hidden in `png` with the given method.
+pub fn extract(png: &Png, method: Method) -> Result<Vec<u8>> {
+    match method {
+        Method::Palette => palette::extract(png),
+        Method::ZlibTrailer => zlib::extract_trailer(png),
+        Method::StoredBlock => zlib::extract_stored_block(png),
+        Method::IdatSplit => zlib::extract_idat_split(png),
+        Method::AfterIend => unframe(bits(png.trailer())),
+        Method::FrameChunks => apng::extract_chunks(png),
+        Method::FrameLsb => apng::extract_lsb(png),
+    }
+}
+
+/// Prefixes `payload` with its length as a big-endian u32 so extraction knows where it ends.
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(payload.len() + 4);
+    framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    framed.extend_from_slice(payload);
+    framed
+}
+
+/// Iterates over the bits of `bytes`, most significant bit first.
+pub fn bits(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| byte >> i & 1 == 1))
+}
+
+/// Reads a payload written by [`frame`] back out of a stream of bits.
+pub fn unframe(mut bits: impl Iterator<Item = bool>) -> Result<Vec<u8>> {
+    let mut next_byte = || -> Option<u8> {
+        (0..8).try_fold(0u8, |byte, _| bits.next().map(|bit| byte << 1 | bit as u8))
+    };
+    let mut length = [0u8; 4];
+    for byte in length.iter_mut() {
+        *byte = next_byte().ok_or("No hidden message found")?;
+    }
+    let length = u32::from_be_bytes(length);
+    (0..length)
+        .map(|_| next_byte().ok_or_else(|| Error::from("Hidden message is truncated")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_round_trip() {
+        let framed = frame(b"hidden");
+        assert_eq!(&framed[..4], &[0, 0, 0, 6]);
+        assert_eq!(unframe(bits(&framed)).unwrap(), b"hidden");
+    }
+
+    #[test]
+    fn test_unframe_truncated() {
+        let framed = frame(b"hidden");
+        assert!(unframe(bits(&framed[..framed.len() - 1])).is_err());
+        assert!(unframe(bits(&framed[..2])).is_err());
+    }
+
+    #[test]
+    fn test_method_from_str() {
+        assert_eq!(Method::from_str("palette").unwrap(), Method::Palette);
+        assert_eq!(Method::from_str("idat-split").unwrap(), Method::IdatSplit);
+        for method in [
+            Method::ZlibTrailer,
+            Method::StoredBlock,
+            Method::AfterIend,
+            Method::FrameChunks,
+            Method::FrameLsb,
+        ] {
+            assert_eq!(Method::from_str(&method.to_string()).unwrap(), method);
+        }
+        assert!(Method::from_str("lsb").is_err());
+    }
+}
@@ -0,0 +1,386 @@
+//! Palette steganography for indexed-color PNGs.
+//!
+//! Flipping the low bit of a palette index can jump to an unrelated color, so instead
+//! each bit is carried by a pixel whose color appears twice in the palette: the lower
+//! index of the pair means 0, the higher index means 1. Pairs are formed from entries
+//! that already look alike and, when that is not enough, from exact copies of busy
+//! colors added to unused palette slots (with their tRNS alpha copied along).
+
+use std::cmp::Reverse;
+use std::fmt;
+
+use crate::ancillary::Histogram;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::image::RawImage;
+use crate::palette::Palette;
+use crate::png::Png;
+use crate::stego::{bits, frame, unframe};
+use crate::{Error, Result};
+
+/// Palette entries whose channels all differ by at most this much are interchangeable
+pub const TOLERANCE: u8 = 2;
+
+/// A palette entry with its tRNS alpha merged in
+type Rgba = [u8; 4];
+
+/// How much can be hidden in an indexed image with the palette method
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaletteCapacity {
+    /// Entries in the PLTE chunk
+    pub entries: usize,
+    /// Entries the bit depth allows
+    pub max_entries: usize,
+    /// Pairs of interchangeable entries already in the palette
+    pub pairs: usize,
+    /// Payload bytes that fit using only the existing pairs
+    pub bytes: usize,
+    /// Payload bytes that fit once free slots are filled with copies of busy colors
+    pub bytes_with_duplicates: usize,
+}
+
+impl fmt::Display for PaletteCapacity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Palette entries: {} of {}",
+            self.entries, self.max_entries
+        )?;
+        writeln!(f, "Interchangeable pairs: {}", self.pairs)?;
+        writeln!(f, "Capacity with current palette: {} bytes", self.bytes)?;
+        write!(
+            f,
+            "Capacity after adding duplicates: {} bytes",
+            self.bytes_with_duplicates
+        )
+    }
+}
+
+/// Estimates how many payload bytes `png` can carry.
+pub fn capacity(png: &Png) -> Result<PaletteCapacity> {
+    let ihdr = indexed_ihdr(png)?;
+    let palette = Palette::from_png(png)?.entries;
+    let image = RawImage::from_png(png)?;
+    let usage = usage(&image, palette.len());
+    let max_entries = max_entries(&ihdr);
+
+    let partners = pairs(&palette);
+    let extended = with_duplicates(&palette, &usage, max_entries, None);
+    Ok(PaletteCapacity {
+        entries: palette.len(),
+        max_entries,
+        pairs: partners.iter().filter(|p| p.is_some()).count() / 2,
+        bytes: payload_bytes(carrier_bits(&partners, &usage)),
+        bytes_with_duplicates: payload_bytes(carrier_bits(&pairs(&extended), &usage)),
+    })
+}
+
+/// Hides `payload` in the pixel indices of `png`, extending PLTE and tRNS if needed.
+pub fn embed(png: &mut Png, payload: &[u8]) -> Result<()> {
+    let ihdr = indexed_ihdr(png)?;
+    let palette = Palette::from_png(png)?.entries;
+    let mut image = RawImage::from_png(png)?;
+    let usage = usage(&image, palette.len());
+
+    let framed = frame(payload);
+    let needed = framed.len() * 8;
+    let extended = with_duplicates(&palette, &usage, max_entries(&ihdr), Some(needed));
+    let partners = pairs(&extended);
+    let available = carrier_bits(&partners, &usage);
+    if available < needed {
+        return Err(Error::from(format!(
+            "Message needs {} bytes but the palette can only carry {}",
+            payload.len(),
+            payload_bytes(available)
+        )));
+    }
+
+    let mut message = bits(&framed);
+    'pixels: for y in 0..image.height() {
+        for x in 0..image.width() {
+            let index = image.sample(x, y, 0) as usize;
+            if let Some(partner) = partners.get(index).copied().flatten() {
+                let Some(bit) = message.next() else {
+                    break 'pixels;
+                };
+                let (zero, one) = (index.min(partner), index.max(partner));
+                image.set_sample(x, y, 0, if bit { one } else { zero } as u16);
+            }
+        }
+    }
+
+    if png.chunk_by_type(Histogram::CHUNK_TYPE).is_some() {
+        // Pixels moved between the entries of each pair, so the old counts are stale
+        png.set_chunk(histogram(&self::usage(&image, extended.len())).to_chunk());
+    }
+    if extended.len() != palette.len() {
+        Palette::new(extended).write_to(png);
+    }
+    png.set_image_data(image.encode(9));
+    Ok(())
+}
+
+/// Reads a payload hidden by [`embed`].
+pub fn extract(png: &Png) -> Result<Vec<u8>> {
+    indexed_ihdr(png)?;
+    let partners = pairs(&Palette::from_png(png)?.entries);
+    let image = RawImage::from_png(png)?;
+
+    let carriers = (0..image.height())
+        .flat_map(|y| (0..image.width()).map(move |x| (x, y)))
+        .filter_map(|(x, y)| {
+            let index = image.sample(x, y, 0) as usize;
+            partners
+                .get(index)
+                .copied()
+                .flatten()
+                .map(|partner| index > partner)
+        });
+    unframe(carriers)
+}
+
+fn indexed_ihdr(png: &Png) -> Result<Ihdr> {
+    let ihdr = png.ihdr()?;
+    if ihdr.color_type != ColorType::Indexed {
+        return Err(Error::from(format!(
+            "Palette embedding needs an indexed-color image, found {}",
+            ihdr.color_type
+        )));
+    }
+    Ok(ihdr)
+}
+
+fn max_entries(ihdr: &Ihdr) -> usize {
+    1 << ihdr.bit_depth
+}
+
+fn payload_bytes(bits: usize) -> usize {
+    (bits / 8).saturating_sub(4)
+}
+
+/// How many pixels use each palette entry
+fn usage(image: &RawImage, entries: usize) -> Vec<usize> {
+    let mut usage = vec![0; entries];
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            if let Some(count) = usage.get_mut(image.sample(x, y, 0) as usize) {
+                *count += 1;
+            }
+        }
+    }
+    usage
+}
+
+/// A hIST for the index counts in `usage`, scaled so the busiest entry gets 65535 and
+/// no used entry rounds down to 0
+fn histogram(usage: &[usize]) -> Histogram {
+    let busiest = usage.iter().copied().max().unwrap_or(0).max(1) as u64;
+    Histogram {
+        frequencies: usage
+            .iter()
+            .map(|&count| (count as u64 * 65535).div_ceil(busiest) as u16)
+            .collect(),
+    }
+}
+
+fn interchangeable(a: &Rgba, b: &Rgba) -> bool {
+    a.iter()
+        .zip(b.iter())
+        .all(|(x, y)| x.abs_diff(*y) <= TOLERANCE)
+}
+
+fn distance(a: &Rgba, b: &Rgba) -> u32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| (x.abs_diff(*y) as u32).pow(2))
+        .sum()
+}
+
+/// Pairs up interchangeable palette entries. Entries are visited in order and each is
+/// matched with the closest later entry that is still free, so the pairing depends only
+/// on the palette and extraction finds the same pairs embedding used.
+fn pairs(palette: &[Rgba]) -> Vec<Option<usize>> {
+    let mut partners = vec![None; palette.len()];
+    for i in 0..palette.len() {
+        if partners[i].is_some() {
+            continue;
+        }
+        let closest = (i + 1..palette.len())
+            .filter(|&j| partners[j].is_none() && interchangeable(&palette[i], &palette[j]))
+            .min_by_key(|&j| (distance(&palette[i], &palette[j]), j));
+        if let Some(j) = closest {
+            partners[i] = Some(j);
+            partners[j] = Some(i);
+        }
+    }
+    partners
+}
+
+/// Number of bits the pixels counted in `usage` can carry with the given pairing
+fn carrier_bits(partners: &[Option<usize>], usage: &[usize]) -> usize {
+    usage
+        .iter()
+        .zip(partners.iter())
+        .filter(|(_, partner)| partner.is_some())
+        .map(|(count, _)| count)
+        .sum()
+}
+
+/// Copies the most used unpaired colors into free palette slots until `needed` bits fit,
+/// or until the palette is full when `needed` is `None`.
+fn with_duplicates(
+    palette: &[Rgba],
+    usage: &[usize],
+    max_entries: usize,
+    needed: Option<usize>,
+) -> Vec<Rgba> {
+    let mut palette = palette.to_vec();
+    let mut usage = usage.to_vec();
+    let mut copied = vec![false; palette.len()];
+    while palette.len() < max_entries {
+        let partners = pairs(&palette);
+        if needed.is_some_and(|needed| carrier_bits(&partners, &usage) >= needed) {
+            break;
+        }
+        let busiest = (0..copied.len())
+            .filter(|&i| partners[i].is_none() && usage[i] > 0 && !copied[i])
+            .max_by_key(|&i| (usage[i], Reverse(i)));
+        let Some(i) = busiest else {
+            break;
+        };
+        copied[i] = true;
+        palette.push(palette[i]);
+        usage.push(0);
+    }
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn indexed_png(palette: &[Rgba], bit_depth: u8, interlaced: bool) -> Png {
+        let ihdr = Ihdr {
+            width: 24,
+            height: 20,
+            bit_depth,
+            color_type: ColorType::Indexed,
+            interlaced,
+        };
+        let mut image = RawImage::blank(ihdr.clone());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                image.set_sample(x, y, 0, ((x / 3 + y) % palette.len()) as u16);
+            }
+        }
+
+        let mut png = Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        Palette::new(palette.to_vec()).write_to(&mut png);
+        png.set_image_data(image.encode(6));
+        png
+    }
+
+    #[test]
+    fn test_pairs_prefers_closest_entry() {
+        let palette = [
+            [10, 10, 10, 255],
+            [200, 0, 0, 255],
+            [11, 10, 10, 255],
+            [10, 10, 10, 255],
+        ];
+        let partners = pairs(&palette);
+        assert_eq!(partners, vec![Some(3), None, None, Some(0)]);
+    }
+
+    #[test]
+    fn test_embed_and_extract_with_existing_pairs() {
+        let palette = [
+            [0, 0, 0, 255],
+            [1, 1, 0, 255],
+            [250, 250, 250, 255],
+            [255, 255, 255, 255],
+        ];
+        let mut png = indexed_png(&palette, 2, false);
+        embed(&mut png, b"hi").unwrap();
+        assert_eq!(png.chunk_by_type("PLTE").unwrap().length(), 12);
+        assert_eq!(extract(&png).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn test_embed_adds_duplicates_and_trns() {
+        let palette = [[255, 0, 0, 128], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let mut png = indexed_png(&palette, 4, true);
+        embed(&mut png, b"secret").unwrap();
+
+        let extended = Palette::from_png(&png).unwrap().entries;
+        assert!(extended.len() > palette.len());
+        assert_eq!(&extended[..3], &palette[..]);
+        for entry in &extended[3..] {
+            assert!(palette.contains(entry));
+        }
+        assert_eq!(
+            extract(&Png::try_from(&png.as_bytes()[..]).unwrap()).unwrap(),
+            b"secret"
+        );
+    }
+
+    #[test]
+    fn test_embed_rebuilds_histogram() {
+        let palette = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let mut png = indexed_png(&palette, 4, false);
+        let hist = Histogram {
+            frequencies: vec![1, 1, 1],
+        };
+        png.set_chunk(hist.to_chunk());
+        embed(&mut png, b"hi").unwrap();
+
+        let entries = Palette::from_png(&png).unwrap().len();
+        let hist = Histogram::try_from(png.chunk_by_type("hIST").unwrap()).unwrap();
+        assert_eq!(hist.frequencies.len(), entries);
+        let used = usage(&RawImage::from_png(&png).unwrap(), entries);
+        for (count, frequency) in used.iter().zip(hist.frequencies.iter()) {
+            assert_eq!(*count == 0, *frequency == 0);
+        }
+        assert_eq!(hist.frequencies.iter().max(), Some(&65535));
+    }
+
+    #[test]
+    fn test_embed_too_large() {
+        let palette = [[0, 0, 0, 255], [255, 255, 255, 255]];
+        let mut png = indexed_png(&palette, 1, false);
+        assert!(embed(&mut png, &[0; 100]).is_err());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let palette = [
+            [0, 0, 0, 255],
+            [1, 1, 0, 255],
+            [250, 250, 250, 255],
+            [255, 255, 255, 255],
+        ];
+        let png = indexed_png(&palette, 4, false);
+        let capacity = capacity(&png).unwrap();
+        assert_eq!(capacity.pairs, 1);
+        assert_eq!(capacity.bytes, 24 * 20 / 2 / 8 - 4);
+        assert_eq!(capacity.bytes_with_duplicates, 24 * 20 / 8 - 4);
+    }
+
+    #[test]
+    fn test_rejects_truecolor() {
+        let png = Png::from_chunks(vec![Ihdr {
+            width: 1,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        }
+        .to_chunk()]);
+        assert!(extract(&png).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! Hiding places inside the IDAT zlib stream.
+//!
+//! Unlike a chunk of its own, data stored here survives tools that strip ancillary
+//! chunks, since every IDAT chunk has to be kept. It does not survive anything that
+//! decodes and re-encodes the image data.
+//!
+//! * `zlib-trailer` appends the payload after the Adler-32 checksum that ends the stream.
+//! * `stored-block` recompresses the image and adds a final stored deflate block, so the
+//!   payload decompresses to bytes after the last scanline.
+//! * `idat-split` splits the stream into IDAT chunks whose lengths spell out the payload.
+
+use flate2::{Compress, Compression, FlushCompress, Status};
+
+use crate::image::{filtered_len, inflate_image_data};
+use crate::png::Png;
+use crate::stego::{bits, frame, unframe};
+use crate::{Error, Result};
+
+/// Largest amount of data a single stored deflate block can hold
+const STORED_BLOCK_MAX: usize = 65535;
+
+/// Appends `payload` after the end of the zlib stream in the last IDAT chunk.
+pub fn embed_trailer(png: &mut Png, payload: &[u8]) -> Result<()> {
+    let mut stream = png.image_data();
+    stream.truncate(stream_len(png, &stream)?);
+    stream.extend(frame(payload));
+    png.set_image_data(stream);
+    Ok(())
+}
+
+/// Reads a payload hidden by [`embed_trailer`].
+pub fn extract_trailer(png: &Png) -> Result<Vec<u8>> {
+    let stream = png.image_data();
+    unframe(bits(&stream[stream_len(png, &stream)?..]))
+}
+
+/// Length of the zlib stream at the start of `stream`, the image data of `png`
+fn stream_len(png: &Png, stream: &[u8]) -> Result<usize> {
+    let (_, used) = inflate_image_data(stream, &png.ihdr()?)?;
+    Ok(used)
+}
+
+/// Recompresses the image data with `payload` in stored blocks after the last scanline.
+pub fn embed_stored_block(png: &mut Png, payload: &[u8]) -> Result<()> {
+    let ihdr = png.ihdr()?;
+    let (mut filtered, _) = inflate_image_data(&png.image_data(), &ihdr)?;
+    let expected = filtered_len(&ihdr)?;
+    if filtered.len() < expected {
+        return Err(Error::from("Image data is truncated"));
+    }
+    filtered.truncate(expected);
+
+    let framed = frame(payload);
+    let mut stream = vec![0x78, 0xda];
+    stream.extend(deflate_unfinished(&filtered)?);
+    let mut blocks = framed.chunks(STORED_BLOCK_MAX).peekable();
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        stream.push(last as u8);
+        stream.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        stream.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    filtered.extend_from_slice(&framed);
+    stream.extend_from_slice(&adler32(&filtered).to_be_bytes());
+    png.set_image_data(stream);
+    Ok(())
+}
+
+/// Reads a payload hidden by [`embed_stored_block`].
+pub fn extract_stored_block(png: &Png) -> Result<Vec<u8>> {
+    let ihdr = png.ihdr()?;
+    let (filtered, _) = inflate_image_data(&png.image_data(), &ihdr)?;
+    let expected = filtered_len(&ihdr)?;
+    if filtered.len() <= expected {
+        return Err(Error::from("No hidden message found"));
+    }
+    unframe(bits(&filtered[expected..]))
+}
+
+/// Splits the zlib stream into IDAT chunks so that every chunk but the last is one byte
+/// longer than the payload byte it stands for.
+pub fn embed_idat_split(png: &mut Png, payload: &[u8]) -> Result<()> {
+    let stream = png.image_data();
+    let framed = frame(payload);
+    let needed: usize = framed.iter().map(|&byte| byte as usize + 1).sum();
+    if needed >= stream.len() {
+        return Err(Error::from(format!(
+            "Message needs {} bytes of image data to split but there are only {}",
+            needed + 1,
+            stream.len()
+        )));
+    }
+
+    let mut parts = Vec::with_capacity(framed.len() + 1);
+    let mut offset = 0;
+    for &byte in framed.iter() {
+        let length = byte as usize + 1;
+        parts.push(stream[offset..offset + length].to_vec());
+        offset += length;
+    }
+    parts.push(stream[offset..].to_vec());
+    png.set_image_chunks(parts);
+    Ok(())
+}
+
+/// Reads a payload hidden by [`embed_idat_split`].
+pub fn extract_idat_split(png: &Png) -> Result<Vec<u8>> {
+    let lengths: Vec<usize> = png
+        .chunks()
+        .iter()
+        .filter(|c| c.chunk_type().to_string() == "IDAT")
+        .map(|c| c.length() as usize)
+        .collect();
+    if lengths.len() < 2
+        || lengths[..lengths.len() - 1]
+            .iter()
+            .any(|&l| l == 0 || l > 256)
+    {
+        return Err(Error::from("No hidden message found"));
+    }
+    let bytes: Vec<u8> = lengths[..lengths.len() - 1]
+        .iter()
+        .map(|&l| (l - 1) as u8)
+        .collect();
+    unframe(bits(&bytes))
+}
+
+/// Payload bytes [`embed_idat_split`] can always fit, and the number that fit for
+/// typical (evenly distributed) message bytes.
+pub fn idat_split_capacity(png: &Png) -> (usize, usize) {
+    let available = png.image_data().len().saturating_sub(1);
+    (
+        (available / 256).saturating_sub(4),
+        ((available * 2) / 257).saturating_sub(4),
+    )
+}
+
+/// Compresses `data` as raw deflate blocks, none of them final, ending on a byte
+/// boundary so more blocks can follow.
+fn deflate_unfinished(data: &[u8]) -> Result<Vec<u8>> {
+    let mut compress = Compress::new(Compression::best(), false);
+    let mut output = Vec::with_capacity(data.len() / 2 + 64);
+    loop {
+        if output.len() == output.capacity() {
+            output.reserve(output.capacity());
+        }
+        let read = compress.total_in() as usize;
+        let status = compress.compress_vec(&data[read..], &mut output, FlushCompress::Sync)?;
+        let done = compress.total_in() as usize == data.len();
+        if done && output.len() < output.capacity() {
+            return Ok(output);
+        }
+        if status == Status::StreamEnd {
+            return Err(Error::from("Deflate stream ended early"));
+        }
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MODULUS;
+        b %= MODULUS;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::ihdr::{ColorType, Ihdr};
+    use crate::image::RawImage;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let ihdr = Ihdr {
+            width: 40,
+            height: 30,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+        let mut image = RawImage::blank(ihdr.clone());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                for channel in 0..3 {
+                    image.set_sample(
+                        x,
+                        y,
+                        channel,
+                        ((x * x * 7 + y * y * 13 + x * y * 3 + channel * 101) % 251) as u16,
+                    );
+                }
+            }
+        }
+        let mut png = Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        png.set_image_data(image.encode(6));
+        png
+    }
+
+    fn reparse(png: &Png) -> Png {
+        Png::try_from(png.as_bytes().as_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn test_trailer_round_trip() {
+        let mut png = testing_png();
+        let image = RawImage::from_png(&png).unwrap();
+        embed_trailer(&mut png, b"after adler").unwrap();
+        let png = reparse(&png);
+        assert_eq!(extract_trailer(&png).unwrap(), b"after adler");
+        assert_eq!(RawImage::from_png(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn test_stored_block_round_trip() {
+        let mut png = testing_png();
+        let image = RawImage::from_png(&png).unwrap();
+        embed_stored_block(&mut png, b"stored").unwrap();
+        let png = reparse(&png);
+        assert_eq!(extract_stored_block(&png).unwrap(), b"stored");
+        assert_eq!(RawImage::from_png(&png).unwrap(), image);
+        let stream = png.image_data();
+        assert_eq!(stream_len(&png, &stream).unwrap(), stream.len());
+    }
+
+    #[test]
+    fn test_idat_split_round_trip() {
+        let mut png = testing_png();
+        let image = RawImage::from_png(&png).unwrap();
+        embed_idat_split(&mut png, b"split").unwrap();
+        let png = reparse(&png);
+        assert_eq!(
+            png.chunks()
+                .iter()
+                .filter(|c| c.chunk_type().to_string() == "IDAT")
+                .count(),
+            10
+        );
+        assert_eq!(extract_idat_split(&png).unwrap(), b"split");
+        assert_eq!(RawImage::from_png(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn test_idat_split_too_large() {
+        let mut png = testing_png();
+        assert!(embed_idat_split(&mut png, &[255; 1000]).is_err());
+    }
+
+    #[test]
+    fn test_extract_without_message() {
+        let png = testing_png();
+        assert!(extract_trailer(&png).is_err());
+        assert!(extract_stored_block(&png).is_err());
+        assert!(extract_idat_split(&png).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! Removing metadata that could leak information before a file is published.
+//!
+//! Critical chunks are never removed, since the image cannot be decoded without them.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// Ancillary chunks that change how the image's colors are displayed
+pub const COLOR_MANAGEMENT: [&str; 8] = [
+    "gAMA", "cHRM", "sRGB", "iCCP", "cICP", "mDCV", "cLLI", "sBIT",
+];
+
+/// Ancillary chunks that change which pixels are shown: transparency and animation.
+/// Every preset keeps these unless they are named with `--drop`.
+pub const IMAGE_CONTENT: [&str; 4] = ["tRNS", "acTL", "fcTL", "fdAT"];
+
+/// Which ancillary chunks `pngme strip` keeps before `--keep` and `--drop` are applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Removes every ancillary chunk except those in [`IMAGE_CONTENT`]
+    All,
+    /// Also keeps the color-management chunks
+    ColorManagement,
+    /// Keeps only [`IMAGE_CONTENT`] and the ancillary chunks listed with `--keep`
+    Listed,
+}
+
+impl FromStr for Preset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "all" => Ok(Preset::All),
+            "color" => Ok(Preset::ColorManagement),
+            "listed" => Ok(Preset::Listed),
+            _ => Err(Error::from(format!("Unknown strip preset: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Preset::All => f.write_str("all"),
+            Preset::ColorManagement => f.write_str("color"),
+            Preset::Listed => f.write_str("listed"),
+        }
+    }
+}
+
+/// What was taken out of a file by [`strip`]
+#[derive(Debug, Default)]
+pub struct StripReport {
+    pub removed: Vec<Chunk>,
+    /// Length of the data that followed IEND
+    pub trailer: usize,
+}
+
+impl fmt::Display for StripReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.removed.is_empty() && self.trailer == 0 {
+            return writeln!(f, "Nothing to remove");
+        }
+        for chunk in self.removed.iter() {
+            writeln!(
+                f,
+                "Removed {} ({} bytes)",
+                chunk.chunk_type(),
+                chunk.length()
+            )?;
+        }
+        if self.trailer > 0 {
+            writeln!(f, "Removed {} bytes after IEND", self.trailer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes the ancillary chunks `preset` does not keep, except those in `keep`, along
+/// with any chunk in `drop` and the data after IEND.
+pub fn strip(
+    png: &mut Png,
+    preset: Preset,
+    keep: &[ChunkType],
+    drop: &[ChunkType],
+) -> Result<StripReport> {
+    if let Some(critical) = drop.iter().find(|t| t.is_critical()) {
+        return Err(Error::from(format!(
+            "{} is a critical chunk and cannot be removed",
+            critical
+        )));
+    }
+    if preset == Preset::Listed && keep.is_empty() {
+        return Err(Error::from(
+            "The listed preset needs at least one --keep type",
+        ));
+    }
+
+    let kept_by_preset = |chunk_type: &ChunkType| {
+        let chunk_type = chunk_type.to_string();
+        IMAGE_CONTENT.contains(&chunk_type.as_str())
+            || (preset == Preset::ColorManagement
+                && COLOR_MANAGEMENT.contains(&chunk_type.as_str()))
+    };
+    let should_remove = |chunk_type: &ChunkType| {
+        if drop.contains(chunk_type) {
+            return true;
+        }
+        !chunk_type.is_critical() && !kept_by_preset(chunk_type) && !keep.contains(chunk_type)
+    };
+
+    let mut report = StripReport::default();
+    let chunks = std::mem::take(png.chunks_mut());
+    for chunk in chunks {
+        if should_remove(chunk.chunk_type()) {
+            report.removed.push(chunk);
+        } else {
+            png.chunks_mut().push(chunk);
+        }
+    }
+    report.trailer = png.take_trailer().len();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::{ColorType, Ihdr};
+    use crate::image::RawImage;
+
+    fn chunk(chunk_type: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![1, 2, 3])
+    }
+
+    fn testing_png() -> Png {
+        let mut png = Png::from_chunks(
+            [
+                "IHDR", "gAMA", "iCCP", "tRNS", "tEXt", "eXIf", "tIME", "prVt", "IDAT", "zTXt",
+                "IEND",
+            ]
+            .iter()
+            .map(|t| chunk(t))
+            .collect(),
+        );
+        png.set_trailer(b"appended".to_vec());
+        png
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_strip_all() {
+        let mut png = testing_png();
+        let report = strip(&mut png, Preset::All, &[], &[]).unwrap();
+        assert_eq!(types(&png), ["IHDR", "tRNS", "IDAT", "IEND"]);
+        assert_eq!(report.removed.len(), 7);
+        assert_eq!(report.trailer, 8);
+        assert!(png.trailer().is_empty());
+    }
+
+    #[test]
+    fn test_strip_color_management() {
+        let mut png = testing_png();
+        let drop = [
+            ChunkType::from_str("iCCP").unwrap(),
+            ChunkType::from_str("tRNS").unwrap(),
+        ];
+        strip(&mut png, Preset::ColorManagement, &[], &drop).unwrap();
+        assert_eq!(types(&png), ["IHDR", "gAMA", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_strip_listed() {
+        let mut png = testing_png();
+        assert!(strip(&mut png, Preset::Listed, &[], &[]).is_err());
+        let keep = [ChunkType::from_str("tIME").unwrap()];
+        strip(&mut png, Preset::Listed, &keep, &[]).unwrap();
+        assert_eq!(types(&png), ["IHDR", "tRNS", "tIME", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_stripped_image_looks_the_same() {
+        let ihdr = Ihdr {
+            width: 2,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+        let mut image = RawImage::blank(ihdr.clone());
+        for channel in 0..3 {
+            image.set_sample(1, 0, channel, 200);
+        }
+        // The second pixel is made transparent by the tRNS color key
+        let trns = Chunk::new(
+            ChunkType::from_str("tRNS").unwrap(),
+            vec![0, 200, 0, 200, 0, 200],
+        );
+        let mut png = Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            trns,
+            chunk("tEXt"),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        png.set_image_data(image.encode(6));
+        let original = png.pixel_digest().unwrap();
+
+        for preset in [Preset::All, Preset::ColorManagement] {
+            let mut stripped = Png::try_from(png.as_bytes().as_slice()).unwrap();
+            strip(&mut stripped, preset, &[], &[]).unwrap();
+            assert_eq!(stripped.pixel_digest().unwrap(), original);
+        }
+    }
+
+    #[test]
+    fn test_strip_refuses_critical() {
+        let mut png = testing_png();
+        let drop = [ChunkType::from_str("IDAT").unwrap()];
+        assert!(strip(&mut png, Preset::All, &[], &drop).is_err());
+        assert_eq!(png.chunks().len(), 11);
+    }
+}
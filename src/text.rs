@@ -0,0 +1,195 @@
+//! The iTXt chunk, which holds UTF-8 text under a Latin-1 keyword.
+//! https://www.w3.org/TR/png-3/#11iTXt
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::image::{deflate, inflate};
+
+/// Largest text a compressed iTXt chunk is decompressed to; anything larger is treated
+/// as a deflate bomb. XMP packets, the biggest iTXt users, stay far below this.
+const MAX_TEXT_LENGTH: usize = 32 << 20;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum TextError {
+    #[error("Expected an iTXt chunk, found {0}")]
+    WrongChunkType(String),
+    #[error("Keywords must be 1 to 79 printable Latin-1 characters")]
+    InvalidKeyword,
+    #[error("iTXt chunk is missing a field separator")]
+    MissingSeparator,
+    #[error("Unknown iTXt compression flag or method")]
+    UnknownCompression,
+    #[error("Compressed text is corrupt")]
+    CorruptCompression,
+    #[error("iTXt text is not valid UTF-8")]
+    InvalidUtf8,
+}
+
+/// The contents of an iTXt chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternationalText {
+    pub keyword: String,
+    /// Whether the text is stored zlib-compressed
+    pub compressed: bool,
+    /// RFC 3066 language tag, empty if unknown
+    pub language: String,
+    /// The keyword translated into `language`
+    pub translated_keyword: String,
+    pub text: String,
+}
+
+impl InternationalText {
+    pub const CHUNK_TYPE: &'static str = "iTXt";
+
+    /// Uncompressed text with no language information
+    pub fn new(keyword: &str, text: &str) -> Result<Self, TextError> {
+        if !is_valid_keyword(keyword) {
+            return Err(TextError::InvalidKeyword);
+        }
+        Ok(InternationalText {
+            keyword: keyword.to_string(),
+            compressed: false,
+            language: String::new(),
+            translated_keyword: String::new(),
+            text: text.to_string(),
+        })
+    }
+
+    /// Builds the iTXt chunk for this text
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(self.keyword.len() + self.text.len() + 8);
+        // Keywords are checked to be Latin-1 when they are created or parsed
+        data.extend(self.keyword.chars().map(|c| c as u8));
+        data.push(0);
+        data.push(self.compressed as u8);
+        data.push(0);
+        data.extend_from_slice(self.language.as_bytes());
+        data.push(0);
+        data.extend_from_slice(self.translated_keyword.as_bytes());
+        data.push(0);
+        match self.compressed {
+            true => data.extend(deflate(self.text.as_bytes(), 9)),
+            false => data.extend_from_slice(self.text.as_bytes()),
+        }
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+/// Whether `keyword` can name a text chunk: 1-79 Latin-1 characters without leading,
+/// trailing or consecutive spaces.
+pub fn is_valid_keyword(keyword: &str) -> bool {
+    let length = keyword.chars().count();
+    (1..=79).contains(&length)
+        && keyword
+            .chars()
+            .all(|c| (' '..='~').contains(&c) || ('\u{a1}'..='\u{ff}').contains(&c))
+        && !keyword.starts_with(' ')
+        && !keyword.ends_with(' ')
+        && !keyword.contains("  ")
+}
+
+impl TryFrom<&Chunk> for InternationalText {
+    type Error = TextError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, TextError> {
+        if chunk.chunk_type().to_string() != Self::CHUNK_TYPE {
+            return Err(TextError::WrongChunkType(chunk.chunk_type().to_string()));
+        }
+        let data = chunk.data();
+        let mut fields = data.splitn(2, |&b| b == 0);
+        let keyword: String = fields.next().unwrap().iter().map(|&b| b as char).collect();
+        let rest = fields.next().ok_or(TextError::MissingSeparator)?;
+        if !is_valid_keyword(&keyword) {
+            return Err(TextError::InvalidKeyword);
+        }
+        let (compressed, rest) = match rest {
+            [0, _, rest @ ..] => (false, rest),
+            [1, 0, rest @ ..] => (true, rest),
+            _ => return Err(TextError::UnknownCompression),
+        };
+        let mut fields = rest.splitn(3, |&b| b == 0);
+        let language = fields.next().unwrap();
+        let translated_keyword = fields.next().ok_or(TextError::MissingSeparator)?;
+        let text = fields.next().ok_or(TextError::MissingSeparator)?;
+        let text = match compressed {
+            true => inflate(text, MAX_TEXT_LENGTH).map_err(|_| TextError::CorruptCompression)?,
+            false => text.to_vec(),
+        };
+        let utf8 = |bytes: Vec<u8>| String::from_utf8(bytes).map_err(|_| TextError::InvalidUtf8);
+        Ok(InternationalText {
+            keyword,
+            compressed,
+            language: utf8(language.to_vec())?,
+            translated_keyword: utf8(translated_keyword.to_vec())?,
+            text: utf8(text)?,
+        })
+    }
+}
+
+impl fmt::Display for InternationalText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.keyword)?;
+        if !self.language.is_empty() {
+            write!(f, " [{}]", self.language)?;
+        }
+        write!(f, ": {}", self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut text = InternationalText::new("Title", "Ünïcödé").unwrap();
+        text.language = "de".to_string();
+        text.translated_keyword = "Titel".to_string();
+        assert_eq!(InternationalText::try_from(&text.to_chunk()).unwrap(), text);
+
+        text.compressed = true;
+        let chunk = text.to_chunk();
+        assert_eq!(InternationalText::try_from(&chunk).unwrap(), text);
+
+        text.text = " ".repeat(MAX_TEXT_LENGTH + 1);
+        assert_eq!(
+            InternationalText::try_from(&text.to_chunk()),
+            Err(TextError::CorruptCompression)
+        );
+    }
+
+    #[test]
+    fn test_parse_raw_chunk() {
+        let chunk = Chunk::new(
+            ChunkType::from_str("iTXt").unwrap(),
+            b"Comment\0\0\0en\0\0hello".to_vec(),
+        );
+        let text = InternationalText::try_from(&chunk).unwrap();
+        assert_eq!(text.keyword, "Comment");
+        assert_eq!(text.language, "en");
+        assert_eq!(text.text, "hello");
+    }
+
+    #[test]
+    fn test_invalid_chunks() {
+        let chunk = |data: &[u8]| Chunk::new(ChunkType::from_str("iTXt").unwrap(), data.to_vec());
+        assert_eq!(
+            InternationalText::try_from(&chunk(b"Comment")),
+            Err(TextError::MissingSeparator)
+        );
+        assert_eq!(
+            InternationalText::try_from(&chunk(b"Comment\0\x02\0\0\0")),
+            Err(TextError::UnknownCompression)
+        );
+        assert_eq!(
+            InternationalText::try_from(&chunk(b" Comment\0\0\0\0\0")),
+            Err(TextError::InvalidKeyword)
+        );
+        assert!(InternationalText::new("", "text").is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! The tIME chunk, which records when the image was last modified.
+//! https://www.w3.org/TR/png-3/#11tIME
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum TimeError {
+    #[error("Expected a tIME chunk, found {0}")]
+    WrongChunkType(String),
+    #[error("tIME chunks are 7 bytes long, found {0}")]
+    InvalidLength(usize),
+    #[error("Invalid date or time {0}")]
+    InvalidTime(String),
+}
+
+/// The contents of a tIME chunk, always in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    /// Up to 60 to allow for leap seconds
+    pub second: u8,
+}
+
+impl Time {
+    pub const CHUNK_TYPE: &'static str = "tIME";
+
+    /// Checks each field is in range, including the number of days in the month
+    pub fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Result<Self, TimeError> {
+        let time = Time {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        };
+        if !(1..=12).contains(&month)
+            || day < 1
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(TimeError::InvalidTime(time.to_string()));
+        }
+        Ok(time)
+    }
+
+    /// The time `seconds` after the Unix epoch
+    pub fn from_unix(seconds: u64) -> Self {
+        let days = (seconds / 86400) as i64;
+        let rest = seconds % 86400;
+        // Howard Hinnant's days-to-civil algorithm, with eras of 400 years
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+        Time {
+            year: year.min(u16::MAX as i64) as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (rest / 3600) as u8,
+            minute: (rest / 60 % 60) as u8,
+            second: (rest % 60) as u8,
+        }
+    }
+
+    /// The current time according to the system clock
+    pub fn now() -> Self {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Time::from_unix(seconds)
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.year.to_be_bytes().to_vec();
+        data.extend_from_slice(&[self.month, self.day, self.hour, self.minute, self.second]);
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    let leap = (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400);
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl TryFrom<&Chunk> for Time {
+    type Error = TimeError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, TimeError> {
+        if chunk.chunk_type().to_string() != Self::CHUNK_TYPE {
+            return Err(TimeError::WrongChunkType(chunk.chunk_type().to_string()));
+        }
+        match chunk.data() {
+            &[y0, y1, month, day, hour, minute, second] => Time::new(
+                u16::from_be_bytes([y0, y1]),
+                month,
+                day,
+                hour,
+                minute,
+                second,
+            ),
+            data => Err(TimeError::InvalidLength(data.len())),
+        }
+    }
+}
+
+impl FromStr for Time {
+    type Err = TimeError;
+
+    /// Parses an ISO-8601 UTC time such as `2024-02-29T13:45:00Z`, or just a date
+    fn from_str(s: &str) -> Result<Self, TimeError> {
+        let invalid = || TimeError::InvalidTime(s.to_string());
+        let s = s.trim().trim_end_matches('Z');
+        let (date, time) = match s.split_once(['T', ' ']) {
+            Some((date, time)) => (date, time),
+            None => (s, "00:00:00"),
+        };
+        let fields = |text: &str, separator: char| -> Result<Vec<u16>, TimeError> {
+            text.split(separator)
+                .map(|field| field.parse::<u16>().map_err(|_| invalid()))
+                .collect()
+        };
+        let date = fields(date, '-')?;
+        let time = fields(time, ':')?;
+        match (date.as_slice(), time.as_slice()) {
+            (&[year, month, day], &[hour, minute, second]) => {
+                let byte = |value: u16| u8::try_from(value).map_err(|_| invalid());
+                Time::new(
+                    year,
+                    byte(month)?,
+                    byte(day)?,
+                    byte(hour)?,
+                    byte(minute)?,
+                    byte(second)?,
+                )
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Time {
+    /// ISO-8601 in UTC
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let time = Time::from_str("2024-02-29T13:45:07Z").unwrap();
+        assert_eq!(time.to_chunk().data(), &[0x07, 0xe8, 2, 29, 13, 45, 7]);
+        assert_eq!(Time::try_from(&time.to_chunk()).unwrap(), time);
+        assert_eq!(time.to_string(), "2024-02-29T13:45:07Z");
+        assert_eq!(
+            Time::from_str("1999-12-31").unwrap().to_string(),
+            "1999-12-31T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn test_invalid_times() {
+        assert!(Time::from_str("2023-02-29T00:00:00Z").is_err());
+        assert!(Time::from_str("2024-13-01T00:00:00Z").is_err());
+        assert!(Time::from_str("2024-01-01T24:00:00Z").is_err());
+        assert!(Time::from_str("yesterday").is_err());
+        let chunk = Chunk::new(ChunkType::from_str("tIME").unwrap(), vec![7, 0xe8, 1, 1]);
+        assert_eq!(Time::try_from(&chunk), Err(TimeError::InvalidLength(4)));
+    }
+
+    #[test]
+    fn test_from_unix() {
+        assert_eq!(Time::from_unix(0).to_string(), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            Time::from_unix(1_709_214_307).to_string(),
+            "2024-02-29T13:45:07Z"
+        );
+        assert_eq!(
+            Time::from_unix(4_107_542_399).to_string(),
+            "2100-02-28T23:59:59Z"
+        );
+    }
+}
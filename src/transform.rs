@@ -0,0 +1,389 @@
+//! Cropping, rotating and flipping.
+//!
+//! The transforms move decoded samples around without resampling, so the color type,
+//! bit depth, palette and interlacing stay as they are. They run in a fixed order: crop,
+//! then rotate, then flip. Chunks tied to the pixel grid follow along where they can:
+//! pHYs and sCAL swap axes when the image turns on its side, and a crop moves an oFFs
+//! given in pixels. Chunks that no longer hold are dropped: hIST and sPLT count pixels,
+//! which a crop changes, and sTER describes a side-by-side stereo pair.
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::ancillary::{
+    Histogram, Offset, OffsetUnit, PhysicalDimensions, Scale, SuggestedPalette,
+};
+use crate::apng::AnimationControl;
+use crate::cgbi;
+use crate::ihdr::Ihdr;
+use crate::image::RawImage;
+use crate::png::{Format, Png};
+use crate::{Error, Result};
+
+/// Marks a stereo pair with the left and right views side by side
+const STEREO_CHUNK_TYPE: &str = "sTER";
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum TransformError {
+    #[error("Invalid crop {0}, expected x,y,width,height")]
+    InvalidCrop(String),
+    #[error("Crop {crop} doesn't fit in a {width}x{height} image")]
+    CropOutOfBounds {
+        crop: String,
+        width: u32,
+        height: u32,
+    },
+    #[error("Invalid rotation {0}, expected 90, 180 or 270")]
+    InvalidRotation(String),
+    #[error("Invalid flip {0}, expected h or v")]
+    InvalidFlip(String),
+}
+
+/// A rectangle to keep, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Crop {
+    type Err = TransformError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, TransformError> {
+        let values: Vec<u32> = s
+            .split(',')
+            .map(|v| v.trim().parse())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| TransformError::InvalidCrop(s.to_string()))?;
+        match values[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Ok(Crop {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(TransformError::InvalidCrop(s.to_string())),
+        }
+    }
+}
+
+/// A clockwise rotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl FromStr for Rotation {
+    type Err = TransformError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, TransformError> {
+        match s {
+            "90" => Ok(Rotation::Quarter),
+            "180" => Ok(Rotation::Half),
+            "270" => Ok(Rotation::ThreeQuarters),
+            _ => Err(TransformError::InvalidRotation(s.to_string())),
+        }
+    }
+}
+
+/// A mirror image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flip {
+    /// Left becomes right
+    Horizontal,
+    /// Top becomes bottom
+    Vertical,
+}
+
+impl FromStr for Flip {
+    type Err = TransformError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, TransformError> {
+        match s {
+            "h" | "horizontal" => Ok(Flip::Horizontal),
+            "v" | "vertical" => Ok(Flip::Vertical),
+            _ => Err(TransformError::InvalidFlip(s.to_string())),
+        }
+    }
+}
+
+/// The transforms to apply, in the order of the fields
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Transform {
+    pub crop: Option<Crop>,
+    pub rotate: Option<Rotation>,
+    pub flip: Option<Flip>,
+}
+
+/// Applies `transform` to the image of `png`, returning the new file along with notes
+/// on the chunks that were dropped.
+pub fn transform(png: &Png, transform: Transform) -> Result<(Png, Vec<String>)> {
+    if png.format() != Format::Png {
+        return Err(Error::from(format!(
+            "Only PNG files can be transformed, found {}",
+            png.format()
+        )));
+    }
+    if cgbi::is_cgbi(png) {
+        return Err(Error::from(
+            "Convert CgBI images with `pngme normalize` first",
+        ));
+    }
+    if png.chunk_by_type(AnimationControl::CHUNK_TYPE).is_some() {
+        return Err(Error::from(
+            "Animated PNGs can't be transformed, their frames would keep the old size",
+        ));
+    }
+    let mut image = RawImage::from_png(png)?;
+
+    if let Some(crop) = transform.crop {
+        let (width, height) = (image.width() as u32, image.height() as u32);
+        if crop
+            .x
+            .checked_add(crop.width)
+            .is_none_or(|right| right > width)
+            || crop
+                .y
+                .checked_add(crop.height)
+                .is_none_or(|bottom| bottom > height)
+        {
+            return Err(TransformError::CropOutOfBounds {
+                crop: format!("{},{},{},{}", crop.x, crop.y, crop.width, crop.height),
+                width,
+                height,
+            }
+            .into());
+        }
+        let (x, y) = (crop.x as usize, crop.y as usize);
+        image = remap(&image, crop.width, crop.height, |i, j| (x + i, y + j));
+    }
+    let (width, height) = (image.width(), image.height());
+    image = match transform.rotate {
+        None => image,
+        Some(Rotation::Quarter) => remap(&image, height as u32, width as u32, |i, j| {
+            (j, height - 1 - i)
+        }),
+        Some(Rotation::Half) => remap(&image, width as u32, height as u32, |i, j| {
+            (width - 1 - i, height - 1 - j)
+        }),
+        Some(Rotation::ThreeQuarters) => remap(&image, height as u32, width as u32, |i, j| {
+            (width - 1 - j, i)
+        }),
+    };
+    let (width, height) = (image.width(), image.height());
+    image = match transform.flip {
+        None => image,
+        Some(Flip::Horizontal) => remap(&image, width as u32, height as u32, |i, j| {
+            (width - 1 - i, j)
+        }),
+        Some(Flip::Vertical) => remap(&image, width as u32, height as u32, |i, j| {
+            (i, height - 1 - j)
+        }),
+    };
+
+    let sideways = matches!(
+        transform.rotate,
+        Some(Rotation::Quarter | Rotation::ThreeQuarters)
+    );
+    let mut removed = Vec::new();
+    let mut chunks = Vec::new();
+    for chunk in png.chunks().iter() {
+        let chunk_type = chunk.chunk_type().to_string();
+        let chunk = match chunk_type.as_str() {
+            Ihdr::CHUNK_TYPE => image.ihdr().to_chunk(),
+            Histogram::CHUNK_TYPE | SuggestedPalette::CHUNK_TYPE if transform.crop.is_some() => {
+                removed.push(format!("{} (pixel counts changed by the crop)", chunk_type));
+                continue;
+            }
+            STEREO_CHUNK_TYPE => {
+                removed.push(format!("{} (no longer a stereo pair)", chunk_type));
+                continue;
+            }
+            PhysicalDimensions::CHUNK_TYPE if sideways => {
+                match PhysicalDimensions::try_from(chunk) {
+                    Ok(dimensions) => PhysicalDimensions {
+                        x: dimensions.y,
+                        y: dimensions.x,
+                        ..dimensions
+                    }
+                    .to_chunk(),
+                    Err(_) => chunk.clone(),
+                }
+            }
+            Scale::CHUNK_TYPE if sideways => match Scale::try_from(chunk) {
+                Ok(scale) => Scale {
+                    width: scale.height,
+                    height: scale.width,
+                    ..scale
+                }
+                .to_chunk(),
+                Err(_) => chunk.clone(),
+            },
+            Offset::CHUNK_TYPE => match (Offset::try_from(chunk), transform.crop) {
+                (_, None) => chunk.clone(),
+                (Ok(offset), Some(crop)) if offset.unit == OffsetUnit::Pixel => Offset {
+                    x: offset.x.saturating_add(crop.x as i32),
+                    y: offset.y.saturating_add(crop.y as i32),
+                    ..offset
+                }
+                .to_chunk(),
+                _ => {
+                    removed.push(format!("{} (position not in pixels)", chunk_type));
+                    continue;
+                }
+            },
+            _ => chunk.clone(),
+        };
+        chunks.push(chunk);
+    }
+
+    let mut transformed = png.clone();
+    *transformed.chunks_mut() = chunks;
+    transformed.set_image_data(image.encode(9));
+    Ok((transformed, removed))
+}
+
+/// A `width` x `height` image in the format of `image`, whose pixel at (x, y) is taken
+/// from `image` at `source(x, y)`
+fn remap(
+    image: &RawImage,
+    width: u32,
+    height: u32,
+    source: impl Fn(usize, usize) -> (usize, usize),
+) -> RawImage {
+    let mut remapped = RawImage::blank(Ihdr {
+        width,
+        height,
+        ..image.ihdr().clone()
+    });
+    for y in 0..remapped.height() {
+        for x in 0..remapped.width() {
+            let (from_x, from_y) = source(x, y);
+            for channel in 0..image.ihdr().color_type.channels() {
+                remapped.set_sample(x, y, channel, image.sample(from_x, from_y, channel));
+            }
+        }
+    }
+    remapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::ihdr::ColorType;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    /// A 3x2 image whose pixels are 0 to 5 in reading order, as palette indices
+    fn indexed_png(interlaced: bool) -> Png {
+        let ihdr = Ihdr {
+            width: 3,
+            height: 2,
+            bit_depth: 4,
+            color_type: ColorType::Indexed,
+            interlaced,
+        };
+        let mut image = RawImage::blank(ihdr.clone());
+        for i in 0..6 {
+            image.set_sample(i % 3, i / 3, 0, i as u16);
+        }
+        let mut png = Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            chunk("PLTE", (0..18).collect()),
+            chunk("hIST", vec![0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1]),
+            chunk("pHYs", vec![0, 0, 0, 1, 0, 0, 0, 2, 0]),
+            chunk("oFFs", vec![0, 0, 0, 10, 0, 0, 0, 20, 0]),
+            chunk("IEND", Vec::new()),
+        ]);
+        png.set_image_data(image.encode(9));
+        png.append_chunk(chunk("ruSt", b"message".to_vec()));
+        png
+    }
+
+    fn pixels(png: &Png) -> Vec<Vec<u16>> {
+        let image = RawImage::from_png(png).unwrap();
+        (0..image.height())
+            .map(|y| (0..image.width()).map(|x| image.sample(x, y, 0)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_crop_and_rotate() {
+        let png = indexed_png(false);
+        let transform = Transform {
+            crop: Some(Crop::from_str("1,0,2,2").unwrap()),
+            rotate: Some(Rotation::Quarter),
+            flip: None,
+        };
+        let (transformed, removed) = super::transform(&png, transform).unwrap();
+        // Cropped to [[1, 2], [4, 5]], then turned clockwise
+        assert_eq!(pixels(&transformed), [[4, 1], [5, 2]]);
+        assert_eq!(removed, ["hIST (pixel counts changed by the crop)"]);
+        assert_eq!(
+            transformed.chunk_by_type("pHYs").unwrap().data(),
+            [0, 0, 0, 2, 0, 0, 0, 1, 0]
+        );
+        assert_eq!(
+            transformed.chunk_by_type("oFFs").unwrap().data(),
+            [0, 0, 0, 11, 0, 0, 0, 20, 0]
+        );
+        assert_eq!(
+            transformed.chunk_by_type("PLTE").unwrap().data(),
+            png.chunk_by_type("PLTE").unwrap().data()
+        );
+        assert_eq!(
+            transformed.chunk_by_type("ruSt").unwrap().data(),
+            b"message"
+        );
+
+        let too_wide = Transform {
+            crop: Some(Crop::from_str("2,0,2,2").unwrap()),
+            ..Transform::default()
+        };
+        assert!(super::transform(&png, too_wide).is_err());
+    }
+
+    #[test]
+    fn test_rotations_and_flips() {
+        let png = indexed_png(true);
+        let run = |rotate, flip| {
+            let transform = Transform {
+                crop: None,
+                rotate,
+                flip,
+            };
+            let (transformed, removed) = super::transform(&png, transform).unwrap();
+            assert!(removed.is_empty());
+            assert!(transformed.ihdr().unwrap().interlaced);
+            pixels(&transformed)
+        };
+        assert_eq!(run(None, Some(Flip::Horizontal)), [[2, 1, 0], [5, 4, 3]]);
+        assert_eq!(run(None, Some(Flip::Vertical)), [[3, 4, 5], [0, 1, 2]]);
+        assert_eq!(run(Some(Rotation::Half), None), [[5, 4, 3], [2, 1, 0]]);
+        assert_eq!(
+            run(Some(Rotation::ThreeQuarters), None),
+            [[2, 5], [1, 4], [0, 3]]
+        );
+        assert_eq!(
+            run(Some(Rotation::Quarter), Some(Flip::Vertical)),
+            run(Some(Rotation::ThreeQuarters), Some(Flip::Horizontal))
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Crop::from_str("1,2,3").is_err());
+        assert!(Crop::from_str("1,2,0,4").is_err());
+        assert!(Rotation::from_str("45").is_err());
+        assert_eq!(Flip::from_str("v"), Ok(Flip::Vertical));
+    }
+}
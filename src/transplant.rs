@@ -0,0 +1,265 @@
+//! Copying ancillary chunks from one PNG into another.
+//!
+//! The PNG spec marks every chunk type as safe or unsafe to copy. Unsafe chunks describe
+//! the critical data (the image layout, palette or pixels), so they are only copied when
+//! the destination shows exactly the same image as the source.
+
+use std::fmt;
+
+use crate::chunk::Chunk;
+use crate::image::RawImage;
+use crate::png::{Placement, Png};
+use crate::Result;
+
+/// Chunk types that may appear more than once in a file
+const REPEATABLE: [&str; 5] = ["tEXt", "zTXt", "iTXt", "sPLT", "gIFg"];
+
+/// APNG chunks, which only make sense alongside the frames of the file they came from
+const ANIMATION: [&str; 3] = ["acTL", "fcTL", "fdAT"];
+
+/// Chunk types that must not appear together: both say which color space the image uses
+const CONFLICTING: [(&str, &str); 1] = [("sRGB", "iCCP")];
+
+/// What happened to each ancillary chunk of the source file
+#[derive(Debug, Default)]
+pub struct TransplantReport {
+    pub copied: Vec<String>,
+    /// Chunks of types the destination may only have one of, whose old copy was replaced
+    pub replaced: Vec<String>,
+    /// Destination chunks removed to make room for a conflicting chunk from the source
+    pub removed: Vec<String>,
+    pub skipped: Vec<(String, &'static str)>,
+}
+
+impl fmt::Display for TransplantReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk_type in self.copied.iter() {
+            writeln!(f, "Copied {}", chunk_type)?;
+        }
+        for chunk_type in self.replaced.iter() {
+            writeln!(f, "Replaced {}", chunk_type)?;
+        }
+        for chunk_type in self.removed.iter() {
+            writeln!(f, "Removed {}", chunk_type)?;
+        }
+        for (chunk_type, reason) in self.skipped.iter() {
+            writeln!(f, "Skipped {}: {}", chunk_type, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies the ancillary chunks of `source` into `destination`. Unsafe-to-copy chunks are
+/// only copied if both files have the same critical data, unless `force` is set. A chunk
+/// that conflicts with one in the destination is skipped, or replaces it if `force` is set.
+pub fn transplant(source: &Png, destination: &mut Png, force: bool) -> Result<TransplantReport> {
+    let same_image = critical_data_matches(source, destination);
+    let mut report = TransplantReport::default();
+
+    for (index, chunk) in source.chunks().iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        let name = chunk_type.to_string();
+        if chunk_type.is_critical() {
+            continue;
+        }
+        if ANIMATION.contains(&name.as_str()) {
+            report
+                .skipped
+                .push((name, "animation chunks belong to the source's frames"));
+            continue;
+        }
+        if !chunk_type.is_safe_to_copy() && !same_image && !force {
+            report.skipped.push((
+                name,
+                "unsafe to copy and the image differs (use --force to copy anyway)",
+            ));
+            continue;
+        }
+        let conflicting = CONFLICTING
+            .iter()
+            .find_map(|&(a, b)| match name.as_str() {
+                n if n == a => Some(b),
+                n if n == b => Some(a),
+                _ => None,
+            })
+            .and_then(|other| {
+                destination
+                    .chunks()
+                    .iter()
+                    .position(|c| c.chunk_type().to_string() == other)
+            });
+        if let Some(i) = conflicting {
+            if !force {
+                report.skipped.push((
+                    name,
+                    "conflicts with the destination's color space (use --force to replace it)",
+                ));
+                continue;
+            }
+            let removed = destination.chunks_mut().remove(i);
+            report.removed.push(removed.chunk_type().to_string());
+        }
+
+        let existing = destination
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type() == chunk_type);
+        let copy = Chunk::new(chunk_type.clone(), chunk.data().to_vec());
+        let repeatable = REPEATABLE.contains(&name.as_str()) || !chunk_type.is_standard();
+        match existing {
+            Some(i) if !repeatable => {
+                if destination.chunks()[i].data() == chunk.data() {
+                    report.skipped.push((name, "already present"));
+                } else {
+                    destination.chunks_mut()[i] = copy;
+                    report.replaced.push(name);
+                }
+            }
+            _ if destination
+                .chunks()
+                .iter()
+                .any(|c| c.chunk_type() == chunk_type && c.data() == chunk.data()) =>
+            {
+                report.skipped.push((name, "already present"));
+            }
+            _ => {
+                let placement =
+                    Placement::required(chunk_type).unwrap_or_else(|| source.placement_of(index));
+                destination.insert_chunk(copy, placement);
+                report.copied.push(name);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Whether both files have the same header and palette and decode to the same samples,
+/// so chunks that describe the critical data still apply.
+fn critical_data_matches(source: &Png, destination: &Png) -> bool {
+    let chunk_data =
+        |png: &Png, chunk_type: &str| png.chunk_by_type(chunk_type).map(|c| c.data().to_vec());
+    if chunk_data(source, "IHDR") != chunk_data(destination, "IHDR")
+        || chunk_data(source, "PLTE") != chunk_data(destination, "PLTE")
+    {
+        return false;
+    }
+    // Compare raw samples rather than pixel digests, which would also take tRNS into account
+    match (RawImage::from_png(source), RawImage::from_png(destination)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::ihdr::{ColorType, Ihdr};
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn indexed_png(level: u32, shade: u8) -> Png {
+        let ihdr = Ihdr {
+            width: 8,
+            height: 8,
+            bit_depth: 8,
+            color_type: ColorType::Indexed,
+            interlaced: false,
+        };
+        let mut png = Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            chunk("PLTE", &[0, 0, 0, shade, shade, shade]),
+            chunk("IEND", &[]),
+        ]);
+        let mut image = RawImage::blank(ihdr);
+        image.set_sample(2, 2, 0, 1);
+        png.set_image_data(image.encode(level));
+        png
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    fn source() -> Png {
+        let mut png = indexed_png(9, 255);
+        let chunks = png.chunks_mut();
+        chunks.insert(1, chunk("gAMA", &[0, 0, 177, 143]));
+        chunks.insert(3, chunk("tRNS", &[0]));
+        chunks.insert(4, chunk("tEXt", b"Author\0me"));
+        chunks.insert(6, chunk("prVt", b"after"));
+        png
+    }
+
+    #[test]
+    fn test_transplant_after_recompression() {
+        let mut destination = indexed_png(1, 255);
+        let report = transplant(&source(), &mut destination, false).unwrap();
+        assert_eq!(
+            types(&destination),
+            ["IHDR", "gAMA", "PLTE", "tRNS", "tEXt", "IDAT", "prVt", "IEND"]
+        );
+        assert_eq!(report.copied.len(), 4);
+
+        // Copying again changes nothing
+        let report = transplant(&source(), &mut destination, false).unwrap();
+        assert!(report.copied.is_empty() && report.replaced.is_empty());
+        assert_eq!(destination.chunks().len(), 8);
+    }
+
+    #[test]
+    fn test_transplant_unsafe_chunks() {
+        let mut destination = indexed_png(9, 128);
+        let report = transplant(&source(), &mut destination, false).unwrap();
+        assert_eq!(
+            types(&destination),
+            ["IHDR", "PLTE", "tEXt", "IDAT", "prVt", "IEND"]
+        );
+        assert_eq!(report.skipped.len(), 2);
+
+        let mut destination = indexed_png(9, 128);
+        transplant(&source(), &mut destination, true).unwrap();
+        assert_eq!(destination.chunks().len(), 8);
+    }
+
+    #[test]
+    fn test_transplant_replaces_single_chunks() {
+        let mut destination = indexed_png(1, 255);
+        destination.set_chunk(chunk("gAMA", &[0, 1, 134, 160]));
+        let report = transplant(&source(), &mut destination, false).unwrap();
+        assert_eq!(report.replaced, ["gAMA"]);
+        assert_eq!(
+            destination.chunk_by_type("gAMA").unwrap().data(),
+            &[0, 0, 177, 143]
+        );
+    }
+
+    #[test]
+    fn test_transplant_conflicting_color_space() {
+        let mut source = source();
+        source.set_chunk(chunk("sRGB", &[0]));
+        let with_profile = || {
+            let mut png = indexed_png(1, 255);
+            png.set_chunk(chunk("iCCP", b"profile\0\0x"));
+            png
+        };
+
+        let mut destination = with_profile();
+        let report = transplant(&source, &mut destination, false).unwrap();
+        assert!(destination.chunk_by_type("sRGB").is_none());
+        assert!(destination.chunk_by_type("iCCP").is_some());
+        assert!(report.skipped.iter().any(|(t, _)| t == "sRGB"));
+
+        let mut destination = with_profile();
+        let report = transplant(&source, &mut destination, true).unwrap();
+        assert!(destination.chunk_by_type("sRGB").is_some());
+        assert!(destination.chunk_by_type("iCCP").is_none());
+        assert_eq!(report.removed, ["iCCP"]);
+    }
+}
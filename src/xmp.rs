@@ -0,0 +1,673 @@
+//! XMP metadata, stored as an XML packet in an iTXt chunk with the keyword
+//! `XML:com.adobe.xmp`.
+//! https://github.com/adobe/XMP-Toolkit-SDK/blob/main/docs/XMPSpecificationPart3.pdf
+//!
+//! Only the subset of XML that XMP packets use is understood: elements, attributes,
+//! text, CDATA, comments and processing instructions. There is no DTD support.
+
+use std::fmt;
+use thiserror::Error;
+
+use crate::png::Png;
+use crate::text::InternationalText;
+use crate::{Error, Result};
+
+/// Keyword of the iTXt chunk that holds the XMP packet
+pub const KEYWORD: &str = "XML:com.adobe.xmp";
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// Namespaces that can be declared automatically when a property is added
+const NAMESPACES: [(&str, &str); 9] = [
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+    ("xmp", "http://ns.adobe.com/xap/1.0/"),
+    ("xmpRights", "http://ns.adobe.com/xap/1.0/rights/"),
+    ("xmpMM", "http://ns.adobe.com/xap/1.0/mm/"),
+    ("photoshop", "http://ns.adobe.com/photoshop/1.0/"),
+    ("tiff", "http://ns.adobe.com/tiff/1.0/"),
+    ("exif", "http://ns.adobe.com/exif/1.0/"),
+    (
+        "Iptc4xmpCore",
+        "http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/",
+    ),
+    ("lr", "http://ns.adobe.com/lightroom/1.0/"),
+];
+
+/// How deeply elements may nest before the packet is rejected
+const MAX_DEPTH: usize = 256;
+
+/// Properties whose values are language alternatives, ordered arrays or unordered arrays
+const ALT_PROPERTIES: [&str; 3] = ["dc:title", "dc:description", "dc:rights"];
+const SEQ_PROPERTIES: [&str; 2] = ["dc:creator", "dc:date"];
+const BAG_PROPERTIES: [&str; 1] = ["dc:subject"];
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum XmlError {
+    #[error("Malformed XML at byte {position}: {reason}")]
+    Malformed {
+        position: usize,
+        reason: &'static str,
+    },
+    #[error("Closing tag </{found}> does not match <{expected}>")]
+    MismatchedTag { expected: String, found: String },
+}
+
+/// A piece of XML content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+    Comment(String),
+    ProcessingInstruction(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    fn new(name: &str) -> Self {
+        Element {
+            name: name.to_string(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn set_attribute(&mut self, name: &str, value: &str) {
+        match self.attributes.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.attributes.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    fn elements_mut(&mut self) -> impl Iterator<Item = &mut Element> {
+        self.children.iter_mut().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    /// The concatenated text content of this element and its descendants
+    pub fn text(&self) -> String {
+        self.children
+            .iter()
+            .map(|node| match node {
+                Node::Text(text) => text.clone(),
+                Node::Element(element) => element.text(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    fn with_text(mut self, text: &str) -> Self {
+        self.children.push(Node::Text(text.to_string()));
+        self
+    }
+
+    /// Writes this element with one space of indentation per level, as XMP tools do
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = " ".repeat(depth);
+        write!(f, "{}<{}", indent, self.name)?;
+        for (i, (name, value)) in self.attributes.iter().enumerate() {
+            match i {
+                0 => write!(f, " ")?,
+                _ => write!(f, "\n{}   ", indent)?,
+            }
+            write!(f, "{}=\"{}\"", name, escape(value, true))?;
+        }
+        match &self.children[..] {
+            [] => return writeln!(f, "/>"),
+            [Node::Text(text)] => return writeln!(f, ">{}</{}>", escape(text, false), self.name),
+            _ => writeln!(f, ">")?,
+        }
+        for child in self.children.iter() {
+            match child {
+                Node::Element(element) => element.write(f, depth + 1)?,
+                Node::Text(text) => writeln!(f, "{} {}", indent, escape(text, false))?,
+                Node::Comment(comment) => writeln!(f, "{} <!--{}-->", indent, comment)?,
+                Node::ProcessingInstruction(pi) => writeln!(f, "{} <?{}?>", indent, pi)?,
+            }
+        }
+        writeln!(f, "{}</{}>", indent, self.name)
+    }
+}
+
+fn escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A cursor over XML text
+struct Parser<'a> {
+    xml: &'a str,
+    position: usize,
+    /// Number of elements currently open
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.xml[self.position..]
+    }
+
+    fn error(&self, reason: &'static str) -> XmlError {
+        XmlError::Malformed {
+            position: self.position,
+            reason,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the text up to `end` and moves past `end`
+    fn until(&mut self, end: &str, reason: &'static str) -> std::result::Result<&'a str, XmlError> {
+        let length = self.rest().find(end).ok_or_else(|| self.error(reason))?;
+        let text = &self.rest()[..length];
+        self.position += length + end.len();
+        Ok(text)
+    }
+
+    fn name(&mut self) -> std::result::Result<&'a str, XmlError> {
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '=' | '?'))
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.position += length;
+        Ok(&rest[..length])
+    }
+
+    /// Parses markup other than elements, returning `None` if there is none here
+    fn misc(&mut self) -> std::result::Result<Option<Node>, XmlError> {
+        if self.eat("<!--") {
+            let comment = self.until("-->", "unterminated comment")?;
+            Ok(Some(Node::Comment(comment.to_string())))
+        } else if self.eat("<![CDATA[") {
+            let text = self.until("]]>", "unterminated CDATA section")?;
+            Ok(Some(Node::Text(text.to_string())))
+        } else if self.eat("<?") {
+            let pi = self.until("?>", "unterminated processing instruction")?;
+            Ok(Some(Node::ProcessingInstruction(pi.to_string())))
+        } else if self.rest().starts_with("<!") {
+            Err(self.error("document type declarations are not supported"))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn element(&mut self) -> std::result::Result<Element, XmlError> {
+        if !self.eat("<") {
+            return Err(self.error("expected an element"));
+        }
+        let mut element = Element::new(self.name()?);
+        loop {
+            self.skip_whitespace();
+            if self.eat("/>") {
+                return Ok(element);
+            }
+            if self.eat(">") {
+                break;
+            }
+            let name = self.name()?;
+            self.skip_whitespace();
+            if !self.eat("=") {
+                return Err(self.error("expected '=' after attribute name"));
+            }
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(q @ ('"' | '\'')) => q,
+                _ => return Err(self.error("expected a quoted attribute value")),
+            };
+            self.position += 1;
+            let value = self.until(&quote.to_string(), "unterminated attribute value")?;
+            element.attributes.push((name.to_string(), unescape(value)));
+        }
+
+        loop {
+            if self.eat("</") {
+                let name = self.name()?;
+                self.skip_whitespace();
+                if !self.eat(">") {
+                    return Err(self.error("expected '>'"));
+                }
+                if name != element.name {
+                    return Err(XmlError::MismatchedTag {
+                        expected: element.name,
+                        found: name.to_string(),
+                    });
+                }
+                return Ok(element);
+            }
+            if let Some(node) = self.misc()? {
+                element.children.push(node);
+            } else if self.rest().starts_with('<') {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error("elements are nested too deeply"));
+                }
+                self.depth += 1;
+                element.children.push(Node::Element(self.element()?));
+                self.depth -= 1;
+            } else {
+                let text = self.rest().split('<').next().unwrap();
+                if self.rest().len() == text.len() {
+                    return Err(self.error("unterminated element"));
+                }
+                self.position += text.len();
+                // Whitespace between elements is only formatting
+                if !text.trim().is_empty() {
+                    element.children.push(Node::Text(unescape(text)));
+                }
+            }
+        }
+    }
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse()))
+                .and_then(|n| n.ok())
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// A parsed XMP packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xmp {
+    /// The `x:xmpmeta` element, or `rdf:RDF` for packets without the wrapper
+    root: Element,
+}
+
+impl Xmp {
+    /// An empty packet with a single `rdf:Description` to hold properties
+    pub fn new() -> Self {
+        let mut description = Element::new("rdf:Description");
+        description.set_attribute("rdf:about", "");
+        let mut rdf = Element::new("rdf:RDF");
+        rdf.set_attribute("xmlns:rdf", RDF);
+        rdf.children.push(Node::Element(description));
+        let mut root = Element::new("x:xmpmeta");
+        root.set_attribute("xmlns:x", "adobe:ns:meta/");
+        root.children.push(Node::Element(rdf));
+        Xmp { root }
+    }
+
+    /// Parses a packet, skipping the `<?xpacket?>` wrapper and anything else around the
+    /// root element.
+    pub fn parse(xml: &str) -> Result<Self> {
+        let mut parser = Parser {
+            xml: xml.trim_start_matches('\u{feff}'),
+            position: 0,
+            depth: 1,
+        };
+        loop {
+            parser.skip_whitespace();
+            if parser.misc()?.is_none() {
+                break;
+            }
+        }
+        let root = parser.element()?;
+        if root.name != "x:xmpmeta" && root.name != "rdf:RDF" {
+            return Err(Error::from(format!(
+                "XMP packet starts with <{}> instead of <x:xmpmeta>",
+                root.name
+            )));
+        }
+        Ok(Xmp { root })
+    }
+
+    /// Reads the XMP packet of `png`, if it has one
+    pub fn from_png(png: &Png) -> Result<Option<Self>> {
+        png.international_text(KEYWORD)
+            .map(|text| Xmp::parse(&text.text))
+            .transpose()
+    }
+
+    /// Builds the iTXt chunk contents for this packet, uncompressed as the XMP spec asks
+    pub fn to_text(&self) -> InternationalText {
+        let packet = format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n{}<?xpacket end=\"w\"?>",
+            self
+        );
+        InternationalText::new(KEYWORD, &packet).unwrap()
+    }
+
+    fn rdf(&self) -> Option<&Element> {
+        match self.root.name.as_str() {
+            "rdf:RDF" => Some(&self.root),
+            _ => self.root.elements().find(|e| e.name == "rdf:RDF"),
+        }
+    }
+
+    fn rdf_mut(&mut self) -> Option<&mut Element> {
+        match self.root.name.as_str() {
+            "rdf:RDF" => Some(&mut self.root),
+            _ => self.root.elements_mut().find(|e| e.name == "rdf:RDF"),
+        }
+    }
+
+    fn descriptions(&self) -> impl Iterator<Item = &Element> {
+        self.rdf()
+            .into_iter()
+            .flat_map(|rdf| rdf.elements())
+            .filter(|e| e.name == "rdf:Description")
+    }
+
+    /// The value of a property such as `dc:title`. Array items are joined with commas.
+    pub fn get(&self, property: &str) -> Option<String> {
+        self.descriptions().find_map(|description| {
+            if let Some(value) = description.attribute(property) {
+                return Some(value.to_string());
+            }
+            let element = description.elements().find(|e| e.name == property)?;
+            match element.elements().find(|e| is_container(&e.name)) {
+                Some(container) => Some(
+                    container
+                        .elements()
+                        .map(|li| li.text())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+                None => Some(element.text()),
+            }
+        })
+    }
+
+    /// Every property as (name, value), in document order
+    pub fn properties(&self) -> Vec<(String, String)> {
+        let mut properties = Vec::new();
+        for description in self.descriptions() {
+            for (name, _) in description.attributes.iter() {
+                if !name.starts_with("xmlns:") && !name.starts_with("rdf:") {
+                    properties.push(name.clone());
+                }
+            }
+            properties.extend(description.elements().map(|e| e.name.clone()));
+        }
+        properties
+            .into_iter()
+            .filter_map(|name| self.get(&name).map(|value| (name, value)))
+            .collect()
+    }
+
+    /// Sets a simple property, replacing all items if it is an array
+    pub fn set(&mut self, property: &str, value: &str) -> Result<()> {
+        let (prefix, _) = property
+            .split_once(':')
+            .ok_or("XMP property names need a namespace prefix, like dc:title")?;
+        let declared = self.is_declared(prefix);
+        let rdf = self.rdf_mut().ok_or("XMP packet has no rdf:RDF element")?;
+        if !rdf.elements().any(|e| e.name == "rdf:Description") {
+            let mut description = Element::new("rdf:Description");
+            description.set_attribute("rdf:about", "");
+            rdf.children.push(Node::Element(description));
+        }
+
+        for description in rdf.elements_mut().filter(|e| e.name == "rdf:Description") {
+            if description.attribute(property).is_some() {
+                description.set_attribute(property, value);
+                return Ok(());
+            }
+            if let Some(element) = description.elements_mut().find(|e| e.name == property) {
+                let container = element.elements_mut().find(|e| is_container(&e.name));
+                if let Some(container) = container {
+                    container.children = vec![Node::Element(list_item(&container.name, value))];
+                } else {
+                    element.children = vec![Node::Text(value.to_string())];
+                }
+                return Ok(());
+            }
+        }
+
+        let description = rdf
+            .elements_mut()
+            .find(|e| e.name == "rdf:Description")
+            .unwrap();
+        if !declared {
+            let uri = NAMESPACES
+                .iter()
+                .find(|(p, _)| *p == prefix)
+                .map(|(_, uri)| *uri)
+                .ok_or_else(|| Error::from(format!("Unknown XMP namespace prefix: {}", prefix)))?;
+            description.set_attribute(&format!("xmlns:{}", prefix), uri);
+        }
+        let container = if ALT_PROPERTIES.contains(&property) {
+            Some("rdf:Alt")
+        } else if SEQ_PROPERTIES.contains(&property) {
+            Some("rdf:Seq")
+        } else if BAG_PROPERTIES.contains(&property) {
+            Some("rdf:Bag")
+        } else {
+            None
+        };
+        match container {
+            Some(container) => {
+                let mut array = Element::new(container);
+                array
+                    .children
+                    .push(Node::Element(list_item(container, value)));
+                let mut element = Element::new(property);
+                element.children.push(Node::Element(array));
+                description.children.push(Node::Element(element));
+            }
+            None => description.set_attribute(property, value),
+        }
+        Ok(())
+    }
+
+    /// Removes a property, returning whether it was there
+    pub fn remove(&mut self, property: &str) -> bool {
+        let mut removed = false;
+        if let Some(rdf) = self.rdf_mut() {
+            for description in rdf.elements_mut().filter(|e| e.name == "rdf:Description") {
+                let before = description.attributes.len() + description.children.len();
+                description.attributes.retain(|(name, _)| name != property);
+                description
+                    .children
+                    .retain(|node| !matches!(node, Node::Element(e) if e.name == property));
+                removed |= description.attributes.len() + description.children.len() != before;
+            }
+        }
+        removed
+    }
+
+    /// Whether `prefix` is declared on the root, rdf:RDF or any rdf:Description
+    fn is_declared(&self, prefix: &str) -> bool {
+        let attribute = format!("xmlns:{}", prefix);
+        std::iter::once(&self.root)
+            .chain(self.rdf())
+            .chain(self.descriptions())
+            .any(|e| e.attribute(&attribute).is_some())
+    }
+}
+
+impl Default for Xmp {
+    fn default() -> Self {
+        Xmp::new()
+    }
+}
+
+fn is_container(name: &str) -> bool {
+    matches!(name, "rdf:Alt" | "rdf:Seq" | "rdf:Bag")
+}
+
+fn list_item(container: &str, value: &str) -> Element {
+    let mut li = Element::new("rdf:li").with_text(value);
+    if container == "rdf:Alt" {
+        li.set_attribute("xml:lang", "x-default");
+    }
+    li
+}
+
+impl fmt::Display for Xmp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.root.write(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET: &str = "<?xpacket begin='\u{feff}' id='W5M0MpCehiHzreSzNTczkc9d'?>
+<x:xmpmeta xmlns:x='adobe:ns:meta/'><rdf:RDF xmlns:rdf='http://www.w3.org/1999/02/22-rdf-syntax-ns#'>
+<rdf:Description rdf:about='' xmlns:dc='http://purl.org/dc/elements/1.1/'
+  xmlns:xmp='http://ns.adobe.com/xap/1.0/' xmp:Rating='3'>
+<!-- written by a DAM -->
+<dc:title><rdf:Alt><rdf:li xml:lang='x-default'>Fish &amp; chips</rdf:li></rdf:Alt></dc:title>
+<dc:subject><rdf:Bag><rdf:li>food</rdf:li><rdf:li>lunch</rdf:li></rdf:Bag></dc:subject>
+<dc:format>image/png</dc:format>
+</rdf:Description></rdf:RDF></x:xmpmeta>
+<?xpacket end='w'?>";
+
+    #[test]
+    fn test_parse_properties() {
+        let xmp = Xmp::parse(PACKET).unwrap();
+        assert_eq!(xmp.get("dc:title").unwrap(), "Fish & chips");
+        assert_eq!(xmp.get("dc:subject").unwrap(), "food, lunch");
+        assert_eq!(xmp.get("xmp:Rating").unwrap(), "3");
+        assert_eq!(xmp.get("dc:format").unwrap(), "image/png");
+        assert_eq!(xmp.get("dc:creator"), None);
+        assert_eq!(xmp.properties().len(), 4);
+    }
+
+    #[test]
+    fn test_pretty_print_round_trip() {
+        let xmp = Xmp::parse(PACKET).unwrap();
+        let printed = xmp.to_text().text;
+        assert!(printed.contains("\n  <rdf:Description rdf:about=\"\""));
+        assert!(printed.contains(">Fish &amp; chips</rdf:li>"));
+        assert_eq!(Xmp::parse(&printed).unwrap(), xmp);
+    }
+
+    #[test]
+    fn test_set_and_remove() {
+        let mut xmp = Xmp::parse(PACKET).unwrap();
+        xmp.set("dc:title", "Haddock").unwrap();
+        xmp.set("xmp:Rating", "5").unwrap();
+        xmp.set("photoshop:City", "Leeds").unwrap();
+        xmp.set("dc:creator", "A. Cook").unwrap();
+        assert!(xmp.set("nope:Thing", "x").is_err());
+        assert!(xmp.set("NoPrefix", "x").is_err());
+
+        let xmp = Xmp::parse(&xmp.to_text().text).unwrap();
+        assert_eq!(xmp.get("dc:title").unwrap(), "Haddock");
+        assert_eq!(xmp.get("xmp:Rating").unwrap(), "5");
+        assert_eq!(xmp.get("photoshop:City").unwrap(), "Leeds");
+        assert_eq!(xmp.get("dc:creator").unwrap(), "A. Cook");
+
+        let mut xmp = xmp;
+        assert!(xmp.remove("dc:subject"));
+        assert!(xmp.remove("xmp:Rating"));
+        assert!(!xmp.remove("xmp:Rating"));
+        assert_eq!(xmp.get("dc:subject"), None);
+    }
+
+    #[test]
+    fn test_new_packet_in_png() {
+        let mut png = Png::from_chunks(Vec::new());
+        assert!(Xmp::from_png(&png).unwrap().is_none());
+        let mut xmp = Xmp::new();
+        xmp.set("dc:title", "New").unwrap();
+        png.set_international_text(xmp.to_text());
+        assert_eq!(Xmp::from_png(&png).unwrap().unwrap(), xmp);
+    }
+
+    #[test]
+    fn test_malformed_xml() {
+        assert!(Xmp::parse("<x:xmpmeta><rdf:RDF></x:xmpmeta>").is_err());
+        assert!(Xmp::parse("<x:xmpmeta a=b/>").is_err());
+        assert!(Xmp::parse("<x:xmpmeta>").is_err());
+        assert!(Xmp::parse("<html/>").is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| {
+            format!(
+                "<x:xmpmeta>{}{}</x:xmpmeta>",
+                "<a>".repeat(depth - 1),
+                "</a>".repeat(depth - 1)
+            )
+        };
+        assert!(Xmp::parse(&nested(MAX_DEPTH)).is_ok());
+        for depth in [MAX_DEPTH + 1, 100_000] {
+            let error = Xmp::parse(&nested(depth)).unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<XmlError>(),
+                Some(XmlError::Malformed { .. })
+            ));
+        }
+    }
+}